@@ -1,4 +1,8 @@
 pub mod persistence;
+mod restrictions;
 mod streetgraph;
+#[cfg(test)]
+mod test_pbf;
 
-pub use streetgraph::Streets;
+pub use restrictions::{Turn, TurnRestrictions};
+pub use streetgraph::{Streets, TravelMode};
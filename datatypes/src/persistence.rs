@@ -1,9 +1,16 @@
-use std::{fs::File, io::Write, path::Path};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
 
 use anyhow::Result;
 use openhousepopulator::Buildings;
-use serde::{Deserialize, Serialize};
-use crate::Streets;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use crate::{Streets, TurnRestrictions};
 
 #[derive(Serialize, Deserialize)]
 pub struct PreProcessingData {
@@ -11,13 +18,204 @@ pub struct PreProcessingData {
     pub streets: Streets,
 }
 
+/// Serialization of the `.map` file, postcard is compact and json readable for debugging.
+/// Loading detects the format, so either can be read regardless of this setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Postcard,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "postcard" => Ok(Format::Postcard),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "unknown format {}, expected postcard or json",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Postcard => write!(f, "postcard"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+fn save<T: Serialize>(data: &T, path: &Path) -> Result<()> {
+    save_as(data, path, Format::Postcard)
+}
+
+fn save_as<T: Serialize>(data: &T, path: &Path, format: Format) -> Result<()> {
+    let mut file = File::create(path)?;
+    match format {
+        Format::Postcard => file.write_all(postcard::to_allocvec(data)?.as_slice())?,
+        Format::Json => serde_json::to_writer(&mut file, data)?,
+    }
+    Ok(())
+}
+
+/// Reads files in either format. Postcard data of a struct never starts with `{`
+/// unless its first field is a sequence of exactly 123 elements, so that case is
+/// retried as postcard if it isn't valid json.
+fn load<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let mut file = File::open(path)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data)?;
+    if data.first() == Some(&b'{') {
+        if let Ok(value) = serde_json::from_slice(&data) {
+            return Ok(value);
+        }
+    }
+    Ok(postcard::from_bytes(&data)?)
+}
+
 pub fn save_preprocessed_data(
     buildings: Buildings,
     streets: Streets,
     path: &Path,
+    format: Format,
 ) -> Result<()> {
     let data = PreProcessingData { buildings, streets };
-    let mut file = File::create(path)?;
-    file.write_all(postcard::to_allocvec(&data)?.as_slice())?;
-    Ok(())
+    save_as(&data, path, format)
+}
+
+pub fn load_preprocessed_data(path: &Path) -> Result<PreProcessingData> {
+    load(path)
+}
+
+/// Streets and buildings are additionally cached on their own (`.streets` and `.buildings`
+/// next to the `.map` file), so one of them can be rebuilt without the other
+pub fn save_streets(streets: &Streets, path: &Path) -> Result<()> {
+    save(streets, path)
+}
+
+pub fn load_streets(path: &Path) -> Result<Streets> {
+    load(path)
+}
+
+pub fn save_buildings(buildings: &Buildings, path: &Path) -> Result<()> {
+    save(buildings, path)
+}
+
+pub fn load_buildings(path: &Path) -> Result<Buildings> {
+    load(path)
+}
+
+/// Turn restrictions are only needed for bike routing and are kept out of the `.map` file
+pub fn save_restrictions(restrictions: &TurnRestrictions, path: &Path) -> Result<()> {
+    save(restrictions, path)
+}
+
+pub fn load_restrictions(path: &Path) -> Result<TurnRestrictions> {
+    load(path)
+}
+
+/// Size and modification time of the files an artifact was generated from, stored as
+/// `.source` next to the `.map` file so caches of replaced files aren't reused
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SourceFingerprint(Vec<(u64, u64)>);
+
+impl SourceFingerprint {
+    pub fn of<P: AsRef<Path>>(sources: &[P]) -> Result<SourceFingerprint> {
+        let files = sources
+            .iter()
+            .map(|source| {
+                let metadata = fs::metadata(source)?;
+                let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+                Ok((metadata.len(), modified.as_secs()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SourceFingerprint(files))
+    }
+}
+
+pub fn save_source(fingerprint: &SourceFingerprint, path: &Path) -> Result<()> {
+    save(fingerprint, path)
+}
+
+pub fn load_source(path: &Path) -> Result<SourceFingerprint> {
+    load(path)
+}
+
+/// Whether the artifacts at `path` were generated from the current state of `sources`,
+/// artifacts without a stored fingerprint are treated as stale
+pub fn is_up_to_date<P: AsRef<Path>>(path: &Path, sources: &[P]) -> bool {
+    match (
+        load_source(&path.with_extension("source")),
+        SourceFingerprint::of(sources),
+    ) {
+        (Ok(stored), Ok(current)) => stored == current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use geo::Point;
+    use osmpbfreader::NodeId;
+
+    use super::*;
+    use crate::Turn;
+
+    #[test]
+    fn streets_survive_a_round_trip_in_every_format() {
+        let mut streets = Streets::new();
+        for (id, lon) in [(1, 16.370), (2, 16.371), (3, 16.372)] {
+            streets.nodes.insert(NodeId(id), Point::new(lon, 48.2));
+        }
+        streets.streetgraph.add_edge(NodeId(1), NodeId(2), 74.5);
+        streets.streetgraph.add_edge(NodeId(2), NodeId(3), 74.25);
+        let turn = Turn {
+            from: (NodeId(1), NodeId(2)),
+            to: (NodeId(2), NodeId(3)),
+        };
+        streets.restrictions.prohibit(turn);
+
+        for format in [Format::Postcard, Format::Json] {
+            let path = std::env::temp_dir().join(format!("streets-{}.{}", process::id(), format));
+            save_as(&streets, &path, format).unwrap();
+            let loaded: Result<Streets> = load(&path);
+            fs::remove_file(&path).unwrap();
+            let loaded = loaded.unwrap();
+
+            assert_eq!(loaded.nodes, streets.nodes, "{}", format);
+            let mut edges: Vec<_> = loaded
+                .streetgraph
+                .all_edges()
+                .map(|(a, b, length)| (a.min(b), a.max(b), length))
+                .collect();
+            edges.sort_by_key(|(a, b, _)| (*a, *b));
+            assert_eq!(
+                edges,
+                [
+                    (NodeId(1), NodeId(2), &74.5),
+                    (NodeId(2), NodeId(3), &74.25)
+                ],
+                "{}",
+                format
+            );
+            // the binary format only holds the street graph, restrictions are in
+            // their own file there
+            assert_eq!(
+                loaded.restrictions.is_prohibited(&turn),
+                format == Format::Json,
+                "{}",
+                format
+            );
+        }
+        assert_eq!("JSON".parse::<Format>(), Ok(Format::Json));
+        assert!("yaml".parse::<Format>().is_err());
+    }
 }
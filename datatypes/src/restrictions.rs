@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+
+use osmpbfreader::{NodeId, OsmId, OsmObj, OsmPbfReader, Relation, Way};
+use serde::{Deserialize, Serialize};
+
+use crate::Streets;
+
+/// A turn from one street segment onto another, both given as (from node, to node)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Turn {
+    pub from: (NodeId, NodeId),
+    pub to: (NodeId, NodeId),
+}
+
+/// Turns prohibited by OSM `type=restriction` relations. Walking ignores them,
+/// vehicle routing has to use `TurnRestrictions::distances` to honor them.
+///
+/// The preprocessing reads them against the bike network and stores them in the
+/// `.restrictions` artifact, the backend attaches them to the bike network of a layer.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TurnRestrictions {
+    prohibited: HashSet<Turn>,
+}
+
+impl TurnRestrictions {
+    pub fn new() -> TurnRestrictions {
+        TurnRestrictions::default()
+    }
+
+    pub fn prohibit(&mut self, turn: Turn) {
+        self.prohibited.insert(turn);
+    }
+
+    pub fn is_prohibited(&self, turn: &Turn) -> bool {
+        self.prohibited.contains(turn)
+    }
+
+    pub fn len(&self) -> usize {
+        self.prohibited.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prohibited.is_empty()
+    }
+
+    /// Adds the restrictions of another area, e.g. when merging street networks
+    pub fn extend(&mut self, other: &TurnRestrictions) {
+        self.prohibited.extend(other.prohibited.iter().copied());
+    }
+
+    // Reads the turn restrictions of an area, only restrictions via a single node are supported
+    pub fn from_pbf<T: std::io::Read + std::io::Seek>(
+        pbf: &mut OsmPbfReader<T>,
+        streets: &Streets,
+    ) -> TurnRestrictions {
+        let objs = pbf
+            .get_objs_and_deps(|obj| {
+                obj.is_relation() && obj.tags().contains("type", "restriction")
+            })
+            .unwrap();
+
+        let mut restrictions = TurnRestrictions::new();
+        for relation in objs.values().filter_map(|obj| obj.relation()) {
+            let Some(kind) = relation.tags.get("restriction") else {
+                continue;
+            };
+            // the restrictions are used for bike routing
+            if relation
+                .tags
+                .get("except")
+                .is_some_and(|except| except.split(';').any(|mode| mode.trim() == "bicycle"))
+            {
+                continue;
+            }
+            let (Some(from), Some(OsmId::Node(via)), Some(to)) = (
+                member_way(relation, "from", &objs),
+                member(relation, "via"),
+                member_way(relation, "to", &objs),
+            ) else {
+                continue;
+            };
+            let (Some(from), Some(to)) = (neighbour_of(from, via), neighbour_of(to, via)) else {
+                continue;
+            };
+
+            if kind.starts_with("no_") {
+                restrictions.prohibit(Turn {
+                    from: (from, via),
+                    to: (via, to),
+                });
+            } else if kind.starts_with("only_") {
+                // every other turn at the via node is prohibited
+                for other in streets.streetgraph.neighbors(via).filter(|n| *n != to) {
+                    restrictions.prohibit(Turn {
+                        from: (from, via),
+                        to: (via, other),
+                    });
+                }
+            }
+        }
+        restrictions
+    }
+
+    /// Distances from `start` to every node reachable within `max_distance` without
+    /// taking a prohibited turn
+    pub fn distances(
+        &self,
+        streets: &Streets,
+        start: NodeId,
+        max_distance: f64,
+    ) -> HashMap<NodeId, f64> {
+        // the search runs over (node, previous node) since restrictions depend on the edge a node is reached by
+        let mut settled: HashSet<(NodeId, Option<NodeId>)> = HashSet::new();
+        let mut distances: HashMap<NodeId, f64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(SearchState {
+            cost: 0.0,
+            node: start,
+            previous: None,
+        });
+
+        while let Some(SearchState {
+            cost,
+            node,
+            previous,
+        }) = queue.pop()
+        {
+            if !settled.insert((node, previous)) {
+                continue;
+            }
+            distances
+                .entry(node)
+                .and_modify(|distance| *distance = distance.min(cost))
+                .or_insert(cost);
+
+            for (_, next, length) in streets.streetgraph.edges(node) {
+                let next_cost = cost + length;
+                if next_cost > max_distance || Some(next) == previous {
+                    continue;
+                }
+                if let Some(previous) = previous {
+                    if self.is_prohibited(&Turn {
+                        from: (previous, node),
+                        to: (node, next),
+                    }) {
+                        continue;
+                    }
+                }
+                queue.push(SearchState {
+                    cost: next_cost,
+                    node: next,
+                    previous: Some(node),
+                });
+            }
+        }
+        distances
+    }
+}
+
+struct SearchState {
+    cost: f64,
+    node: NodeId,
+    previous: Option<NodeId>,
+}
+
+impl PartialEq for SearchState {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SearchState {}
+
+impl Ord for SearchState {
+    // reversed, so the BinaryHeap pops the cheapest state first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for SearchState {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn member(relation: &Relation, role: &str) -> Option<OsmId> {
+    relation
+        .refs
+        .iter()
+        .find(|reference| reference.role.as_str() == role)
+        .map(|reference| reference.member)
+}
+
+fn member_way<'a>(
+    relation: &Relation,
+    role: &str,
+    objs: &'a BTreeMap<OsmId, OsmObj>,
+) -> Option<&'a Way> {
+    objs.get(&member(relation, role)?)?.way()
+}
+
+/// The node next to `via` on a way that starts or ends at `via`
+fn neighbour_of(way: &Way, via: NodeId) -> Option<NodeId> {
+    match way.nodes.as_slice() {
+        [first, second, ..] if *first == via => Some(*second),
+        [.., second_last, last] if *last == via => Some(*second_last),
+        _ => None,
+    }
+}
@@ -1,56 +1,107 @@
 use std::collections::HashMap;
 
 use geo::{HaversineDistance, Point};
-use osmpbfreader::{NodeId, OsmObj, OsmPbfReader};
+use osmpbfreader::{NodeId, OsmObj, OsmPbfReader, Tags};
 use petgraph::prelude::UnGraphMap;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::TurnRestrictions;
+
+#[derive(Debug, Clone)]
 pub struct Streets {
     pub nodes: HashMap<NodeId, Point>,
-    #[serde(
-        serialize_with = "Streets::serialize_streetgraph",
-        deserialize_with = "Streets::deserialize_streetgraph"
-    )]
     pub streetgraph: UnGraphMap<NodeId, f64>,
+    /// Turns prohibited on this network, see `Serialize for Streets` for how they're stored
+    pub restrictions: TurnRestrictions,
 }
 
-impl Streets {
-    pub fn new() -> Streets {
-        Streets {
-            streetgraph: UnGraphMap::new(),
-            nodes: HashMap::new(),
-        }
-    }
+/// The graph is stored as its list of edges
+type Edges = Vec<(NodeId, NodeId, f64)>;
 
-    fn serialize_streetgraph<S>(
-        graph: &UnGraphMap<NodeId, f64>,
-        serializer: S,
-    ) -> Result<S::Ok, S::Error>
+#[derive(Serialize)]
+struct StreetsRef<'a> {
+    nodes: &'a HashMap<NodeId, Point>,
+    streetgraph: Edges,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restrictions: Option<&'a TurnRestrictions>,
+}
+
+#[derive(Deserialize)]
+struct StoredStreets {
+    nodes: HashMap<NodeId, Point>,
+    streetgraph: Edges,
+}
+
+#[derive(Deserialize)]
+struct ReadableStreets {
+    nodes: HashMap<NodeId, Point>,
+    streetgraph: Edges,
+    #[serde(default)]
+    restrictions: TurnRestrictions,
+}
+
+/// Binary formats only contain the nodes and the graph, so the postcard files of the
+/// preprocessing keep their format and the restrictions go to their own artifact.
+/// Human readable formats, like the json the backend caches its layers in, also
+/// contain the restrictions.
+impl Serialize for Streets {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let edges: Vec<(NodeId, NodeId, f64)> = graph
-            .all_edges()
-            .into_iter()
-            .map(|edge| (edge.0, edge.1, *edge.2))
-            .collect();
-
-        Serialize::serialize(&edges, serializer)
+        let restrictions = serializer.is_human_readable().then_some(&self.restrictions);
+        StreetsRef {
+            nodes: &self.nodes,
+            streetgraph: self
+                .streetgraph
+                .all_edges()
+                .map(|edge| (edge.0, edge.1, *edge.2))
+                .collect(),
+            restrictions,
+        }
+        .serialize(serializer)
     }
+}
 
-    fn deserialize_streetgraph<'de, D>(deserializer: D) -> Result<UnGraphMap<NodeId, f64>, D::Error>
+impl<'de> Deserialize<'de> for Streets {
+    fn deserialize<D>(deserializer: D) -> Result<Streets, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let edges: Vec<(NodeId, NodeId, f64)> = Deserialize::deserialize(deserializer)?;
+        let (nodes, edges, restrictions) = if deserializer.is_human_readable() {
+            let streets = ReadableStreets::deserialize(deserializer)?;
+            (streets.nodes, streets.streetgraph, streets.restrictions)
+        } else {
+            let streets = StoredStreets::deserialize(deserializer)?;
+            (streets.nodes, streets.streetgraph, TurnRestrictions::new())
+        };
+        Ok(Streets {
+            nodes,
+            streetgraph: UnGraphMap::from_edges(edges),
+            restrictions,
+        })
+    }
+}
 
-        let streetgraph = UnGraphMap::from_edges(edges);
-        Ok(streetgraph)
+impl Streets {
+    pub fn new() -> Streets {
+        Streets {
+            streetgraph: UnGraphMap::new(),
+            nodes: HashMap::new(),
+            restrictions: TurnRestrictions::new(),
+        }
     }
 
-    // Generates a street graph for a given area
+    // Generates a street graph for walking in a given area
     pub fn from_pbf<T: std::io::Read + std::io::Seek>(pbf: &mut OsmPbfReader<T>) -> Streets {
+        Streets::from_pbf_for_mode(pbf, TravelMode::Walk)
+    }
+
+    /// Generates a street graph of the ways traversable with `mode`
+    pub fn from_pbf_for_mode<T: std::io::Read + std::io::Seek>(
+        pbf: &mut OsmPbfReader<T>,
+        mode: TravelMode,
+    ) -> Streets {
         let osm_nodes = pbf.get_objs_and_deps(|obj| obj.is_node()).unwrap();
 
         let nodes: HashMap<NodeId, Point> = osm_nodes
@@ -71,7 +122,7 @@ impl Streets {
             .collect();
 
         let osm_ways = pbf
-            .get_objs_and_deps(|obj| obj.is_way() && obj.tags().contains_key("highway"))
+            .get_objs_and_deps(|obj| obj.is_way() && mode.allows(obj.tags()))
             .unwrap();
 
         let edges: Vec<(NodeId, NodeId, f64)> = osm_ways
@@ -109,6 +160,156 @@ impl Streets {
 
         let streetgraph = UnGraphMap::from_edges(edges);
 
-        Streets { streetgraph, nodes }
+        Streets {
+            streetgraph,
+            nodes,
+            restrictions: TurnRestrictions::new(),
+        }
+    }
+}
+
+/// Which ways of the OSM data are part of the street graph. The graph is undirected,
+/// so `oneway` isn't modelled: it doesn't apply to walking and cyclists can still
+/// push their bike against the direction of travel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TravelMode {
+    Walk,
+    Bike,
+}
+
+impl TravelMode {
+    /// Whether a way with these tags can be used with this mode
+    pub fn allows(&self, tags: &Tags) -> bool {
+        let Some(highway) = tags.get("highway") else {
+            return false;
+        };
+        let mode_access = match self {
+            TravelMode::Walk => tags.get("foot"),
+            TravelMode::Bike => tags.get("bicycle"),
+        };
+        // an explicit permission for the mode overrides the general access and the highway type
+        match mode_access.map(|access| access.as_str()) {
+            Some("yes" | "designated" | "permissive") => return true,
+            Some("no" | "private" | "use_sidepath") => return false,
+            _ => {}
+        }
+        if matches!(
+            tags.get("access").map(|access| access.as_str()),
+            Some("no" | "private")
+        ) {
+            return false;
+        }
+        match self {
+            TravelMode::Walk => !matches!(
+                highway.as_str(),
+                "motorway" | "motorway_link" | "construction" | "proposed"
+            ),
+            TravelMode::Bike => !matches!(
+                highway.as_str(),
+                "motorway"
+                    | "motorway_link"
+                    | "construction"
+                    | "proposed"
+                    | "footway"
+                    | "steps"
+                    | "pedestrian"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pbf::{pbf, TestWay};
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter()
+            .map(|(key, value)| ((*key).into(), (*value).into()))
+            .collect()
+    }
+
+    #[test]
+    fn motorways_are_excluded() {
+        let motorway = tags(&[("highway", "motorway")]);
+        assert!(!TravelMode::Walk.allows(&motorway));
+        assert!(!TravelMode::Bike.allows(&motorway));
+        assert!(TravelMode::Walk.allows(&tags(&[("highway", "residential")])));
+    }
+
+    #[test]
+    fn private_access_is_excluded() {
+        let private = tags(&[("highway", "service"), ("access", "private")]);
+        assert!(!TravelMode::Walk.allows(&private));
+        assert!(!TravelMode::Bike.allows(&private));
+    }
+
+    #[test]
+    fn mode_access_overrides_general_access() {
+        let foot_only = tags(&[("highway", "track"), ("access", "no"), ("foot", "yes")]);
+        assert!(TravelMode::Walk.allows(&foot_only));
+        assert!(!TravelMode::Bike.allows(&foot_only));
+    }
+
+    #[test]
+    fn ways_without_foot_access_are_not_part_of_the_walking_graph() {
+        let nodes = [
+            (1, 16.370, 48.200),
+            (2, 16.371, 48.200),
+            (3, 16.372, 48.200),
+            (4, 16.372, 48.201),
+        ];
+        let ways = [
+            TestWay {
+                id: 10,
+                nodes: &[1, 2, 3],
+                tags: &[("highway", "footway")],
+            },
+            TestWay {
+                id: 11,
+                nodes: &[3, 4],
+                tags: &[("highway", "footway"), ("foot", "no")],
+            },
+        ];
+
+        let streets = Streets::from_pbf(&mut pbf(&nodes, &ways));
+
+        assert_eq!(streets.streetgraph.edge_count(), 2);
+        assert!(streets.streetgraph.contains_edge(NodeId(1), NodeId(2)));
+        assert!(streets.streetgraph.contains_edge(NodeId(2), NodeId(3)));
+        assert!(!streets.streetgraph.contains_edge(NodeId(3), NodeId(4)));
+        assert!(!streets.nodes.contains_key(&NodeId(4)));
+        assert_eq!(streets.nodes.len(), 3);
+        let first = streets.nodes[&NodeId(1)];
+        assert!((first.x() - 16.370).abs() < 1e-9 && (first.y() - 48.200).abs() < 1e-9);
+    }
+
+    #[test]
+    fn restrictions_are_only_kept_by_human_readable_formats() {
+        let mut streets = Streets::new();
+        streets.nodes.insert(NodeId(1), Point::new(16.370, 48.200));
+        streets.nodes.insert(NodeId(2), Point::new(16.371, 48.200));
+        streets.streetgraph.add_edge(NodeId(1), NodeId(2), 74.0);
+        let plain = postcard::to_allocvec(&streets).unwrap();
+        let turn = crate::Turn {
+            from: (NodeId(1), NodeId(2)),
+            to: (NodeId(2), NodeId(1)),
+        };
+        streets.restrictions.prohibit(turn);
+
+        // the postcard files don't change, so older files can still be read
+        assert_eq!(postcard::to_allocvec(&streets).unwrap(), plain);
+        let loaded: Streets = postcard::from_bytes(&plain).unwrap();
+        assert!(loaded.restrictions.is_empty());
+        assert_eq!(loaded.streetgraph.edge_count(), 1);
+
+        let loaded: Streets =
+            serde_json::from_slice(&serde_json::to_vec(&streets).unwrap()).unwrap();
+        assert!(loaded.restrictions.is_prohibited(&turn));
+        assert_eq!(loaded.nodes, streets.nodes);
+        let without_restrictions =
+            r#"{"nodes": {"1": {"x": 16.37, "y": 48.2}}, "streetgraph": []}"#;
+        let loaded: Streets = serde_json::from_str(without_restrictions).unwrap();
+        assert!(loaded.restrictions.is_empty());
     }
 }
@@ -0,0 +1,85 @@
+//! Writes tiny uncompressed pbf files in memory, so the graph building can be tested
+//! without fixture files.
+
+use std::io::Cursor;
+
+use osmpbfreader::{fileformat, osmformat, OsmPbfReader};
+use protobuf::Message;
+
+pub struct TestWay<'a> {
+    pub id: i64,
+    pub nodes: &'a [i64],
+    pub tags: &'a [(&'a str, &'a str)],
+}
+
+/// `nodes` as `(id, lon, lat)`, all of them are written to one primitive block
+pub fn pbf(nodes: &[(i64, f64, f64)], ways: &[TestWay]) -> OsmPbfReader<Cursor<Vec<u8>>> {
+    // index 0 of the string table is reserved as delimiter
+    let mut strings: Vec<String> = vec![String::new()];
+    let mut string_index = |value: &str| -> u32 {
+        match strings.iter().position(|string| string == value) {
+            Some(index) => index as u32,
+            None => {
+                strings.push(value.to_owned());
+                strings.len() as u32 - 1
+            }
+        }
+    };
+
+    let mut group = osmformat::PrimitiveGroup::new();
+    for (id, lon, lat) in nodes {
+        let mut node = osmformat::Node::new();
+        node.set_id(*id);
+        // at the default granularity of 100 nanodegrees
+        node.set_lon((lon * 1e7).round() as i64);
+        node.set_lat((lat * 1e7).round() as i64);
+        group.mut_nodes().push(node);
+    }
+    for test_way in ways {
+        let mut way = osmformat::Way::new();
+        way.set_id(test_way.id);
+        for (key, value) in test_way.tags {
+            way.mut_keys().push(string_index(key));
+            way.mut_vals().push(string_index(value));
+        }
+        // node references are delta encoded
+        let mut previous = 0;
+        for node in test_way.nodes {
+            way.mut_refs().push(node - previous);
+            previous = *node;
+        }
+        group.mut_ways().push(way);
+    }
+
+    let mut block = osmformat::PrimitiveBlock::new();
+    for string in strings {
+        block.mut_stringtable().mut_s().push(string.into_bytes());
+    }
+    block.mut_primitivegroup().push(group);
+
+    let mut header = osmformat::HeaderBlock::new();
+    header
+        .mut_required_features()
+        .push("OsmSchema-V0.6".to_owned());
+
+    let mut data = Vec::new();
+    write_blob(&mut data, "OSMHeader", header.write_to_bytes().unwrap());
+    write_blob(&mut data, "OSMData", block.write_to_bytes().unwrap());
+    OsmPbfReader::new(Cursor::new(data))
+}
+
+fn write_blob(data: &mut Vec<u8>, blob_type: &str, content: Vec<u8>) {
+    let mut blob = fileformat::Blob::new();
+    blob.set_raw_size(content.len() as i32);
+    blob.set_raw(content);
+    let blob = blob.write_to_bytes().unwrap();
+
+    let mut header = fileformat::BlobHeader::new();
+    header.set_field_type(blob_type.to_owned());
+    header.set_datasize(blob.len() as i32);
+    let header = header.write_to_bytes().unwrap();
+
+    data.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&blob);
+}
@@ -0,0 +1,102 @@
+//! Optional API key for the routes changing the loaded data.
+//!
+//! With `auth.api_key` set, `POST`, `PUT`, `PATCH` and `DELETE` requests to a
+//! wrapped scope need the key in an `X-API-Key` or `Authorization: Bearer` header.
+//! Reading stays open, as do the calculations outside of the wrapped scopes.
+
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{web, Error, ResponseError};
+use config::Config;
+
+use crate::error::OLPError;
+
+/// Header the key can be sent in instead of `Authorization`
+const API_KEY_HEADER: &str = "X-API-Key";
+
+pub struct RequireApiKey;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireApiKey
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireApiKeyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireApiKeyMiddleware { service }))
+    }
+}
+
+pub struct RequireApiKeyMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireApiKeyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(error) = authorize(&req) {
+            log::warn!(
+                "rejected {} request to {}: {}",
+                req.method(),
+                req.path(),
+                error
+            );
+            let response = req
+                .into_response(error.error_response())
+                .map_into_right_body();
+            return Box::pin(ready(Ok(response)));
+        }
+        let response = self.service.call(req);
+        Box::pin(async move { response.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+fn authorize(req: &ServiceRequest) -> Result<(), OLPError> {
+    if !matches!(
+        *req.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) {
+        return Ok(());
+    }
+    let Some(api_key) = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.get_string("auth.api_key").ok())
+        .filter(|api_key| !api_key.is_empty())
+    else {
+        return Ok(());
+    };
+    let sent_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .or_else(|| req.headers().get(header::AUTHORIZATION))
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_start_matches("Bearer ").trim());
+    match sent_key {
+        Some(sent_key) if sent_key == api_key => Ok(()),
+        Some(_) => Err(OLPError::Unauthorized("invalid api key".to_owned())),
+        None => Err(OLPError::Unauthorized(format!(
+            "this server requires an api key in the {} or Authorization header",
+            API_KEY_HEADER
+        ))),
+    }
+}
@@ -3,27 +3,67 @@ use actix_web::http::header::ContentType;
 use actix_web::web;
 use actix_web::HttpResponse;
 use actix_web::Responder;
+use config::Config;
+use geo::Centroid;
+use geo::Contains;
+use geo::Geometry;
+use geo::Intersects;
+use geo::LineString;
+use geo::MultiPoint;
+use geo::MultiPolygon;
 use geo::Point;
+use geo::Polygon;
+use geo::Rect;
 use geojson::de::deserialize_geometry;
 use geojson::ser::serialize_geometry;
 use geojson::ser::to_feature_collection_string;
+use geojson::Feature;
+use mvt::GeomEncoder;
+use mvt::GeomType;
+use mvt::Tile;
 use serde::Deserialize;
 use serde::Serialize;
 use rayon::prelude::*;
 use datatypes::Streets;
 
+use crate::envelope::ListResponse;
 use crate::error::OLPError;
+use crate::geometry::crosses_barrier;
+use crate::geometry::multi_polygon_from_geojson;
+use crate::geometry::service_area;
+use crate::geometry::snap_to_street;
+use crate::geometry::web_mercator_tile_position;
+use crate::geometry::BarrierAwareDistanceCalculator;
 use crate::geometry::DistanceCalculator;
 use crate::geometry::DistanceFromPoint;
 use crate::geometry::HaversineDistanceCalculator;
+use crate::geometry::NetworkDistanceCalculator;
 use crate::geometry::OsmDistanceCalculator;
+use crate::layers::CentroidIndex;
+use crate::layers::Layer;
+use crate::layers::LayerType;
 use crate::layers::Layers;
 use crate::layers::PopulatedCentroid;
+use crate::persistence;
+use crate::population::AccessDistances;
+use crate::population::InhabitantsMap;
+use crate::validation;
 use crate::Station;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Once;
 use std::sync::RwLock;
 
+/// Extent of generated vector tiles in pixels
+const MVT_EXTENT: u32 = 4096;
+/// `routing.snap_tolerance_m` if neither it nor the legacy `routing.max_snap_distance`
+/// is configured. Not a config default, [`snap_tolerance`] couldn't tell a configured
+/// `routing.snap_tolerance_m` from it otherwise.
+const DEFAULT_SNAP_TOLERANCE_M: f64 = 100.0;
+
 #[derive(Serialize)]
 pub struct CoverageMap<'a, 'b>(pub HashMap<&'a str, StationCoverageInfo<'b>>);
 
@@ -31,28 +71,61 @@ pub struct CoverageMap<'a, 'b>(pub HashMap<&'a str, StationCoverageInfo<'b>>);
 pub struct StationCoverageInfo<'a> {
     pub houses: Vec<PopulatedCentroidInfo<'a>>,
     pub inhabitants: u32,
+    /// inhabitants each of `houses` adds to `inhabitants`, in the same order
+    #[serde(skip)]
+    pub contributions: Vec<u32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy)]
 pub enum Routing {
     #[serde(rename = "naive")]
     Naive,
     #[serde(rename = "osm")]
     Osm,
+    /// walking distance over the street network, bounded by the coverage radius
+    #[serde(rename = "network")]
+    Network,
+    /// like `Network` over the ways usable by bike without the turns prohibited by
+    /// turn restrictions, time budgets and times are converted with
+    /// `coverage.cycling_speed_kmh`
+    #[serde(rename = "bike")]
+    Bike,
+}
+
+impl Routing {
+    /// The network of `layer` this routing uses
+    pub fn streets<'a>(&self, layer: &'a Layer) -> &'a Streets {
+        match self {
+            Routing::Bike => layer.get_bike_streets(),
+            _ => layer.get_streets(),
+        }
+    }
+
+    /// Speed in km/h distances are converted to travel times with
+    pub fn speed_kmh(&self, config: &Config) -> Result<f64, OLPError> {
+        let key = match self {
+            Routing::Bike => "coverage.cycling_speed_kmh",
+            _ => "coverage.walking_speed_kmh",
+        };
+        config.get_float(key).map_err(OLPError::from_error)
+    }
 }
 
 impl<'a> StationCoverageInfo<'a> {
-    pub fn from_houses_with_method(value: Vec<PopulatedCentroidInfo<'a>>, method: &Method) -> Self {
+    /// `coverage` is the radius the houses were searched in, decay functions reach
+    /// their lowest weight there
+    pub fn from_houses_with_method(
+        value: Vec<PopulatedCentroidInfo<'a>>,
+        method: &Method,
+        coverage: f64,
+    ) -> Self {
+        let contributions: Vec<u32> = value
+            .iter()
+            .map(|hi| hi.inhabitants(method, coverage))
+            .collect();
         StationCoverageInfo {
-            inhabitants: value
-                .iter()
-                .map(|hi| match method {
-                    Method::Absolute => hi.centroid.pop,
-                    Method::Relative => {
-                        (hi.centroid.pop as f64 * (1f64 / hi.distance.sqrt())) as u32
-                    }
-                })
-                .sum(),
+            inhabitants: contributions.iter().sum(),
+            contributions,
             houses: value,
         }
     }
@@ -64,12 +137,127 @@ pub struct PopulatedCentroidInfo<'a> {
     pub distance: f64,
 }
 
-#[derive(Deserialize)]
+impl PopulatedCentroidInfo<'_> {
+    /// Inhabitants of the house counted for the station with `method`
+    pub fn inhabitants(&self, method: &Method, coverage: f64) -> u32 {
+        let relative = self.centroid.pop as f64 * (1f64 / self.distance.sqrt());
+        match method {
+            Method::Absolute => self.centroid.pop,
+            Method::Relative => relative as u32,
+            Method::Decay(decay) => (relative * decay.weight(self.distance, coverage)) as u32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
 pub enum Method {
     #[serde(rename = "relative")]
     Relative,
     #[serde(rename = "absolute")]
     Absolute,
+    /// `Relative` scaled down further by the distance to the station, e.g.
+    /// `{"decay": "linear"}`. `{"decay": "step"}` counts the same as `Relative`.
+    #[serde(rename = "decay")]
+    Decay(DecayFunction),
+}
+
+/// Method, routing and coverage radius of requests that don't choose one, read from
+/// `coverage.default_method`, `coverage.default_routing` and `coverage.default_radius`
+#[derive(Clone, Copy)]
+pub struct CoverageDefaults {
+    pub method: Method,
+    pub routing: Routing,
+    pub radius: f64,
+}
+
+impl CoverageDefaults {
+    pub fn from_config(config: &Config) -> Result<Self, OLPError> {
+        let invalid = |key: &str, e: config::ConfigError| {
+            OLPError::GenericError(format!("invalid {}: {}", key, e))
+        };
+        let radius = config
+            .get_float("coverage.default_radius")
+            .map_err(|e| invalid("coverage.default_radius", e))?;
+        if !radius.is_finite() || radius <= 0.0 {
+            return Err(OLPError::GenericError(
+                "invalid coverage.default_radius: has to be a positive number".to_owned(),
+            ));
+        }
+        Ok(CoverageDefaults {
+            method: config
+                .get("coverage.default_method")
+                .map_err(|e| invalid("coverage.default_method", e))?,
+            routing: config
+                .get("coverage.default_routing")
+                .map_err(|e| invalid("coverage.default_routing", e))?,
+            radius,
+        })
+    }
+}
+
+/// One method for all layer types or one per layer type, e.g.
+/// `{"workplace": "absolute", "residential": {"decay": "linear"}}`
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MethodSelection {
+    Global(Method),
+    PerLayerType(HashMap<LayerType, Method>),
+}
+
+impl MethodSelection {
+    /// `None` for layer types a per layer type selection leaves out
+    pub fn for_layer_type(&self, layer_type: &LayerType) -> Option<&Method> {
+        match self {
+            MethodSelection::Global(method) => Some(method),
+            MethodSelection::PerLayerType(methods) => methods.get(layer_type),
+        }
+    }
+}
+
+/// Weight the relative inhabitants of a house are multiplied with depending on its
+/// distance to the station, from 1 at the station down to the value at the coverage radius
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum DecayFunction {
+    /// full weight up to the coverage radius
+    #[serde(rename = "step")]
+    Step,
+    /// 0 at the coverage radius
+    #[serde(rename = "linear")]
+    Linear,
+    /// 0.05 at the coverage radius
+    #[serde(rename = "exponential")]
+    Exponential,
+    /// standard deviation of half the coverage radius, 0.14 at the coverage radius
+    #[serde(rename = "gaussian")]
+    Gaussian,
+}
+
+impl DecayFunction {
+    pub fn weight(&self, distance: f64, coverage: f64) -> f64 {
+        if !within_coverage(distance, coverage) {
+            return 0.0;
+        }
+        let relative_distance = if coverage > 0.0 {
+            (distance / coverage).min(1.0)
+        } else {
+            0.0
+        };
+        match self {
+            DecayFunction::Step => 1.0,
+            DecayFunction::Linear => 1.0 - relative_distance,
+            DecayFunction::Exponential => (-3.0 * relative_distance).exp(),
+            DecayFunction::Gaussian => (-2.0 * relative_distance.powi(2)).exp(),
+        }
+    }
+}
+
+/// Tolerance (in meters) for houses at the edge of a coverage area, so rounding in
+/// the distance calculation doesn't decide whether they are included
+const COVERAGE_TOLERANCE: f64 = 1e-6;
+
+/// Houses exactly at the coverage radius are inside the coverage area
+pub fn within_coverage(distance: f64, coverage: f64) -> bool {
+    distance <= coverage + COVERAGE_TOLERANCE
 }
 
 /// Gets all houses which are in the coverage area of a station and which are not closer to another station
@@ -79,97 +267,388 @@ pub fn get_houses_in_coverage<'a, D: DistanceCalculator + Sync>(
     houses: &'a [PopulatedCentroid],
     distance_calculator: D,
     possible_collision_stations: &[&Station],
+) -> Vec<PopulatedCentroidInfo<'a>> {
+    houses_in_coverage(
+        origin,
+        coverage,
+        houses.par_iter(),
+        distance_calculator,
+        possible_collision_stations,
+    )
+}
+
+/// Like `get_houses_in_coverage`, but only looks at the houses the index returns for the station
+fn get_indexed_houses_in_coverage<'a, D: DistanceCalculator + Sync>(
+    origin: &Point,
+    coverage: f64,
+    houses: &'a [PopulatedCentroid],
+    index: Option<&CentroidIndex>,
+    distance_calculator: D,
+    possible_collision_stations: &[&Station],
+) -> Vec<PopulatedCentroidInfo<'a>> {
+    let Some(index) = index else {
+        return get_houses_in_coverage(
+            origin,
+            coverage,
+            houses,
+            distance_calculator,
+            possible_collision_stations,
+        );
+    };
+    let candidates = index.candidates(origin, coverage);
+    houses_in_coverage(
+        origin,
+        coverage,
+        candidates.par_iter().map(|position| &houses[*position]),
+        distance_calculator,
+        possible_collision_stations,
+    )
+}
+
+fn houses_in_coverage<'a, D: DistanceCalculator + Sync>(
+    origin: &Point,
+    coverage: f64,
+    houses: impl ParallelIterator<Item = &'a PopulatedCentroid>,
+    distance_calculator: D,
+    possible_collision_stations: &[&Station],
 ) -> Vec<PopulatedCentroidInfo<'a>> {
     let distance_from_origin = distance_calculator.fix_point(origin);
+    // every station is fixed once, network routing searches the streets from there
+    let others: Vec<(D::FixedPoint, f64)> = possible_collision_stations
+        .iter()
+        .map(|other| {
+            (
+                distance_calculator.fix_point(&other.location),
+                other.coverage(),
+            )
+        })
+        .collect();
     houses
-        .par_iter()
         .filter_map(|house| {
             let distance = distance_from_origin.distance(house);
-            if distance < coverage {
-                Some(PopulatedCentroidInfo{centroid: house, distance})
+            if within_coverage(distance, coverage) {
+                Some(PopulatedCentroidInfo {
+                    centroid: house,
+                    distance,
+                })
             } else {
                 None
             }
         }) // PopulatedCentroid is in the radius of our station
         .filter(|hi| {
-            possible_collision_stations.iter().all(|other| {
-                distance_calculator.distance(hi.centroid, &other.location) > other.coverage() // PopulatedCentroid is not in the coverage area of the other station or
-                    || distance_calculator.distance(hi.centroid, &origin) < distance_calculator.distance(hi.centroid, &other.location)
+            others.iter().all(|(distance_from_other, other_coverage)| {
+                let other_distance = distance_from_other.distance(hi.centroid);
+                !within_coverage(other_distance, *other_coverage) // PopulatedCentroid is not in the coverage area of the other station or
+                    || hi.distance < other_distance
                 // PopulatedCentroid is closer to the current station
             })
         })
         .collect()
 }
 
+/// Houses in the coverage of stations closer to each other than `separation_distance`
+/// (in meters) are only counted for the nearer station. Without a separation
+/// distance this applies to all stations with overlapping coverage areas.
+/// The `index` has to be built from `houses`, without one all houses are checked.
+/// With naive routing houses behind one of the `barriers` aren't covered.
 pub fn houses_for_stations<'a, 'b>(
     stations: &'a [Station],
     houses: &'b [PopulatedCentroid],
+    index: Option<&CentroidIndex>,
     method: &Method,
     routing: &Routing,
     streets: &Streets,
+    barriers: &[LineString],
+    separation_distance: Option<f64>,
 ) -> CoverageMap<'a, 'b> {
-    let mut inhabitants_map = HashMap::new();
-
-    for station in stations {
-        let possible_collision_stations: Vec<&Station> = stations
-            .iter()
-            .filter(|other| *other != station)
-            .filter(|other| {
-                other.haversine_distance(station) < (other.coverage() + station.coverage())
-            })
-            .collect();
-        let houses = match routing {
-            Routing::Naive => get_houses_in_coverage(
-                &station.location,
-                station.coverage(),
-                houses,
-                HaversineDistanceCalculator::new(),
-                &possible_collision_stations,
-            ),
-            Routing::Osm => get_houses_in_coverage(
-                &station.location,
-                station.coverage(),
-                houses,
-                OsmDistanceCalculator::new(streets),
-                &possible_collision_stations,
-            ),
-        };
-        inhabitants_map.insert(
-            station.id.as_str(),
-            StationCoverageInfo::from_houses_with_method(houses, method),
-        );
-    }
+    // stations are independent of each other, every station yields one entry so the
+    // result doesn't depend on the order they are processed in
+    let inhabitants_map = stations
+        .par_iter()
+        .map(|station| {
+            if !station.is_active() {
+                return (
+                    station.id.as_str(),
+                    StationCoverageInfo::from_houses_with_method(
+                        Vec::new(),
+                        method,
+                        station.coverage(),
+                    ),
+                );
+            }
+            let possible_collision_stations: Vec<&Station> = stations
+                .iter()
+                .filter(|other| other.is_active())
+                .filter(|other| *other != station)
+                .filter(|other| {
+                    other.haversine_distance(station)
+                        < separation_distance.unwrap_or(other.coverage() + station.coverage())
+                })
+                .collect();
+            let houses = match routing {
+                Routing::Naive if barriers.is_empty() => get_indexed_houses_in_coverage(
+                    &station.location,
+                    station.coverage(),
+                    houses,
+                    index,
+                    HaversineDistanceCalculator::new(),
+                    &possible_collision_stations,
+                ),
+                Routing::Naive => get_indexed_houses_in_coverage(
+                    &station.location,
+                    station.coverage(),
+                    houses,
+                    index,
+                    BarrierAwareDistanceCalculator::new(barriers),
+                    &possible_collision_stations,
+                ),
+                Routing::Osm => get_indexed_houses_in_coverage(
+                    &station.location,
+                    station.coverage(),
+                    houses,
+                    index,
+                    OsmDistanceCalculator::new(streets),
+                    &possible_collision_stations,
+                ),
+                Routing::Network => get_indexed_houses_in_coverage(
+                    &station.location,
+                    station.coverage(),
+                    houses,
+                    index,
+                    NetworkDistanceCalculator::new(
+                        streets,
+                        possible_collision_stations
+                            .iter()
+                            .map(|other| other.coverage())
+                            .fold(station.coverage(), f64::max),
+                    ),
+                    &possible_collision_stations,
+                ),
+                Routing::Bike => get_indexed_houses_in_coverage(
+                    &station.location,
+                    station.coverage(),
+                    houses,
+                    index,
+                    NetworkDistanceCalculator::cycling(
+                        streets,
+                        possible_collision_stations
+                            .iter()
+                            .map(|other| other.coverage())
+                            .fold(station.coverage(), f64::max),
+                    ),
+                    &possible_collision_stations,
+                ),
+            };
+            (
+                station.id.as_str(),
+                StationCoverageInfo::from_houses_with_method(houses, method, station.coverage()),
+            )
+        })
+        .collect();
 
     CoverageMap(inhabitants_map)
 }
 
-#[derive(Serialize)]
+/// Inhabitants of a station split into those no other station covers and those also
+/// in the coverage area of another station
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct StationOverlap {
+    pub exclusive: u32,
+    pub shared: u32,
+}
+
+/// Splits the houses of every station in `coverage_map` by whether another active
+/// station covers them as well. Houses stay assigned to the station of the
+/// `CoverageMap`, so `exclusive + shared` is the inhabitants of the station there.
+pub fn station_overlaps(
+    coverage_map: &CoverageMap,
+    stations: &[Station],
+    method: &Method,
+    routing: &Routing,
+    streets: &Streets,
+) -> Vec<(String, StationOverlap)> {
+    stations
+        .par_iter()
+        .filter_map(|station| {
+            let coverage = coverage_map.0.get(station.id.as_str())?;
+            // the street network is never shorter than the direct distance
+            let others: Vec<&Station> = stations
+                .iter()
+                .filter(|other| other.is_active())
+                .filter(|other| *other != station)
+                .filter(|other| {
+                    other.haversine_distance(station) < other.coverage() + station.coverage()
+                })
+                .collect();
+            let (exclusive, shared) = match routing {
+                Routing::Naive => split_shared(
+                    &coverage.houses,
+                    &others,
+                    HaversineDistanceCalculator::new(),
+                ),
+                Routing::Osm => split_shared(
+                    &coverage.houses,
+                    &others,
+                    OsmDistanceCalculator::new(streets),
+                ),
+                Routing::Network => split_shared(
+                    &coverage.houses,
+                    &others,
+                    NetworkDistanceCalculator::new(
+                        streets,
+                        others
+                            .iter()
+                            .map(|other| other.coverage())
+                            .fold(0.0, f64::max),
+                    ),
+                ),
+                Routing::Bike => split_shared(
+                    &coverage.houses,
+                    &others,
+                    NetworkDistanceCalculator::cycling(
+                        streets,
+                        others
+                            .iter()
+                            .map(|other| other.coverage())
+                            .fold(0.0, f64::max),
+                    ),
+                ),
+            };
+            Some((
+                station.id.clone(),
+                StationOverlap {
+                    exclusive: StationCoverageInfo::from_houses_with_method(
+                        exclusive,
+                        method,
+                        station.coverage(),
+                    )
+                    .inhabitants,
+                    shared: StationCoverageInfo::from_houses_with_method(
+                        shared,
+                        method,
+                        station.coverage(),
+                    )
+                    .inhabitants,
+                },
+            ))
+        })
+        .collect()
+}
+
+type SplitHouses<'a> = (
+    Vec<PopulatedCentroidInfo<'a>>,
+    Vec<PopulatedCentroidInfo<'a>>,
+);
+
+/// (houses only in reach of their own station, houses in the coverage of one of `others` too)
+fn split_shared<'a, D: DistanceCalculator>(
+    houses: &[PopulatedCentroidInfo<'a>],
+    others: &[&Station],
+    distance_calculator: D,
+) -> SplitHouses<'a> {
+    let others: Vec<(D::FixedPoint, f64)> = others
+        .iter()
+        .map(|other| {
+            (
+                distance_calculator.fix_point(&other.location),
+                other.coverage(),
+            )
+        })
+        .collect();
+    houses.iter().cloned().partition(|house| {
+        !others.iter().any(|(distance_from_other, coverage)| {
+            within_coverage(distance_from_other.distance(house.centroid), *coverage)
+        })
+    })
+}
+
+#[derive(Serialize, Default)]
 pub struct PopulatedCentroidCoverageLayer(Vec<PopulatedCentroidCoverage>);
 
-impl From<CoverageMap<'_, '_>> for PopulatedCentroidCoverageLayer {
-    fn from(value: CoverageMap<'_, '_>) -> Self {
+impl PopulatedCentroidCoverageLayer {
+    pub fn new(
+        value: CoverageMap<'_, '_>,
+        output_cost: OutputCost,
+        walking_speed_kmh: f64,
+    ) -> Self {
         Self(
             value
                 .0
                 .into_iter()
                 .flat_map(|(station, sci)| {
-                    sci.houses.into_iter().map(|hi| PopulatedCentroidCoverage {
-                        geometry: hi.centroid.geometry,
-                        data_layer: "dl".to_owned(), // TODO: maybe remove this
-                        distance: hi.distance,
-                        closest_station: station.to_owned(),
-                    })
+                    sci.houses
+                        .into_iter()
+                        .map(move |hi| PopulatedCentroidCoverage {
+                            geometry: hi.centroid.geometry,
+                            data_layer: "dl".to_owned(), // TODO: maybe remove this
+                            distance: match output_cost {
+                                OutputCost::Distance => Some(hi.distance),
+                                OutputCost::Time => None,
+                            },
+                            time: match output_cost {
+                                OutputCost::Distance => None,
+                                OutputCost::Time => {
+                                    Some(walking_minutes(hi.distance, walking_speed_kmh))
+                                }
+                            },
+                            closest_station: station.to_owned(),
+                        })
                 })
                 .collect(),
         )
     }
 }
 
+impl PopulatedCentroidCoverageLayer {
+    pub fn to_geojson_string(&self) -> Result<String, OLPError> {
+        to_feature_collection_string(&self.0).map_err(OLPError::from_error)
+    }
+}
+
+impl PopulatedCentroidCoverageLayer {
+    /// Encodes the houses inside tile `z/x/y` as a Mapbox Vector Tile with a single
+    /// `coverage` layer, `None` if there are no houses in the tile
+    pub fn to_mvt(&self, z: u32, x: u32, y: u32) -> Result<Option<Vec<u8>>, OLPError> {
+        let mut tile = Tile::new(MVT_EXTENT);
+        let mut layer = tile.create_layer("coverage");
+        let mut empty = true;
+        for house in &self.0 {
+            let (tile_x, tile_y) = web_mercator_tile_position(&house.geometry, z);
+            let pixel_x = (tile_x - x as f64) * MVT_EXTENT as f64;
+            let pixel_y = (tile_y - y as f64) * MVT_EXTENT as f64;
+            if !(0.0..MVT_EXTENT as f64).contains(&pixel_x)
+                || !(0.0..MVT_EXTENT as f64).contains(&pixel_y)
+            {
+                continue;
+            }
+            empty = false;
+            let geometry = GeomEncoder::new(GeomType::Point)
+                .point(pixel_x, pixel_y)
+                .and_then(|encoder| encoder.encode())
+                .map_err(OLPError::from_error)?;
+            let mut feature = layer.into_feature(geometry);
+            feature.add_tag_string("closest_station", &house.closest_station);
+            if let Some(distance) = house.distance {
+                feature.add_tag_double("distance", distance);
+            }
+            if let Some(time) = house.time {
+                feature.add_tag_double("time", time);
+            }
+            layer = feature.into_layer();
+        }
+        if empty {
+            return Ok(None);
+        }
+        tile.add_layer(layer).map_err(OLPError::from_error)?;
+        tile.to_bytes().map(Some).map_err(OLPError::from_error)
+    }
+}
+
 impl Responder for PopulatedCentroidCoverageLayer {
     type Body = BoxBody;
 
     fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
-        match to_feature_collection_string(&self.0) {
+        match self.to_geojson_string() {
             Ok(body) => HttpResponse::Ok()
                 .content_type(ContentType::json())
                 .body(body),
@@ -187,22 +666,1385 @@ pub struct PopulatedCentroidCoverage {
     )]
     geometry: Point,
     data_layer: String,
-    distance: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<f64>,
+    /// walking time in minutes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<f64>,
     closest_station: String,
 }
 
+/// Which cost is attached to each covered house
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub enum OutputCost {
+    #[default]
+    #[serde(rename = "distance")]
+    Distance,
+    #[serde(rename = "time")]
+    Time,
+}
+
+/// Least recently used cache for the results of `/station-info`, keyed by a hash of
+/// the request. Entries of an older generation of the layers are dropped.
+pub struct CoverageCache<T = (InhabitantsMap, AccessDistances)> {
+    entries: HashMap<u64, CoverageCacheEntry<T>>,
+    capacity: usize,
+    generation: u64,
+    clock: u64,
+}
+
+/// Cache for the results of `/station-removal-impact`, which has its own entries
+/// since the same request gives a different result there
+pub type RemovalImpactCache = CoverageCache<InhabitantsMap>;
+
+struct CoverageCacheEntry<T> {
+    last_used: u64,
+    result: T,
+}
+
+impl CoverageCache {
+    /// Hash of the json of `request` with sorted keys, as the order of the entries of
+    /// its maps differs between two requests with the same content
+    pub fn key<T: Serialize>(request: &T) -> Result<u64, OLPError> {
+        let mut hasher = DefaultHasher::new();
+        persistence::to_stable_json(request)?.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+}
+
+impl<T: Clone> CoverageCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        CoverageCache {
+            entries: HashMap::new(),
+            capacity,
+            generation: 0,
+            clock: 0,
+        }
+    }
+
+    fn advance(&mut self, generation: u64) {
+        if generation > self.generation {
+            self.entries.clear();
+            self.generation = generation;
+        }
+        self.clock += 1;
+    }
+
+    pub fn get(&mut self, key: u64, generation: u64) -> Option<T> {
+        self.advance(generation);
+        if generation < self.generation {
+            return None;
+        }
+        let entry = self.entries.get_mut(&key)?;
+        entry.last_used = self.clock;
+        Some(entry.result.clone())
+    }
+
+    pub fn insert(&mut self, key: u64, generation: u64, result: T) {
+        self.advance(generation);
+        // results of outdated layers and a cache without entries aren't stored
+        if generation < self.generation || self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            key,
+            CoverageCacheEntry {
+                last_used: self.clock,
+                result,
+            },
+        );
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CoverageInfoQuery {
+    output_cost: Option<OutputCost>,
+    /// minutes of walking, replaces the coverage of all stations
+    time_budget: Option<f64>,
+    /// count the expected wait for the next departure against the time budget
+    #[serde(default)]
+    include_wait: bool,
+}
+
+/// Converts a walking distance in meters to minutes
+pub fn walking_minutes(distance: f64, walking_speed_kmh: f64) -> f64 {
+    distance / (walking_speed_kmh * 1000.0 / 60.0)
+}
+
+/// Converts minutes of walking to a distance in meters
+pub fn walking_distance(minutes: f64, walking_speed_kmh: f64) -> f64 {
+    minutes * walking_speed_kmh * 1000.0 / 60.0
+}
+
+/// Distance in meters that can be travelled with `routing` to a station within
+/// `time_budget` minutes. With `include_wait` the expected wait of half the `headway` (minutes between two
+/// departures) at the station is part of the budget, so less frequent service shrinks
+/// the coverage, down to 0 once the wait takes up the whole budget.
+pub fn travel_time_coverage(
+    time_budget: f64,
+    headway: Option<f64>,
+    include_wait: bool,
+    routing: &Routing,
+    config: &Config,
+) -> Result<f64, OLPError> {
+    if !time_budget.is_finite() || time_budget <= 0.0 {
+        return Err(OLPError::InvalidInput(
+            "time_budget has to be a positive number of minutes".to_owned(),
+        ));
+    }
+    let wait = match headway {
+        Some(headway) if !headway.is_finite() || headway < 0.0 => {
+            return Err(OLPError::InvalidInput(
+                "headway has to be a positive number of minutes".to_owned(),
+            ))
+        }
+        Some(headway) if include_wait => headway / 2.0,
+        _ => 0.0,
+    };
+    Ok(walking_distance(
+        (time_budget - wait).max(0.0),
+        routing.speed_kmh(config)?,
+    ))
+}
+
+/// Sets the coverage of all stations to the distance travelled in `time_budget` minutes
+/// at the speed of `routing`, minus the expected wait at the station with
+/// `include_wait`. Without a time budget the stations are unchanged.
+pub fn with_time_budget(
+    stations: &[Station],
+    time_budget: Option<f64>,
+    include_wait: bool,
+    routing: &Routing,
+    config: &Config,
+) -> Result<Vec<Station>, OLPError> {
+    let Some(minutes) = time_budget else {
+        if include_wait {
+            return Err(OLPError::InvalidInput(
+                "include_wait needs a time_budget".to_owned(),
+            ));
+        }
+        return Ok(stations.to_vec());
+    };
+    stations
+        .iter()
+        .map(|station| {
+            let coverage =
+                travel_time_coverage(minutes, station.headway(), include_wait, routing, config)?;
+            Ok(station.with_coverage(coverage))
+        })
+        .collect()
+}
+
 pub async fn coverage_info(
     stations: web::Json<Vec<Station>>,
     routing: web::Path<Routing>,
+    query: web::Query<CoverageInfoQuery>,
     layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
 ) -> Result<PopulatedCentroidCoverageLayer, OLPError> {
-    let layer = layers.read().map_err(OLPError::from_error)?.all_merged();
+    coverage_layer(
+        &with_time_budget(
+            &stations,
+            query.time_budget,
+            query.include_wait,
+            &routing,
+            &config,
+        )?,
+        &routing,
+        query.output_cost.unwrap_or_default(),
+        &layers,
+        &config,
+    )
+}
+
+/// All layers merged into one, after checking there are stations and they are within
+/// the data
+fn merged_layer(
+    stations: &[Station],
+    layers: &RwLock<Layers>,
+    config: &Config,
+) -> Result<Layer, OLPError> {
+    validation::validate_not_empty(stations.len(), "stations")?;
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    layers.ensure_loaded()?;
+    validation::validate_points(
+        stations.iter().map(|station| &station.location),
+        validation::data_bounds(config, &layers).as_ref(),
+    )?;
+    Ok(layers.all_merged())
+}
+
+pub fn coverage_layer(
+    stations: &[Station],
+    routing: &Routing,
+    output_cost: OutputCost,
+    layers: &RwLock<Layers>,
+    config: &Config,
+) -> Result<PopulatedCentroidCoverageLayer, OLPError> {
+    let layer = merged_layer(stations, layers, config)?;
+    let coverage_info = houses_for_stations(
+        stations,
+        layer.get_centroids(),
+        Some(layer.get_index()),
+        &Method::Absolute,
+        routing,
+        routing.streets(&layer),
+        layer.get_barriers(),
+        None,
+    );
+    Ok(PopulatedCentroidCoverageLayer::new(
+        coverage_info,
+        output_cost,
+        routing.speed_kmh(config)?,
+    ))
+}
+
+/// Covered region of a station, the concave hull of its covered houses, see
+/// `geometry::service_area`
+#[derive(Serialize)]
+pub struct StationCoverageArea {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: Geometry,
+    station: String,
+    inhabitants: u32,
+    houses: usize,
+}
+
+#[derive(Serialize)]
+pub struct StationCoverageAreas(Vec<StationCoverageArea>);
+
+impl Responder for StationCoverageAreas {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
+        match to_feature_collection_string(&self.0) {
+            Ok(body) => HttpResponse::Ok()
+                .content_type("application/geo+json")
+                .body(body),
+            Err(error) => HttpResponse::InternalServerError()
+                .body(format!("failed to get coverage areas: {}", error)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CoverageAreaQuery {
+    /// minutes of walking, replaces the coverage of all stations
+    time_budget: Option<f64>,
+    /// count the expected wait for the next departure against the time budget
+    #[serde(default)]
+    include_wait: bool,
+    /// number of neighbouring houses the outline is built from, at least 3, defaults
+    /// to `coverage.area_concavity`
+    concavity: Option<u32>,
+}
+
+/// The covered region of every station as a GeoJSON FeatureCollection, stations
+/// without covered houses are left out
+pub async fn coverage_areas(
+    stations: web::Json<Vec<Station>>,
+    routing: web::Path<Routing>,
+    query: web::Query<CoverageAreaQuery>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<StationCoverageAreas, OLPError> {
+    let stations = with_time_budget(
+        &stations,
+        query.time_budget,
+        query.include_wait,
+        &routing,
+        &config,
+    )?;
+    let concavity = match query.concavity {
+        Some(concavity) => concavity,
+        None => config
+            .get_int("coverage.area_concavity")
+            .map_err(OLPError::from_error)?
+            .clamp(0, u32::MAX as i64) as u32,
+    };
+    if concavity < 3 {
+        return Err(OLPError::InvalidInput(
+            "concavity has to be at least 3".to_owned(),
+        ));
+    }
+    let layer = merged_layer(&stations, &layers, &config)?;
     let coverage_info = houses_for_stations(
         &stations,
         layer.get_centroids(),
+        Some(layer.get_index()),
         &Method::Absolute,
         &routing,
-        layer.get_streets(),
+        routing.streets(&layer),
+        layer.get_barriers(),
+        None,
     );
-    Ok(PopulatedCentroidCoverageLayer::from(coverage_info))
+    Ok(StationCoverageAreas(
+        stations
+            .iter()
+            .filter_map(|station| {
+                let coverage = coverage_info.0.get(station.id.as_str())?;
+                if coverage.houses.is_empty() {
+                    return None;
+                }
+                let houses: Vec<Point> = coverage
+                    .houses
+                    .iter()
+                    .map(|hi| hi.centroid.geometry)
+                    .collect();
+                Some(StationCoverageArea {
+                    geometry: service_area(&houses, concavity),
+                    station: station.id.clone(),
+                    inhabitants: coverage.inhabitants,
+                    houses: coverage.houses.len(),
+                })
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AreaCoverageRequest {
+    stations: Vec<Station>,
+    /// named polygons, the name is read from the `name` property of each feature
+    areas: Vec<Feature>,
+    routing: Option<Routing>,
+}
+
+#[derive(Serialize)]
+pub struct AreaCoverage {
+    name: String,
+    total_inhabitants: u32,
+    covered_inhabitants: u32,
+}
+
+struct NamedArea {
+    name: String,
+    geometry: MultiPolygon,
+}
+
+impl TryFrom<Feature> for NamedArea {
+    type Error = OLPError;
+
+    fn try_from(value: Feature) -> Result<Self, Self::Error> {
+        let name = value
+            .property("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or_default()
+            .to_owned();
+        let geometry = value
+            .geometry
+            .ok_or_else(|| OLPError::InvalidInput(format!("area {:?} has no geometry", name)))?;
+        let geometry = multi_polygon_from_geojson(geometry)?;
+        Ok(NamedArea { name, geometry })
+    }
+}
+
+/// Covered and total inhabitants within each of the given areas
+pub async fn area_coverage_info(
+    request: web::Json<AreaCoverageRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<ListResponse<AreaCoverage>, OLPError> {
+    let request = request.into_inner();
+    validation::validate_not_empty(request.stations.len(), "stations")?;
+    let areas = request
+        .areas
+        .into_iter()
+        .map(NamedArea::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let layer = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        validation::validate_points(
+            request.stations.iter().map(|station| &station.location),
+            validation::data_bounds(&config, &layers).as_ref(),
+        )?;
+        layers.all_merged()
+    };
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let routing = request.routing.as_ref().unwrap_or(&defaults.routing);
+    let coverage_info = houses_for_stations(
+        &request.stations,
+        layer.get_centroids(),
+        Some(layer.get_index()),
+        &Method::Absolute,
+        routing,
+        routing.streets(&layer),
+        layer.get_barriers(),
+        None,
+    );
+    let covered_houses: Vec<&PopulatedCentroid> = coverage_info
+        .0
+        .values()
+        .flat_map(|coverage| coverage.houses.iter().map(|hi| hi.centroid))
+        .collect();
+    Ok(ListResponse::new(
+        areas
+            .into_iter()
+            .map(|area| AreaCoverage {
+                total_inhabitants: layer
+                    .get_centroids()
+                    .iter()
+                    .filter(|house| area.geometry.contains(&house.geometry))
+                    .map(|house| house.pop)
+                    .sum(),
+                covered_inhabitants: covered_houses
+                    .iter()
+                    .filter(|house| area.geometry.contains(&house.geometry))
+                    .map(|house| house.pop)
+                    .sum(),
+                name: area.name,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AreaPopulationRequest {
+    /// GeoJSON polygon or multipolygon
+    area: geojson::Geometry,
+}
+
+#[derive(Serialize)]
+pub struct AreaPopulation {
+    total_inhabitants: u64,
+    /// every loaded layer type, 0 if none of its centroids is in the area
+    by_layer_type: HashMap<LayerType, u64>,
+}
+
+/// Inhabitants of all centroids in the area, regardless of any station. Centroids on
+/// the boundary count as inside, so a centroid on the shared edge of two adjacent
+/// areas is counted for both.
+pub async fn population_in_area(
+    request: web::Json<AreaPopulationRequest>,
+    layers: web::Data<RwLock<Layers>>,
+) -> Result<web::Json<AreaPopulation>, OLPError> {
+    let area = multi_polygon_from_geojson(request.into_inner().area)?;
+    let merged_layers = layers
+        .read()
+        .map_err(OLPError::from_error)?
+        .all_merged_by_type();
+    let by_layer_type: HashMap<LayerType, u64> = merged_layers
+        .par_iter()
+        .map(|layer| {
+            let inhabitants = layer
+                .get_centroids()
+                .iter()
+                .filter(|house| area.intersects(&house.geometry))
+                .map(|house| house.pop as u64)
+                .sum();
+            (*layer.get_type(), inhabitants)
+        })
+        .collect();
+    Ok(web::Json(AreaPopulation {
+        total_inhabitants: by_layer_type.values().sum(),
+        by_layer_type,
+    }))
+}
+
+/// Meters per degree of latitude, and of longitude at the equator
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+#[derive(Deserialize)]
+pub struct PopulationGridRequest {
+    /// `[west, south, east, north]` in degrees
+    bbox: [f64; 4],
+    /// edge length of the cells in meters
+    cell_size: f64,
+}
+
+#[derive(Serialize)]
+pub struct PopulationGridCell {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: Polygon,
+    inhabitants: u64,
+}
+
+#[derive(Serialize)]
+pub struct PopulationGrid(Vec<PopulationGridCell>);
+
+impl Responder for PopulationGrid {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
+        match to_feature_collection_string(&self.0) {
+            Ok(body) => HttpResponse::Ok()
+                .content_type("application/geo+json")
+                .body(body),
+            Err(error) => HttpResponse::InternalServerError()
+                .body(format!("failed to get population grid: {}", error)),
+        }
+    }
+}
+
+/// Inhabitants of all layers summed up per grid cell as a GeoJSON FeatureCollection,
+/// to show the density before placing stations. The cells are `cell_size` meters at
+/// the middle of the bbox, cells along the east and north edge are cut off by it.
+/// Only cells with inhabitants are returned, grids with more than
+/// `population.grid_max_cells` cells are rejected.
+pub async fn population_grid(
+    request: web::Json<PopulationGridRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<PopulationGrid, OLPError> {
+    let bbox = validation::validate_bbox(request.bbox)?;
+    if !request.cell_size.is_finite() || request.cell_size <= 0.0 {
+        return Err(OLPError::InvalidInput(
+            "cell_size has to be a positive number of meters".to_owned(),
+        ));
+    }
+    let cell_height = request.cell_size / METERS_PER_DEGREE;
+    let cell_width =
+        request.cell_size / (METERS_PER_DEGREE * bbox.center().y.to_radians().cos().max(1e-6));
+    let columns = (bbox.width() / cell_width).ceil();
+    let rows = (bbox.height() / cell_height).ceil();
+    let max_cells = config
+        .get_int("population.grid_max_cells")
+        .map_err(OLPError::from_error)?;
+    if columns * rows > max_cells as f64 {
+        return Err(OLPError::InvalidInput(format!(
+            "the grid would have {} cells, at most {} are allowed, use a larger cell_size or a smaller bbox",
+            columns * rows,
+            max_cells
+        )));
+    }
+    let (columns, rows) = (columns as usize, rows as usize);
+
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    let mut inhabitants = vec![0u64; columns * rows];
+    for house in layers.iter().flat_map(|layer| layer.get_centroids()) {
+        if !bbox.intersects(&house.geometry) {
+            continue;
+        }
+        // houses on the east or north edge belong to the last cell
+        let column = (((house.geometry.x() - bbox.min().x) / cell_width) as usize).min(columns - 1);
+        let row = (((house.geometry.y() - bbox.min().y) / cell_height) as usize).min(rows - 1);
+        inhabitants[row * columns + column] += house.pop as u64;
+    }
+
+    Ok(PopulationGrid(
+        inhabitants
+            .into_iter()
+            .enumerate()
+            .filter(|(_, inhabitants)| *inhabitants > 0)
+            .map(|(cell, inhabitants)| {
+                let west = bbox.min().x + (cell % columns) as f64 * cell_width;
+                let south = bbox.min().y + (cell / columns) as f64 * cell_height;
+                PopulationGridCell {
+                    geometry: Rect::new(
+                        (west, south),
+                        (
+                            (west + cell_width).min(bbox.max().x),
+                            (south + cell_height).min(bbox.max().y),
+                        ),
+                    )
+                    .to_polygon(),
+                    inhabitants,
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct StationSnappingRequest {
+    stations: Vec<Station>,
+    /// overrides `routing.snap_tolerance_m`
+    #[serde(alias = "max_snap_distance")]
+    snap_tolerance_m: Option<f64>,
+}
+
+/// Where a station is connected to the street network for osm routing
+#[derive(Serialize)]
+pub struct StationSnapping {
+    id: String,
+    snapped_location: Option<Point>,
+    snap_distance: Option<f64>,
+    /// the station is further away from the street network than the tolerance,
+    /// its osm based coverage is not reliable
+    off_network: bool,
+}
+
+/// Meters a station may be away from the street network before routing from it is
+/// flagged. `routing.max_snap_distance` is still read for older configs that don't
+/// set `routing.snap_tolerance_m`, with a deprecation warning.
+pub fn snap_tolerance(config: &Config, requested: Option<f64>) -> Result<f64, OLPError> {
+    static DEPRECATION: Once = Once::new();
+    let tolerance = match requested {
+        Some(tolerance) => tolerance,
+        None => match config.get_float("routing.snap_tolerance_m") {
+            Ok(tolerance) => tolerance,
+            Err(config::ConfigError::NotFound(_)) => {
+                match config.get_float("routing.max_snap_distance") {
+                    Ok(tolerance) => {
+                        DEPRECATION.call_once(|| {
+                            log::warn!("routing.max_snap_distance is deprecated, use routing.snap_tolerance_m instead")
+                        });
+                        tolerance
+                    }
+                    Err(config::ConfigError::NotFound(_)) => DEFAULT_SNAP_TOLERANCE_M,
+                    Err(e) => return Err(OLPError::from_error(e)),
+                }
+            }
+            Err(e) => return Err(OLPError::from_error(e)),
+        },
+    };
+    if !tolerance.is_finite() || tolerance < 0.0 {
+        return Err(OLPError::InvalidInput(
+            "snap_tolerance_m has to be a positive number of meters".to_owned(),
+        ));
+    }
+    Ok(tolerance)
+}
+
+/// Ids of the stations further than `tolerance` meters from the network of a layer
+/// type routed over the streets, their coverage there starts from a far away node
+pub fn unsnapped_stations<'r>(
+    stations: &[Station],
+    layers: &Layers,
+    routing_for: impl Fn(&LayerType) -> &'r Routing,
+    tolerance: f64,
+) -> Vec<String> {
+    let mut closest: HashMap<(&str, LayerType), f64> = HashMap::new();
+    for layer in layers.iter() {
+        let routing = routing_for(layer.get_type());
+        if matches!(routing, Routing::Naive) {
+            continue;
+        }
+        let streets = routing.streets(layer);
+        for station in stations {
+            let distance = snap_to_street(streets, &station.location)
+                .map_or(f64::MAX, |(_, distance)| distance);
+            closest
+                .entry((&station.id, *layer.get_type()))
+                .and_modify(|closest| *closest = closest.min(distance))
+                .or_insert(distance);
+        }
+    }
+    let mut unsnapped: Vec<String> = closest
+        .into_iter()
+        .filter(|(_, distance)| *distance > tolerance)
+        .map(|((id, _), _)| id.to_owned())
+        .collect();
+    unsnapped.sort();
+    unsnapped.dedup();
+    unsnapped
+}
+
+fn off_network_warning(id: &str, distance: Option<f64>, tolerance: f64) -> String {
+    match distance {
+        Some(distance) => format!(
+            "station {} is {:.0}m from the street network, more than the {:.0}m tolerance",
+            id, distance, tolerance
+        ),
+        None => format!(
+            "station {} can't be snapped, no street network is loaded",
+            id
+        ),
+    }
+}
+
+pub async fn station_snapping(
+    request: web::Json<StationSnappingRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<ListResponse<StationSnapping>, OLPError> {
+    validation::validate_not_empty(request.stations.len(), "stations")?;
+    let tolerance = snap_tolerance(&config, request.snap_tolerance_m)?;
+    let layer = layers.read().map_err(OLPError::from_error)?.all_merged();
+    let streets = layer.get_streets();
+    let snappings: Vec<StationSnapping> = request
+        .stations
+        .iter()
+        .map(|station| {
+            let snapped = snap_to_street(streets, &station.location);
+            StationSnapping {
+                id: station.id.clone(),
+                snapped_location: snapped.map(|(node, _)| streets.nodes[&node]),
+                snap_distance: snapped.map(|(_, distance)| distance),
+                off_network: snapped.is_none_or(|(_, distance)| distance > tolerance),
+            }
+        })
+        .collect();
+    let warnings = snappings
+        .iter()
+        .filter(|snapping| snapping.off_network)
+        .map(|snapping| off_network_warning(&snapping.id, snapping.snap_distance, tolerance))
+        .collect();
+    Ok(ListResponse::new(snappings).with_warnings(warnings))
+}
+
+/// Houses close to a station which can't be reached over the street network
+/// because a barrier is in the way, hinting at a crossing missing in the data
+#[derive(Serialize)]
+pub struct BarrierHotspot {
+    station: String,
+    location: Point,
+    houses: usize,
+    inhabitants: u32,
+}
+
+/// Barriers are only kept when `routing.import_barriers` is set, without them there
+/// is nothing to look for and the request is rejected instead of answered empty
+pub async fn barrier_hotspots(
+    stations: web::Json<Vec<Station>>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<ListResponse<BarrierHotspot>, OLPError> {
+    validation::validate_not_empty(stations.len(), "stations")?;
+    if !config.get_bool("routing.import_barriers").unwrap_or(false) {
+        return Err(OLPError::UnprocessableInput(
+            "barriers are not imported, enable routing.import_barriers and reload the data"
+                .to_owned(),
+        ));
+    }
+    let layer = merged_layer(&stations, &layers, &config)?;
+    let distance_calculator = OsmDistanceCalculator::new(layer.get_streets());
+    let hotspots = stations
+        .iter()
+        .filter_map(|station| {
+            let distance_from_station = distance_calculator.fix_point(&station.location);
+            let unreachable: Vec<&PopulatedCentroid> = layer
+                .get_centroids()
+                .par_iter()
+                .filter(|house| {
+                    within_coverage(
+                        house.haversine_distance(&station.location),
+                        station.coverage(),
+                    )
+                })
+                .filter(|house| {
+                    !within_coverage(distance_from_station.distance(house), station.coverage())
+                })
+                .filter(|house| {
+                    crosses_barrier(&station.location, &house.geometry, layer.get_barriers())
+                })
+                .collect();
+            let location =
+                MultiPoint::from_iter(unreachable.iter().map(|house| house.geometry)).centroid()?;
+            Some(BarrierHotspot {
+                station: station.id.clone(),
+                location,
+                houses: unreachable.len(),
+                inhabitants: unreachable.iter().map(|house| house.pop).sum(),
+            })
+        })
+        .collect();
+    Ok(ListResponse::new(hotspots))
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{HaversineDistance, Point};
+    use osmpbfreader::NodeId;
+
+    use super::*;
+
+    const COVERAGE: f64 = 400.0;
+
+    fn assert_weight(decay: DecayFunction, distance: f64, expected: f64) {
+        let weight = decay.weight(distance, COVERAGE);
+        assert!(
+            (weight - expected).abs() < 1e-9,
+            "weight at {} m is {}, expected {}",
+            distance,
+            weight,
+            expected
+        );
+    }
+
+    #[test]
+    fn decay_weights_at_station_half_distance_and_radius() {
+        let curves = [
+            (DecayFunction::Step, 1.0, 1.0),
+            (DecayFunction::Linear, 0.5, 0.0),
+            (DecayFunction::Exponential, (-1.5f64).exp(), (-3.0f64).exp()),
+            (DecayFunction::Gaussian, (-0.5f64).exp(), (-2.0f64).exp()),
+        ];
+        for (decay, at_half, at_radius) in curves {
+            assert_weight(decay, 0.0, 1.0);
+            assert_weight(decay, COVERAGE / 2.0, at_half);
+            assert_weight(decay, COVERAGE, at_radius);
+            assert_weight(decay, COVERAGE + 1.0, 0.0);
+        }
+    }
+
+    #[test]
+    fn step_decay_counts_like_relative() {
+        let house = PopulatedCentroid::poi(Point::new(16.37, 48.2), 37);
+        for distance in [0.5, 1.0, 12.3, 50.0, 199.9, COVERAGE] {
+            let info = PopulatedCentroidInfo {
+                centroid: &house,
+                distance,
+            };
+            assert_eq!(
+                info.inhabitants(&Method::Decay(DecayFunction::Step), COVERAGE),
+                info.inhabitants(&Method::Relative, COVERAGE),
+                "at {} m",
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn houses_exactly_at_the_radius_are_covered() {
+        let station = Point::new(16.37, 48.2);
+        let houses = [PopulatedCentroid::poi(Point::new(16.372, 48.2027), 10)];
+        // from the house to the station, not the other way round as in the coverage
+        let radius = houses[0].haversine_distance(&station);
+        assert!(within_coverage(radius, radius));
+        assert!(!within_coverage(radius + 0.01, radius));
+
+        let covered = get_houses_in_coverage(
+            &station,
+            radius,
+            &houses,
+            HaversineDistanceCalculator::new(),
+            &[],
+        );
+        assert_eq!(covered.len(), 1);
+        let index = CentroidIndex::new(&houses, &Streets::new(), &Streets::new());
+        let indexed = get_indexed_houses_in_coverage(
+            &station,
+            radius,
+            &houses,
+            Some(&index),
+            HaversineDistanceCalculator::new(),
+            &[],
+        );
+        assert_eq!(indexed.len(), 1);
+        let outside = get_houses_in_coverage(
+            &station,
+            radius - 0.01,
+            &houses,
+            HaversineDistanceCalculator::new(),
+            &[],
+        );
+        assert!(outside.is_empty());
+    }
+
+    #[test]
+    fn tiles_without_houses_are_empty() {
+        let layer = PopulatedCentroidCoverageLayer(vec![PopulatedCentroidCoverage {
+            geometry: Point::new(16.37, 48.2),
+            data_layer: "residential".to_owned(),
+            distance: Some(120.0),
+            time: None,
+            closest_station: "a".to_owned(),
+        }]);
+        let (x, y) = web_mercator_tile_position(&Point::new(16.37, 48.2), 12);
+        let (x, y) = (x as u32, y as u32);
+        assert!(layer.to_mvt(12, x, y).unwrap().is_some());
+        assert!(layer.to_mvt(12, x + 1, y).unwrap().is_none());
+    }
+
+    fn config(values: &[(&str, f64)]) -> Config {
+        let mut builder = Config::builder();
+        for (key, value) in values {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn the_snap_tolerance_is_preferred_over_the_legacy_key() {
+        let both = config(&[
+            ("routing.snap_tolerance_m", 50.0),
+            ("routing.max_snap_distance", 10.0),
+        ]);
+        assert_eq!(snap_tolerance(&both, None).unwrap(), 50.0);
+        let legacy = config(&[("routing.max_snap_distance", 10.0)]);
+        assert_eq!(snap_tolerance(&legacy, None).unwrap(), 10.0);
+        assert_eq!(
+            snap_tolerance(&config(&[]), None).unwrap(),
+            DEFAULT_SNAP_TOLERANCE_M
+        );
+        assert_eq!(snap_tolerance(&both, Some(5.0)).unwrap(), 5.0);
+        assert!(snap_tolerance(&both, Some(-1.0)).is_err());
+    }
+
+    fn station(id: &str, location: Point) -> Station {
+        serde_json::from_value(serde_json::json!({ "id": id, "location": location })).unwrap()
+    }
+
+    #[test]
+    fn stations_away_from_the_streets_are_unsnapped() {
+        let street = Point::new(16.37, 48.2);
+        let mut streets = Streets::new();
+        streets.nodes.insert(NodeId(1), street);
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(Vec::new(), streets, Streets::new()));
+
+        // a degree of latitude is about 111 km
+        let stations = [
+            station("near", Point::new(16.37, 48.2 + 5.0 / 111_000.0)),
+            station("far", Point::new(16.37, 48.2 + 500.0 / 111_000.0)),
+        ];
+        let tolerance = snap_tolerance(&config(&[]), None).unwrap();
+        let unsnapped = unsnapped_stations(&stations, &layers, |_| &Routing::Osm, tolerance);
+        assert_eq!(unsnapped, vec!["far".to_owned()]);
+    }
+
+    #[test]
+    fn a_cycleway_shortcut_extends_the_bike_catchment() {
+        let (a, b, c) = (NodeId(1), NodeId(2), NodeId(3));
+        let mut walking = Streets::new();
+        walking.nodes.insert(a, Point::new(16.37, 48.2));
+        walking.nodes.insert(b, Point::new(16.3725, 48.2011));
+        // about 250 m north of a, but more than 400 m away along the streets
+        walking.nodes.insert(c, Point::new(16.37, 48.20225));
+        let add = |streets: &mut Streets, from: NodeId, to: NodeId| {
+            let length = streets.nodes[&from].haversine_distance(&streets.nodes[&to]);
+            streets.streetgraph.add_edge(from, to, length);
+        };
+        add(&mut walking, a, b);
+        add(&mut walking, b, c);
+        let mut cycling = walking.clone();
+        add(&mut cycling, a, c);
+
+        let mut house = PopulatedCentroid::poi(walking.nodes[&c], 10);
+        house.street_graph_id = Some(c);
+        house.bike_graph_id = Some(c);
+        let layer = Layer::fixture(vec![house], walking, cycling);
+        let stations = [station("a", Point::new(16.37, 48.2))];
+
+        let covered = |routing: Routing| {
+            let coverage = houses_for_stations(
+                &stations,
+                layer.get_centroids(),
+                None,
+                &Method::Absolute,
+                &routing,
+                routing.streets(&layer),
+                &[],
+                None,
+            );
+            coverage.0["a"].houses.len()
+        };
+        assert_eq!(covered(Routing::Network), 0);
+        assert_eq!(covered(Routing::Bike), 1);
+    }
+
+    #[test]
+    fn houses_across_a_gap_in_the_streets_are_not_reached() {
+        // two streets with a gap between them, the house is connected to the east one
+        let mut streets = Streets::new();
+        let locations = [(1, 16.370), (2, 16.371), (3, 16.3725), (4, 16.374)];
+        for (id, lon) in locations {
+            streets.nodes.insert(NodeId(id), Point::new(lon, 48.2));
+        }
+        for (from, to) in [(1, 2), (3, 4)] {
+            let (from, to) = (NodeId(from), NodeId(to));
+            let length = streets.nodes[&from].haversine_distance(&streets.nodes[&to]);
+            streets.streetgraph.add_edge(from, to, length);
+        }
+        let mut house = PopulatedCentroid::poi(Point::new(16.3715, 48.2), 10);
+        house.street_graph_id = Some(NodeId(3));
+        let houses = [house];
+        // the house is closer to a in a straight line
+        let stations = [
+            station("a", Point::new(16.370, 48.2)),
+            station("b", Point::new(16.374, 48.2)),
+        ];
+
+        let covered = |routing: Routing| {
+            let coverage = houses_for_stations(
+                &stations,
+                &houses,
+                None,
+                &Method::Absolute,
+                &routing,
+                &streets,
+                &[],
+                None,
+            );
+            (coverage.0["a"].houses.len(), coverage.0["b"].houses.len())
+        };
+        assert_eq!(covered(Routing::Naive), (1, 0));
+        assert_eq!(covered(Routing::Network), (0, 1));
+    }
+
+    #[test]
+    fn overlapping_stations_count_shared_houses_once() {
+        let houses: Vec<PopulatedCentroid> = (0..10)
+            .flat_map(|x| (0..10).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let location = Point::new(16.37 + x as f64 * 0.0005, 48.2 + y as f64 * 0.0003);
+                PopulatedCentroid::poi(location, 10)
+            })
+            .collect();
+        let layer = Layer::fixture(houses, Streets::new(), Streets::new());
+        // about 75 m apart, each of them covers most of the houses
+        let stations = [
+            station("a", Point::new(16.3715, 48.2010)),
+            station("b", Point::new(16.3725, 48.2013)),
+            station("c", Point::new(16.3735, 48.2016)),
+        ];
+
+        for separation_distance in [None, Some(100.0), Some(1000.0)] {
+            let coverage = houses_for_stations(
+                &stations,
+                layer.get_centroids(),
+                None,
+                &Method::Absolute,
+                &Routing::Naive,
+                layer.get_streets(),
+                &[],
+                separation_distance,
+            );
+            let covered: u64 = coverage
+                .0
+                .values()
+                .map(|info| info.inhabitants as u64)
+                .sum();
+            assert!(covered > 0);
+            assert!(
+                covered <= layer.total_inhabitants(),
+                "{} of {} inhabitants with {:?}",
+                covered,
+                layer.total_inhabitants(),
+                separation_distance
+            );
+        }
+    }
+
+    #[test]
+    fn the_centroid_index_finds_the_same_houses_as_the_full_scan() {
+        let houses: Vec<PopulatedCentroid> = (0..30)
+            .flat_map(|x| (0..30).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let location = Point::new(16.36 + x as f64 * 0.0007, 48.19 + y as f64 * 0.0004);
+                PopulatedCentroid::poi(location, 1 + (x * y) % 17)
+            })
+            .collect();
+        let index = CentroidIndex::new(&houses, &Streets::new(), &Streets::new());
+        let stations = [
+            station("a", Point::new(16.365, 48.193)),
+            station("b", Point::new(16.368, 48.194)),
+            station("c", Point::new(16.375, 48.200)),
+            // at the border of the houses
+            station("d", Point::new(16.36, 48.19)),
+        ];
+
+        let coverage = |index: Option<&CentroidIndex>| {
+            houses_for_stations(
+                &stations,
+                &houses,
+                index,
+                &Method::Relative,
+                &Routing::Naive,
+                &Streets::new(),
+                &[],
+                None,
+            )
+        };
+        let (scanned, indexed) = (coverage(None), coverage(Some(&index)));
+        assert_eq!(scanned.0.len(), indexed.0.len());
+        for (id, scanned) in &scanned.0 {
+            let indexed = &indexed.0[id];
+            let houses = |info: &StationCoverageInfo| {
+                let mut houses: Vec<(*const PopulatedCentroid, u64)> = info
+                    .houses
+                    .iter()
+                    .map(|house| (house.centroid as *const _, house.distance.to_bits()))
+                    .collect();
+                houses.sort();
+                houses
+            };
+            assert!(!scanned.houses.is_empty(), "station {}", id);
+            assert_eq!(houses(scanned), houses(indexed), "station {}", id);
+            assert_eq!(scanned.inhabitants, indexed.inhabitants, "station {}", id);
+        }
+    }
+
+    #[test]
+    fn time_budgets_are_converted_to_walking_distances() {
+        // 4.8 km/h are 80 m per minute
+        assert!((walking_distance(15.0, 4.8) - 1200.0).abs() < 1e-9);
+        assert!((walking_minutes(1200.0, 4.8) - 15.0).abs() < 1e-9);
+        assert!((walking_minutes(walking_distance(7.3, 5.1), 5.1) - 7.3).abs() < 1e-9);
+
+        let config = crate::config_defaults()
+            .set_override("coverage.walking_speed_kmh", 4.8)
+            .unwrap()
+            .build()
+            .unwrap();
+        let location = Point::new(16.37, 48.2);
+        let houses = [PopulatedCentroid::poi(Point::new(16.3732, 48.2021), 10)];
+        let distance = houses[0].haversine_distance(&location);
+        let covered = |minutes: f64| {
+            let stations = [station("a", location)];
+            let stations =
+                with_time_budget(&stations, Some(minutes), false, &Routing::Naive, &config)
+                    .unwrap();
+            let coverage = houses_for_stations(
+                &stations,
+                &houses,
+                None,
+                &Method::Absolute,
+                &Routing::Naive,
+                &Streets::new(),
+                &[],
+                None,
+            );
+            coverage.0["a"].houses.len()
+        };
+        // the house is exactly at the distance walked in the budget
+        assert_eq!(covered(walking_minutes(distance, 4.8)), 1);
+        assert_eq!(covered(walking_minutes(distance - 0.1, 4.8)), 0);
+    }
+
+    #[test]
+    fn exclusive_and_shared_inhabitants_add_up_to_the_station_total() {
+        let houses: Vec<PopulatedCentroid> = (0..12)
+            .flat_map(|x| (0..8).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let location = Point::new(16.37 + x as f64 * 0.0006, 48.2 + y as f64 * 0.0004);
+                PopulatedCentroid::poi(location, 5 + (x + 3 * y) % 11)
+            })
+            .collect();
+        let stations = [
+            station("a", Point::new(16.3715, 48.2012)),
+            station("b", Point::new(16.3745, 48.2015)),
+            station("c", Point::new(16.3790, 48.2020)),
+        ];
+
+        for method in [Method::Absolute, Method::Relative] {
+            let coverage = houses_for_stations(
+                &stations,
+                &houses,
+                None,
+                &method,
+                &Routing::Naive,
+                &Streets::new(),
+                &[],
+                None,
+            );
+            let overlaps = station_overlaps(
+                &coverage,
+                &stations,
+                &method,
+                &Routing::Naive,
+                &Streets::new(),
+            );
+            assert_eq!(overlaps.len(), stations.len());
+            for (id, overlap) in &overlaps {
+                assert_eq!(
+                    overlap.exclusive + overlap.shared,
+                    coverage.0[id.as_str()].inhabitants,
+                    "station {}",
+                    id
+                );
+            }
+            // a and b are close enough to share houses
+            assert!(overlaps.iter().any(|(_, overlap)| overlap.shared > 0));
+        }
+    }
+
+    #[test]
+    fn a_longer_headway_shrinks_the_catchment() {
+        let config = crate::config_defaults()
+            .set_override("coverage.walking_speed_kmh", 4.8)
+            .unwrap()
+            .build()
+            .unwrap();
+        let location = Point::new(16.37, 48.2);
+        // every 50 m up to 1 km north of the station, between the coverage boundaries
+        let houses: Vec<PopulatedCentroid> = (0..20)
+            .map(|i| {
+                let location = Point::new(16.37, 48.2 + (25.0 + i as f64 * 50.0) / 111_195.0);
+                PopulatedCentroid::poi(location, 10)
+            })
+            .collect();
+        let covered = |headway: Option<f64>| {
+            let stations: Vec<Station> = vec![serde_json::from_value(serde_json::json!({
+                "id": "a", "location": location, "headway": headway
+            }))
+            .unwrap()];
+            let stations =
+                with_time_budget(&stations, Some(10.0), true, &Routing::Naive, &config).unwrap();
+            let coverage = houses_for_stations(
+                &stations,
+                &houses,
+                None,
+                &Method::Absolute,
+                &Routing::Naive,
+                &Streets::new(),
+                &[],
+                None,
+            );
+            (stations[0].coverage(), coverage.0["a"].inhabitants)
+        };
+
+        let (frequent_coverage, frequent) = covered(Some(4.0));
+        let (rare_coverage, rare) = covered(Some(12.0));
+        // 8 and 4 minutes of walking remain
+        assert!((frequent_coverage - 640.0).abs() < 1e-9);
+        assert!((rare_coverage - 320.0).abs() < 1e-9);
+        assert_eq!(frequent, 130);
+        assert_eq!(rare, 60);
+        // without a headway the whole budget is walked
+        assert_eq!(covered(None).1, 160);
+        // the wait can take up the whole budget
+        assert_eq!(covered(Some(30.0)), (0.0, 0));
+    }
+
+    fn run_barrier_hotspots(
+        layers: Layers,
+        import_barriers: bool,
+    ) -> Result<ListResponse<BarrierHotspot>, OLPError> {
+        let config = crate::config_defaults()
+            .set_override("routing.import_barriers", import_barriers)
+            .unwrap()
+            .build()
+            .unwrap();
+        let stations = vec![station("a", Point::new(16.37, 48.2))];
+        actix_web::rt::System::new().block_on(barrier_hotspots(
+            web::Json(stations),
+            web::Data::new(RwLock::new(layers)),
+            web::Data::new(config),
+        ))
+    }
+
+    #[test]
+    fn barrier_hotspots_need_loaded_data_and_imported_barriers() {
+        let result = run_barrier_hotspots(Layers::new(), true);
+        assert!(matches!(result, Err(OLPError::NoDataLoaded)));
+
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(
+            vec![PopulatedCentroid::poi(Point::new(16.371, 48.2), 10)],
+            Streets::new(),
+            Streets::new(),
+        ));
+        let result = run_barrier_hotspots(layers, false);
+        assert!(matches!(result, Err(OLPError::UnprocessableInput(_))));
+    }
+
+    #[test]
+    fn coverage_layers_need_loaded_data() {
+        let config = crate::config_defaults().build().unwrap();
+        let result = coverage_layer(
+            &[station("a", Point::new(16.37, 48.2))],
+            &Routing::Naive,
+            OutputCost::default(),
+            &RwLock::new(Layers::new()),
+            &config,
+        );
+        assert!(matches!(result, Err(OLPError::NoDataLoaded)));
+    }
+
+    #[test]
+    fn requests_without_stations_are_rejected() {
+        let layers = web::Data::new(RwLock::new(Layers::new()));
+        let config = web::Data::new(crate::config_defaults().build().unwrap());
+        fn json<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> T {
+            serde_json::from_value(value).unwrap()
+        }
+
+        actix_web::rt::System::new().block_on(async {
+            let result = coverage_info(
+                web::Json(Vec::new()),
+                web::Path::from(Routing::Naive),
+                web::Query(json(serde_json::json!({}))),
+                layers.clone(),
+                config.clone(),
+            )
+            .await;
+            assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+            let result = coverage_areas(
+                web::Json(Vec::new()),
+                web::Path::from(Routing::Naive),
+                web::Query(json(serde_json::json!({}))),
+                layers.clone(),
+                config.clone(),
+            )
+            .await;
+            assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+            let result = area_coverage_info(
+                web::Json(json(serde_json::json!({"stations": [], "areas": []}))),
+                layers.clone(),
+                config.clone(),
+            )
+            .await;
+            assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+            let result = station_snapping(
+                web::Json(json(serde_json::json!({"stations": []}))),
+                layers.clone(),
+                config.clone(),
+            )
+            .await;
+            assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+            let result =
+                barrier_hotspots(web::Json(Vec::new()), layers.clone(), config.clone()).await;
+            assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+        });
+    }
+
+    #[test]
+    fn the_population_grid_sums_up_to_the_inhabitants_in_its_bbox() {
+        let houses = |points: &[(f64, f64, u32)]| {
+            points
+                .iter()
+                .map(|(x, y, pop)| PopulatedCentroid::poi(Point::new(*x, *y), *pop))
+                .collect()
+        };
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(
+            houses(&[
+                (16.3712, 48.2013, 40),
+                (16.3716, 48.2017, 25),
+                (16.3755, 48.2051, 12),
+                // the north east corner of the bbox
+                (16.38, 48.21, 7),
+                // outside of the bbox
+                (16.39, 48.205, 1000),
+            ]),
+            Streets::new(),
+            Streets::new(),
+        ));
+        layers.push(Layer::fixture(
+            houses(&[(16.3712, 48.2013, 3), (16.3791, 48.2002, 18)]),
+            Streets::new(),
+            Streets::new(),
+        ));
+        let layers = web::Data::new(RwLock::new(layers));
+        let grid = |max_cells: i64| {
+            let config = crate::config_defaults()
+                .set_override("population.grid_max_cells", max_cells)
+                .unwrap()
+                .build()
+                .unwrap();
+            let request = PopulationGridRequest {
+                bbox: [16.37, 48.2, 16.38, 48.21],
+                cell_size: 250.0,
+            };
+            actix_web::rt::System::new().block_on(population_grid(
+                web::Json(request),
+                layers.clone(),
+                web::Data::new(config),
+            ))
+        };
+
+        // about 740 m wide and 1110 m high, 3 columns and 5 rows
+        let PopulationGrid(cells) = grid(15).unwrap();
+        let total: u64 = cells.iter().map(|cell| cell.inhabitants).sum();
+        assert_eq!(total, 40 + 25 + 12 + 7 + 3 + 18);
+        assert_eq!(cells.len(), 4);
+        let corner = cells
+            .iter()
+            .find(|cell| cell.geometry.intersects(&Point::new(16.38, 48.21)))
+            .unwrap();
+        assert_eq!(corner.inhabitants, 7);
+        assert!(matches!(grid(14), Err(OLPError::InvalidInput(_))));
+    }
 }
@@ -0,0 +1,89 @@
+//! The preprocessed areas in `data.dir`.
+//!
+//! Layers are calculated from these files on demand, one layer per admin area, so
+//! every listed dataset can be used at the same time without switching between them.
+
+use std::{fs, path::Path, sync::RwLock, time::UNIX_EPOCH};
+
+use actix_web::web;
+use config::Config;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    envelope::ListResponse,
+    error::OLPError,
+    layers::{self, Layers},
+};
+
+#[derive(Serialize)]
+pub struct Dataset {
+    /// id of the admin area the data was preprocessed for
+    name: String,
+    /// size of the street and building data
+    size_bytes: u64,
+    /// seconds since the epoch of the last change to the street and building data
+    modified: Option<u64>,
+    bike_network: bool,
+    /// the loaded layer calculated from this dataset
+    layer: Option<Uuid>,
+}
+
+pub async fn list_datasets(
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<ListResponse<Dataset>, OLPError> {
+    let data_dir = config
+        .get_string("data.dir")
+        .map_err(OLPError::from_error)?;
+    let entries = match fs::read_dir(&data_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("failed to read data directory {}: {}", data_dir, e);
+            return Ok(ListResponse::new(Vec::new()));
+        }
+    };
+    // datasets are either one `.map` file or, from older preprocessing runs,
+    // separate `.streets` and `.buildings` files
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "map" || extension == "streets")
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let loaded_layers = layers.read().map_err(OLPError::from_error)?;
+    let datasets = names
+        .into_iter()
+        .map(|name| {
+            let base = Path::new(&data_dir).join(&name);
+            let files: Vec<fs::Metadata> = ["map", "streets", "buildings"]
+                .iter()
+                .filter_map(|extension| fs::metadata(base.with_extension(extension)).ok())
+                .collect();
+            let layer = name
+                .parse()
+                .ok()
+                .map(layers::calculated_layer_id)
+                .filter(|id| loaded_layers.contains_key(id));
+            Dataset {
+                size_bytes: files.iter().map(|metadata| metadata.len()).sum(),
+                modified: files
+                    .iter()
+                    .filter_map(|metadata| metadata.modified().ok())
+                    .filter_map(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs())
+                    .max(),
+                bike_network: base.with_extension("bike").exists(),
+                layer,
+                name,
+            }
+        })
+        .collect();
+    Ok(ListResponse::new(datasets))
+}
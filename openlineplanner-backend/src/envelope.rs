@@ -0,0 +1,94 @@
+//! Common shape for list responses.
+//!
+//! List endpoints answer with a plain JSON array by default. Clients sending
+//! `X-OLP-Envelope: true` (or all clients, if `api.envelope` is set) instead get
+//! `{ "data": [...], "meta": { "total", "offset", "limit" }, "warnings": [...] }`,
+//! which can be paginated with the `offset` and `limit` query parameters.
+
+use actix_web::{body::BoxBody, web, HttpRequest, HttpResponse, Responder};
+use config::Config;
+use serde::{Deserialize, Serialize};
+
+static ENVELOPE_HEADER: &str = "X-OLP-Envelope";
+
+#[derive(Deserialize, Default)]
+struct Pagination {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Meta {
+    total: usize,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Envelope<T> {
+    data: Vec<T>,
+    meta: Meta,
+    warnings: Vec<String>,
+}
+
+pub struct ListResponse<T> {
+    items: Vec<T>,
+    warnings: Vec<String>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        ListResponse {
+            items,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn with_warnings(mut self, warnings: Vec<String>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+}
+
+fn wants_envelope(req: &HttpRequest) -> bool {
+    match req.headers().get(ENVELOPE_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map(|value| value.eq_ignore_ascii_case("true") || value == "1")
+            .unwrap_or(false),
+        None => req
+            .app_data::<web::Data<Config>>()
+            .and_then(|config| config.get_bool("api.envelope").ok())
+            .unwrap_or(false),
+    }
+}
+
+impl<T: Serialize> Responder for ListResponse<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        if !wants_envelope(req) {
+            return HttpResponse::Ok().json(self.items);
+        }
+        let pagination = web::Query::<Pagination>::from_query(req.query_string())
+            .map(|query| query.into_inner())
+            .unwrap_or_default();
+        let total = self.items.len();
+        let offset = pagination.offset.unwrap_or(0);
+        let data = self
+            .items
+            .into_iter()
+            .skip(offset)
+            .take(pagination.limit.unwrap_or(usize::MAX))
+            .collect();
+        HttpResponse::Ok().json(Envelope {
+            data,
+            meta: Meta {
+                total,
+                offset,
+                limit: pagination.limit,
+            },
+            warnings: self.warnings,
+        })
+    }
+}
@@ -1,17 +1,74 @@
 use std::{error::Error, fmt::Display};
 
-use actix_web::{body::BoxBody, HttpResponse, Responder, ResponseError};
+use actix_web::{body::BoxBody, HttpRequest, HttpResponse, Responder, ResponseError};
+use serde::Serialize;
 
 #[derive(Debug)]
 pub enum OLPError {
     GeometryError,
+    InvalidInput(String),
+    UnprocessableInput(String),
+    UpstreamUnavailable(String),
+    NoDataLoaded,
+    NotFound(String),
+    Unauthorized(String),
+    PayloadTooLarge(String),
     GenericError(String),
 }
 
+/// Machine readable kind of an error, sent as `code` next to the `message` of error
+/// responses so clients don't have to parse the message
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    GeometryError,
+    InvalidInput,
+    UnprocessableInput,
+    UpstreamUnavailable,
+    NoBaseData,
+    NotFound,
+    Unauthorized,
+    PayloadTooLarge,
+    InternalError,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: ErrorCode,
+    message: String,
+}
+
 impl OLPError {
     pub fn from_error<T: Display>(error: T) -> Self {
         Self::GenericError(error.to_string())
     }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            OLPError::GeometryError => ErrorCode::GeometryError,
+            OLPError::InvalidInput(_) => ErrorCode::InvalidInput,
+            OLPError::UnprocessableInput(_) => ErrorCode::UnprocessableInput,
+            OLPError::UpstreamUnavailable(_) => ErrorCode::UpstreamUnavailable,
+            OLPError::NoDataLoaded => ErrorCode::NoBaseData,
+            OLPError::NotFound(_) => ErrorCode::NotFound,
+            OLPError::Unauthorized(_) => ErrorCode::Unauthorized,
+            OLPError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            OLPError::GenericError(_) => ErrorCode::InternalError,
+        }
+    }
+
+    fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        }
+    }
+}
+
+/// Passed to the extractor configs, so malformed bodies, queries and paths are
+/// answered with the `invalid_input` code as well
+pub fn invalid_input_handler<E: Display>(error: E, _req: &HttpRequest) -> actix_web::Error {
+    OLPError::InvalidInput(error.to_string()).into()
 }
 
 impl Error for OLPError {}
@@ -20,6 +77,16 @@ impl Display for OLPError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             OLPError::GeometryError => write!(f, "an error occurred when converting geometries"),
+            OLPError::InvalidInput(err) => write!(f, "invalid input: {}", err),
+            OLPError::UnprocessableInput(err) => write!(f, "unprocessable input: {}", err),
+            OLPError::UpstreamUnavailable(err) => write!(f, "upstream unavailable: {}", err),
+            OLPError::NoDataLoaded => write!(
+                f,
+                "no base data is loaded, calculate a layer for the area first"
+            ),
+            OLPError::NotFound(err) => write!(f, "not found: {}", err),
+            OLPError::Unauthorized(err) => write!(f, "unauthorized: {}", err),
+            OLPError::PayloadTooLarge(err) => write!(f, "response too large: {}", err),
             OLPError::GenericError(err) => write!(f, "{}", err.to_string()),
         }
     }
@@ -35,17 +102,78 @@ impl Responder for OLPError {
             req.path(),
             self
         );
-        HttpResponse::InternalServerError().body(self.to_string())
+        HttpResponse::build(self.status_code()).json(self.body())
     }
 }
 
 impl ResponseError for OLPError {
     fn status_code(&self) -> reqwest::StatusCode {
-        reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            OLPError::InvalidInput(_) => reqwest::StatusCode::BAD_REQUEST,
+            OLPError::UnprocessableInput(_) => reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+            OLPError::UpstreamUnavailable(_) | OLPError::NoDataLoaded => {
+                reqwest::StatusCode::SERVICE_UNAVAILABLE
+            }
+            OLPError::NotFound(_) => reqwest::StatusCode::NOT_FOUND,
+            OLPError::Unauthorized(_) => reqwest::StatusCode::UNAUTHORIZED,
+            OLPError::PayloadTooLarge(_) => reqwest::StatusCode::PAYLOAD_TOO_LARGE,
+            OLPError::GeometryError | OLPError::GenericError(_) => {
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
         log::error!("failed to process query: {}", self);
-        HttpResponse::new(self.status_code()).set_body(BoxBody::new(self.to_string()))
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn every_error_has_a_status_and_a_code() {
+        let text = || "x".to_owned();
+        let errors = [
+            (OLPError::GeometryError, 500, "geometry_error"),
+            (OLPError::InvalidInput(text()), 400, "invalid_input"),
+            (
+                OLPError::UnprocessableInput(text()),
+                422,
+                "unprocessable_input",
+            ),
+            (
+                OLPError::UpstreamUnavailable(text()),
+                503,
+                "upstream_unavailable",
+            ),
+            (OLPError::NoDataLoaded, 503, "no_base_data"),
+            (OLPError::NotFound(text()), 404, "not_found"),
+            (OLPError::Unauthorized(text()), 401, "unauthorized"),
+            (OLPError::PayloadTooLarge(text()), 413, "payload_too_large"),
+            (OLPError::GenericError(text()), 500, "internal_error"),
+        ];
+        for (error, status, code) in errors {
+            assert_eq!(
+                error.status_code(),
+                StatusCode::from_u16(status).unwrap(),
+                "{}",
+                error
+            );
+            assert_eq!(serde_json::to_value(error.code()).unwrap(), code);
+
+            let response = error.error_response();
+            assert_eq!(response.status(), error.status_code());
+            let body = actix_web::rt::System::new()
+                .block_on(actix_web::body::to_bytes(response.into_body()))
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], code);
+            assert_eq!(body["message"], error.to_string());
+        }
     }
 }
@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::RwLock;
+use std::task::{Context, Poll};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    http::header,
+    web::{self, Bytes},
+    HttpResponse,
+};
+use config::Config;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::coverage::{coverage_layer, CoverageDefaults, OutputCost, Routing};
+use crate::error::OLPError;
+use crate::layers::Layers;
+use crate::line::Lines;
+use crate::station::Station;
+use crate::validation;
+
+#[derive(Serialize, Deserialize)]
+pub struct BundleRequest {
+    stations: Vec<Station>,
+    routing: Option<Routing>,
+    output_cost: Option<OutputCost>,
+}
+
+#[derive(Serialize)]
+struct BundleManifest {
+    version: &'static str,
+    data_hash: String,
+    files: Vec<&'static str>,
+}
+
+static BUNDLE_README: &str = "This bundle was exported from OpenLinePlanner.
+
+coverage.geojson   covered houses and their closest station
+stations.json      the stations the coverage was calculated for
+parameters.json    the parameters of the calculation
+manifest.json      backend version and a hash of the data that was loaded
+
+Loading the same data (see data_hash) and sending parameters.json to
+/export/bundle.zip reproduces this result.
+";
+
+/// Bundles the coverage of the given stations with everything needed to reproduce it into a zip
+pub async fn export_bundle(
+    request: web::Json<BundleRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, OLPError> {
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let coverage = coverage_layer(
+        &request.stations,
+        request.routing.as_ref().unwrap_or(&defaults.routing),
+        request.output_cost.unwrap_or_default(),
+        &layers,
+        &config,
+    )?;
+    let data_hash = layers
+        .read()
+        .map_err(OLPError::from_error)?
+        .content_hash()?;
+    let manifest = BundleManifest {
+        version: env!("CARGO_PKG_VERSION"),
+        data_hash: format!("{:016x}", data_hash),
+        files: vec![
+            "coverage.geojson",
+            "stations.json",
+            "parameters.json",
+            "README.txt",
+            "manifest.json",
+        ],
+    };
+
+    let files = [
+        ("coverage.geojson", coverage.to_geojson_string()?),
+        (
+            "stations.json",
+            serde_json::to_string_pretty(&request.stations).map_err(OLPError::from_error)?,
+        ),
+        (
+            "parameters.json",
+            serde_json::to_string_pretty(&*request).map_err(OLPError::from_error)?,
+        ),
+        ("README.txt", BUNDLE_README.to_owned()),
+        (
+            "manifest.json",
+            serde_json::to_string_pretty(&manifest).map_err(OLPError::from_error)?,
+        ),
+    ];
+
+    zip_response("bundle.zip", files)
+}
+
+/// Exports the street graph of the loaded data as `nodes.csv` (id, lon, lat) and
+/// `edges.csv` (from, to, length in meters) for use in other routing tools
+pub async fn export_graph(layers: web::Data<RwLock<Layers>>) -> Result<HttpResponse, OLPError> {
+    let layer = layers.read().map_err(OLPError::from_error)?.all_merged();
+    let streets = layer.get_streets();
+
+    let mut nodes = String::from("id,lon,lat\n");
+    for (id, point) in &streets.nodes {
+        writeln!(nodes, "{},{},{}", id.0, point.x(), point.y()).map_err(OLPError::from_error)?;
+    }
+    let mut edges = String::from("from,to,length\n");
+    for (from, to, length) in streets.streetgraph.all_edges() {
+        writeln!(edges, "{},{},{:.2}", from.0, to.0, length).map_err(OLPError::from_error)?;
+    }
+
+    zip_response("graph.zip", [("nodes.csv", nodes), ("edges.csv", edges)])
+}
+
+/// GTFS route_type for the line types of the frontend, unknown types are exported as bus
+fn gtfs_route_type(mode: Option<&str>) -> u32 {
+    match mode {
+        Some("tram") => 0,
+        Some("subway") | Some("metro") => 1,
+        Some("rail") | Some("train") => 2,
+        Some("ferry") => 4,
+        _ => 3,
+    }
+}
+
+/// Exports a stored line as a minimal GTFS feed: its active stations as stops.txt in
+/// the order they are served, plus a single route and trip. There is no schedule,
+/// agency or calendar, so operational tools have to complete the feed.
+pub async fn export_line_gtfs(
+    id: web::Path<Uuid>,
+    lines: web::Data<RwLock<Lines>>,
+) -> Result<HttpResponse, OLPError> {
+    let lines = lines.read().map_err(OLPError::from_error)?;
+    let line = lines.get(&id)?;
+    let stations: Vec<&Station> = line
+        .get_stations()
+        .iter()
+        .filter(|station| station.is_active())
+        .collect();
+    validation::validate_points(stations.iter().map(|station| &station.location), None)?;
+
+    let mut stops = String::from("stop_id,stop_name,stop_lat,stop_lon\n");
+    for station in stations {
+        let id = csv_field(&station.id);
+        writeln!(
+            stops,
+            "{},{},{},{}",
+            id,
+            id,
+            station.location.y(),
+            station.location.x()
+        )
+        .map_err(OLPError::from_error)?;
+    }
+    let route_id = line.get_id().to_string();
+    let routes = format!(
+        "route_id,route_short_name,route_long_name,route_type\n{},{},,{}\n",
+        route_id,
+        csv_field(line.get_name()),
+        gtfs_route_type(line.get_mode())
+    );
+    let trips = format!(
+        "route_id,service_id,trip_id\n{},planned,{}-1\n",
+        route_id, route_id
+    );
+
+    zip_response(
+        "gtfs.zip",
+        [
+            ("stops.txt", stops),
+            ("routes.txt", routes),
+            ("trips.txt", trips),
+        ],
+    )
+}
+
+/// Quotes fields containing separators, quotes or line breaks
+pub(crate) fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn zip_response<const N: usize>(
+    name: &str,
+    files: [(&'static str, String); N],
+) -> Result<HttpResponse, OLPError> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(header::ContentDisposition::attachment(name))
+        .body(ZipStream::new(files)))
+}
+
+/// Zip archive that is written one file at a time while the response body is sent,
+/// so the archive isn't held in memory next to the files
+struct ZipStream {
+    files: std::vec::IntoIter<(&'static str, String)>,
+    zip: Option<ZipWriter<ChunkWriter>>,
+    output: ChunkWriter,
+}
+
+impl ZipStream {
+    fn new<const N: usize>(files: [(&'static str, String); N]) -> Self {
+        let output = ChunkWriter::default();
+        ZipStream {
+            files: Vec::from(files).into_iter(),
+            zip: Some(ZipWriter::new(output.clone())),
+            output,
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        while let Some(zip) = &mut self.zip {
+            match self.files.next() {
+                Some((name, content)) => {
+                    zip.start_file(name, FileOptions::default())?;
+                    zip.write_all(content.as_bytes())?;
+                }
+                None => {
+                    zip.finish()?;
+                    self.zip = None;
+                    return Ok(Some(self.output.take(true)));
+                }
+            }
+            let chunk = self.output.take(false);
+            if !chunk.is_empty() {
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl MessageBody for ZipStream {
+    type Error = io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Poll::Ready(self.get_mut().next_chunk().transpose())
+    }
+}
+
+/// Output of a `ZipWriter` that hands out the bytes it won't change any more. The
+/// writer only seeks back into the local header of the file it is writing, to fill in
+/// its checksum and size, and never before an earlier seek, so all bytes before the
+/// last seek back are final.
+#[derive(Clone, Default)]
+struct ChunkWriter(Rc<RefCell<Chunks>>);
+
+#[derive(Default)]
+struct Chunks {
+    /// bytes before `buffer` that were already handed out
+    taken: u64,
+    buffer: Vec<u8>,
+    position: u64,
+    /// end of the final bytes
+    final_end: u64,
+}
+
+impl ChunkWriter {
+    /// The final bytes that weren't handed out yet, or all remaining ones with `all`
+    fn take(&self, all: bool) -> Bytes {
+        let mut chunks = self.0.borrow_mut();
+        let end = if all {
+            chunks.buffer.len()
+        } else {
+            (chunks.final_end - chunks.taken) as usize
+        };
+        chunks.taken += end as u64;
+        Bytes::from(chunks.buffer.drain(..end).collect::<Vec<u8>>())
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut chunks = self.0.borrow_mut();
+        let start = (chunks.position - chunks.taken) as usize;
+        let end = start + data.len();
+        if chunks.buffer.len() < end {
+            chunks.buffer.resize(end, 0);
+        }
+        chunks.buffer[start..end].copy_from_slice(data);
+        chunks.position += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ChunkWriter {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let mut chunks = self.0.borrow_mut();
+        let end = chunks.taken + chunks.buffer.len() as u64;
+        let position = match to {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => chunks.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) if position >= chunks.final_end && position <= end => {
+                if position < chunks.position {
+                    chunks.final_end = position;
+                }
+                chunks.position = position;
+                Ok(position)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the bytes that were already sent",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[test]
+    fn streamed_zip_holds_all_files() {
+        let coverage = "x".repeat(100_000);
+        let mut stream = ZipStream::new([
+            ("coverage.geojson", coverage.clone()),
+            ("stations.json", "[]".to_owned()),
+            ("README.txt", BUNDLE_README.to_owned()),
+        ]);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next_chunk().unwrap() {
+            chunks.push(chunk);
+        }
+        assert!(chunks.len() > 1, "the archive is sent in one chunk");
+
+        let mut archive = ZipArchive::new(Cursor::new(chunks.concat())).unwrap();
+        assert_eq!(archive.len(), 3);
+        for (name, expected) in [
+            ("coverage.geojson", coverage.as_str()),
+            ("stations.json", "[]"),
+            ("README.txt", BUNDLE_README),
+        ] {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, expected);
+        }
+    }
+
+    #[test]
+    fn bundles_without_stations_are_rejected() {
+        let request = serde_json::from_value(serde_json::json!({"stations": []})).unwrap();
+        let result = actix_web::rt::System::new().block_on(export_bundle(
+            web::Json(request),
+            web::Data::new(RwLock::new(Layers::new())),
+            web::Data::new(crate::config_defaults().build().unwrap()),
+        ));
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+    }
+}
@@ -1,12 +1,18 @@
 use geo::{
-    CoordFloat, HaversineDistance, HaversineLength, Line, LineInterpolatePoint, LineString, Point,
+    Area, Centroid, Closest, ClosestPoint, Contains, Coord, CoordFloat, Geometry,
+    HaversineDistance, HaversineLength, InteriorPoint, Intersects, KNearestConcaveHull, Line,
+    LineInterpolatePoint, LineString, MapCoords, MultiPoint, MultiPolygon, Point,
 };
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 use datatypes::Streets;
 
-use crate::layers::PopulatedCentroid;
+use crate::error::OLPError;
+use crate::layers::{PopulatedCentroid, StreetIndex};
+use crate::validation;
 use osmpbfreader::NodeId;
-use petgraph::algo::dijkstra;
+use petgraph::algo::{astar, dijkstra};
+use rayon::prelude::*;
 
 pub trait DensifyHaversine<F: CoordFloat> {
     type Output;
@@ -55,6 +61,223 @@ where
     }
 }
 
+/// The point a building is counted at: its centroid, which is weighted by area for
+/// multi polygons. Shapes like an L or U can have their centroid outside of the
+/// building, those use a point inside of their largest polygon instead.
+pub fn representative_point(geometry: &Geometry) -> Option<Point> {
+    let centroid = geometry.centroid()?;
+    let outside = match geometry {
+        Geometry::Polygon(polygon) => !polygon.contains(&centroid),
+        Geometry::MultiPolygon(multi_polygon) => !multi_polygon.contains(&centroid),
+        _ => false,
+    };
+    if outside {
+        return geometry.interior_point().or(Some(centroid));
+    }
+    Some(centroid)
+}
+
+/// Filled outline of `points` for display, following their shape instead of spanning
+/// the gaps like a convex hull. `concavity` is the number of nearest points the outline
+/// picks its next corner from, larger values give rounder shapes, see
+/// `geo::KNearestConcaveHull`. `geo::ConcaveHull` is not used as it keeps the convex hull
+/// for shapes like a U. Fewer than three distinct points are returned as they are and
+/// points on one line as the line between the outermost ones.
+pub fn service_area(points: &[Point], concavity: u32) -> Geometry {
+    let mut distinct: Vec<Point> = Vec::with_capacity(points.len());
+    for point in points {
+        if !distinct.contains(point) {
+            distinct.push(*point);
+        }
+    }
+    if distinct.len() < 3 {
+        return Geometry::MultiPoint(MultiPoint::new(distinct));
+    }
+    let hull = MultiPoint::new(distinct.clone()).k_nearest_concave_hull(concavity);
+    if hull.unsigned_area() > 0.0 {
+        return Geometry::Polygon(hull);
+    }
+    // collinear points are ordered along the line, so the extremes are its ends
+    let by_position =
+        |a: &&Point, b: &&Point| a.x().total_cmp(&b.x()).then(a.y().total_cmp(&b.y()));
+    let start = distinct.iter().min_by(by_position).unwrap();
+    let end = distinct.iter().max_by(by_position).unwrap();
+    Geometry::LineString(LineString::from(vec![*start, *end]))
+}
+
+/// Converts a GeoJSON polygon or multipolygon into a `MultiPolygon`
+pub fn multi_polygon_from_geojson(geometry: geojson::Geometry) -> Result<MultiPolygon, OLPError> {
+    let multi_polygon = match geometry.value.try_into() {
+        Ok(Geometry::Polygon(polygon)) => MultiPolygon::from(polygon),
+        Ok(Geometry::MultiPolygon(multi_polygon)) => multi_polygon,
+        _ => {
+            return Err(OLPError::InvalidInput(
+                "expected a Polygon or MultiPolygon geometry".to_owned(),
+            ))
+        }
+    };
+    validation::validate_geometry(&multi_polygon)?;
+    Ok(multi_polygon)
+}
+
+/// Projects `point` to Web Mercator tile coordinates at `zoom`, the integer part
+/// is the tile and the fraction the position within it
+pub fn web_mercator_tile_position(point: &Point, zoom: u32) -> (f64, f64) {
+    let tiles = 2f64.powi(zoom as i32);
+    let latitude = point.y().to_radians();
+    let x = (point.x() + 180.0) / 360.0 * tiles;
+    let y =
+        (1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / std::f64::consts::PI) / 2.0 * tiles;
+    (x, y)
+}
+
+/// Distance in meters from `point` to the closest point of `geometry`. Searching the
+/// closest point on raw degrees would favour north-south lines away from the equator,
+/// so longitudes are scaled to the length of a degree at the latitude of `point` first.
+pub fn haversine_distance_to<G>(geometry: &G, point: &Point) -> Option<f64>
+where
+    G: MapCoords<f64, f64>,
+    G::Output: ClosestPoint<f64>,
+{
+    let scale = point.y().to_radians().cos().max(f64::EPSILON);
+    let scaled = geometry.map_coords(|coord| Coord {
+        x: coord.x * scale,
+        y: coord.y,
+    });
+    match scaled.closest_point(&Point::new(point.x() * scale, point.y())) {
+        Closest::Intersection(closest) | Closest::SinglePoint(closest) => {
+            Some(Point::new(closest.x() / scale, closest.y()).haversine_distance(point))
+        }
+        Closest::Indeterminate => None,
+    }
+}
+
+/// Whether walking straight from `from` to `to` would cross one of the barriers
+pub fn crosses_barrier(from: &Point, to: &Point, barriers: &[LineString]) -> bool {
+    let line = Line::new(*from, *to);
+    barriers.iter().any(|barrier| barrier.intersects(&line))
+}
+
+/// Like `snap_to_street` but only considers nodes that can be reached without crossing a
+/// barrier. `index` has to be built from `streets`, it limits the nodes checked against the
+/// barriers to those around `origin`.
+pub fn snap_to_street_avoiding(
+    streets: &Streets,
+    index: &StreetIndex,
+    origin: &Point,
+    barriers: &[LineString],
+) -> Option<(NodeId, f64)> {
+    match snap_to_street(streets, origin) {
+        Some((id, _)) if crosses_barrier(origin, &streets.nodes[&id], barriers) => {
+            index.nearest_node(origin, |node| !crosses_barrier(origin, node, barriers))
+        }
+        snapped => snapped,
+    }
+}
+
+/// Finds the street graph node closest to `origin` and its distance in meters
+pub fn snap_to_street(streets: &Streets, origin: &Point) -> Option<(NodeId, f64)> {
+    nearest_node(streets.nodes.iter(), origin)
+}
+
+/// Of nodes at the same distance the one with the lowest id is taken, so the result
+/// doesn't depend on the order of the nodes in the map
+fn nearest_node<'a>(
+    nodes: impl Iterator<Item = (&'a NodeId, &'a Point)>,
+    origin: &Point,
+) -> Option<(NodeId, f64)> {
+    nodes
+        .map(|(id, node)| (*id, node.haversine_distance(origin)))
+        .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))
+}
+
+/// Shortest walking distance between two street nodes, `None` if they aren't connected.
+/// A* with the direct distance to `target` as estimate, which never overestimates as
+/// every street segment is at least as long as the direct distance between its ends.
+/// Searches from one node to many should use a single Dijkstra run instead.
+pub fn shortest_distance(streets: &Streets, start: NodeId, target: NodeId) -> Option<f64> {
+    let target_location = streets.nodes.get(&target)?;
+    astar(
+        &streets.streetgraph,
+        start,
+        |node| node == target,
+        |e| *e.2,
+        |node| {
+            streets
+                .nodes
+                .get(&node)
+                .map_or(0.0, |location| location.haversine_distance(target_location))
+        },
+    )
+    .map(|(distance, _)| distance)
+}
+
+/// Pairwise walking distances between `points` over the street network, `None` for
+/// pairs without a connection. The diagonal is zero. Turn restrictions are ignored,
+/// as they would make the distances differ by direction.
+pub fn network_distance_matrix(streets: &Streets, points: &[Point]) -> Vec<Vec<Option<f64>>> {
+    let snapped: Vec<Option<(NodeId, f64)>> = points
+        .iter()
+        .map(|point| snap_to_street(streets, point))
+        .collect();
+    let distance = |origin: usize, target: usize| -> Option<f64> {
+        let ((origin_node, origin_distance), (target_node, target_distance)) =
+            (snapped[origin]?, snapped[target]?);
+        Some(
+            origin_distance
+                + shortest_distance(streets, origin_node, target_node)?
+                + target_distance,
+        )
+    };
+    // streets can be walked in both directions, so only the upper triangle is searched
+    let upper: Vec<Vec<Option<f64>>> = (0..points.len())
+        .into_par_iter()
+        .map(|i| (i + 1..points.len()).map(|j| distance(i, j)).collect())
+        .collect();
+    (0..points.len())
+        .map(|i| {
+            (0..points.len())
+                .map(|j| match i.cmp(&j) {
+                    Ordering::Equal => Some(0.0),
+                    Ordering::Less => upper[i][j - i - 1],
+                    Ordering::Greater => upper[j][i - j - 1],
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Street segments whose both ends are within `max_distance` meters walking of
+/// `origin`, with the walking distance to the farther end
+pub fn reachable_edges(
+    streets: &Streets,
+    origin: &Point,
+    max_distance: f64,
+) -> Vec<(NodeId, NodeId, f64)> {
+    let Some((origin_node, snap_distance)) = snap_to_street(streets, origin) else {
+        return Vec::new();
+    };
+    if snap_distance > max_distance {
+        return Vec::new();
+    }
+    let distances = bounded_dijkstra(streets, origin_node, max_distance - snap_distance);
+    distances
+        .iter()
+        .flat_map(|(node, distance)| {
+            let distances = &distances;
+            streets
+                .streetgraph
+                .neighbors(*node)
+                // every edge is only returned once, from its smaller node
+                .filter(move |other| node < other)
+                .filter_map(move |other| {
+                    let other_distance = distances.get(&other)?;
+                    Some((*node, other, snap_distance + distance.max(*other_distance)))
+                })
+        })
+        .collect()
+}
+
 pub trait DistanceCalculator {
     type FixedPoint: DistanceFromPoint + Sync;
     fn distance(&self, a: &PopulatedCentroid, b: &Point) -> f64;
@@ -96,21 +319,63 @@ impl HaversineDistanceCalculator {
     }
 }
 
+/// Straight line distance, except for houses behind a barrier like a river or a
+/// railway, which can't be reached in a straight line at all
+pub struct BarrierAwareDistanceCalculator<'a> {
+    barriers: &'a [LineString],
+}
+
+pub struct BarrierAwareFixedPoint<'a> {
+    point: Point,
+    barriers: &'a [LineString],
+}
+
+impl DistanceFromPoint for BarrierAwareFixedPoint<'_> {
+    fn distance(&self, other: &PopulatedCentroid) -> f64 {
+        if crosses_barrier(&self.point, &other.geometry, self.barriers) {
+            return f64::MAX;
+        }
+        self.point.haversine_distance(&other.geometry)
+    }
+}
+
+impl<'a> DistanceCalculator for BarrierAwareDistanceCalculator<'a> {
+    type FixedPoint = BarrierAwareFixedPoint<'a>;
+
+    fn distance(&self, a: &PopulatedCentroid, b: &Point) -> f64 {
+        self.fix_point(b).distance(a)
+    }
+    fn fix_point(&self, point: &Point) -> Self::FixedPoint {
+        BarrierAwareFixedPoint {
+            point: *point,
+            barriers: self.barriers,
+        }
+    }
+}
+
+impl<'a> BarrierAwareDistanceCalculator<'a> {
+    pub fn new(barriers: &'a [LineString]) -> Self {
+        Self { barriers }
+    }
+}
+
 pub struct OsmDistanceCalculator<'a> {
     streets: &'a Streets,
 }
 
 pub struct OsmFixedPoint {
+    origin: Point,
     diff_distance: f64,
     distance_matrix: HashMap<NodeId, f64>,
 }
 
 impl DistanceFromPoint for OsmFixedPoint {
     fn distance(&self, other: &PopulatedCentroid) -> f64 {
-        self.distance_matrix
-            .get(&other.street_graph_id.unwrap())
-            .unwrap_or(&f64::MAX)
-            + self.diff_distance
+        // centroids too far from any street are reached in a straight line
+        let Some(id) = other.street_graph_id else {
+            return self.origin.haversine_distance(&other.geometry);
+        };
+        self.distance_matrix.get(&id).unwrap_or(&f64::MAX) + self.diff_distance
     }
 }
 
@@ -118,17 +383,17 @@ impl<'a> DistanceCalculator for OsmDistanceCalculator<'a> {
     type FixedPoint = OsmFixedPoint;
 
     fn distance(&self, a: &PopulatedCentroid, b: &Point) -> f64 {
+        let Some(id) = a.street_graph_id else {
+            return a.haversine_distance(b);
+        };
         let (origin_node, diff_distance) = self.find_closest_node_to_point(b);
-        let osm_distance_matrix = dijkstra(&self.streets.streetgraph, origin_node, None, |e| *e.2);
-        osm_distance_matrix
-            .get(&a.street_graph_id.unwrap())
-            .unwrap_or(&f64::MAX)
-            + diff_distance
+        shortest_distance(self.streets, origin_node, id).unwrap_or(f64::MAX) + diff_distance
     }
     fn fix_point(&self, point: &Point) -> Self::FixedPoint {
         let (origin_node, diff_distance) = self.find_closest_node_to_point(point);
         let distance_matrix = dijkstra(&self.streets.streetgraph, origin_node, None, |e| *e.2);
         OsmFixedPoint {
+            origin: *point,
             diff_distance,
             distance_matrix,
         }
@@ -141,11 +406,474 @@ impl<'a> OsmDistanceCalculator<'a> {
     }
 
     fn find_closest_node_to_point(&self, origin: &Point) -> (NodeId, f64) {
-        self.streets
-            .nodes
+        snap_to_street(self.streets, origin).unwrap()
+    }
+}
+
+/// Walking distance along the street network, including the way from the station
+/// and from each house to the street. The search stops at `max_distance`, houses
+/// further away are unreachable.
+pub struct NetworkDistanceCalculator<'a> {
+    streets: &'a Streets,
+    max_distance: f64,
+    /// node of `streets` a house is snapped to
+    graph_id: fn(&PopulatedCentroid) -> Option<NodeId>,
+}
+
+pub struct NetworkFixedPoint<'a> {
+    streets: &'a Streets,
+    origin: Point,
+    diff_distance: f64,
+    distance_matrix: HashMap<NodeId, f64>,
+    graph_id: fn(&PopulatedCentroid) -> Option<NodeId>,
+}
+
+impl DistanceFromPoint for NetworkFixedPoint<'_> {
+    fn distance(&self, other: &PopulatedCentroid) -> f64 {
+        // centroids too far from any street are reached in a straight line
+        let Some(id) = (self.graph_id)(other) else {
+            return self.origin.haversine_distance(&other.geometry);
+        };
+        let Some((distance, node)) = self
+            .distance_matrix
+            .get(&id)
+            .zip(self.streets.nodes.get(&id))
+        else {
+            return f64::MAX;
+        };
+        self.diff_distance + distance + other.haversine_distance(node)
+    }
+}
+
+impl<'a> DistanceCalculator for NetworkDistanceCalculator<'a> {
+    type FixedPoint = NetworkFixedPoint<'a>;
+
+    fn distance(&self, a: &PopulatedCentroid, b: &Point) -> f64 {
+        self.fix_point(b).distance(a)
+    }
+    fn fix_point(&self, point: &Point) -> Self::FixedPoint {
+        let Some((origin_node, diff_distance)) = snap_to_street(self.streets, point) else {
+            return NetworkFixedPoint {
+                streets: self.streets,
+                origin: *point,
+                diff_distance: f64::MAX,
+                distance_matrix: HashMap::new(),
+                graph_id: self.graph_id,
+            };
+        };
+        NetworkFixedPoint {
+            streets: self.streets,
+            origin: *point,
+            diff_distance,
+            distance_matrix: bounded_dijkstra(
+                self.streets,
+                origin_node,
+                self.max_distance - diff_distance,
+            ),
+            graph_id: self.graph_id,
+        }
+    }
+}
+
+impl<'a> NetworkDistanceCalculator<'a> {
+    pub fn new(streets: &'a Streets, max_distance: f64) -> Self {
+        Self {
+            streets,
+            max_distance,
+            graph_id: |centroid| centroid.street_graph_id,
+        }
+    }
+
+    /// Cycling distance, `streets` has to be the bike network of a layer
+    pub fn cycling(streets: &'a Streets, max_distance: f64) -> Self {
+        Self {
+            streets,
+            max_distance,
+            graph_id: |centroid| centroid.bike_graph_id,
+        }
+    }
+}
+
+struct SearchState {
+    cost: f64,
+    node: NodeId,
+}
+
+impl PartialEq for SearchState {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SearchState {}
+
+impl Ord for SearchState {
+    // reversed, so the BinaryHeap pops the closest node first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for SearchState {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Dijkstra from `start` that doesn't expand nodes further away than `max_distance`.
+/// Networks with turn restrictions are searched without the prohibited turns.
+fn bounded_dijkstra(streets: &Streets, start: NodeId, max_distance: f64) -> HashMap<NodeId, f64> {
+    if !streets.restrictions.is_empty() {
+        return streets.restrictions.distances(streets, start, max_distance);
+    }
+    let mut distances: HashMap<NodeId, f64> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    queue.push(SearchState {
+        cost: 0.0,
+        node: start,
+    });
+    while let Some(SearchState { cost, node }) = queue.pop() {
+        if distances.contains_key(&node) {
+            continue;
+        }
+        distances.insert(node, cost);
+        for (_, next, length) in streets.streetgraph.edges(node) {
+            let next_cost = cost + length;
+            if next_cost <= max_distance && !distances.contains_key(&next) {
+                queue.push(SearchState {
+                    cost: next_cost,
+                    node: next,
+                });
+            }
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatypes::Turn;
+
+    /// 4x4 grid of streets about 100 m apart with detours of different lengths, and a
+    /// node without any street
+    fn grid() -> Streets {
+        let mut streets = Streets::new();
+        let node = |row: i64, column: i64| NodeId(row * 4 + column);
+        for row in 0..4 {
+            for column in 0..4 {
+                let location =
+                    Point::new(16.37 + column as f64 * 0.00135, 48.2 + row as f64 * 0.0009);
+                streets.nodes.insert(node(row, column), location);
+            }
+        }
+        streets.nodes.insert(NodeId(100), Point::new(16.38, 48.21));
+        let mut add = |a: NodeId, b: NodeId, detour: f64| {
+            let length = streets.nodes[&a].haversine_distance(&streets.nodes[&b]) * detour;
+            streets.streetgraph.add_edge(a, b, length);
+        };
+        for row in 0..4 {
+            for column in 0..4 {
+                let detour = 1.0 + ((row * 7 + column * 3) % 5) as f64 / 4.0;
+                if column < 3 {
+                    add(node(row, column), node(row, column + 1), detour);
+                }
+                if row < 3 {
+                    add(node(row, column), node(row + 1, column), 3.0 - detour);
+                }
+            }
+        }
+        add(node(0, 0), node(3, 3), 1.05);
+        streets
+    }
+
+    #[test]
+    fn astar_and_dijkstra_find_the_same_distances() {
+        let streets = grid();
+        let start = NodeId(1);
+        let distances = dijkstra(&streets.streetgraph, start, None, |e| *e.2);
+        let bounded = bounded_dijkstra(&streets, start, f64::MAX);
+        for target in streets.nodes.keys() {
+            let astar_distance = shortest_distance(&streets, start, *target);
+            assert_eq!(astar_distance.is_some(), distances.contains_key(target));
+            if let Some(distance) = astar_distance {
+                assert!((distance - distances[target]).abs() < 1e-9, "{:?}", target);
+                assert!((distance - bounded[target]).abs() < 1e-9, "{:?}", target);
+            }
+        }
+        assert_eq!(shortest_distance(&streets, start, NodeId(100)), None);
+        assert_eq!(distances.len(), 16);
+    }
+
+    #[test]
+    fn prohibited_turns_force_a_detour() {
+        // a straight street from the station at 1 over 2 to the house at 3, and a
+        // detour over 4 north of it
+        let mut streets = Streets::new();
+        let locations = [
+            (1, 16.370, 48.2),
+            (2, 16.371, 48.2),
+            (3, 16.372, 48.2),
+            (4, 16.371, 48.201),
+        ];
+        for (id, lon, lat) in locations {
+            streets.nodes.insert(NodeId(id), Point::new(lon, lat));
+        }
+        for (a, b) in [(1, 2), (2, 3), (1, 4), (4, 3)] {
+            let length = streets.nodes[&NodeId(a)].haversine_distance(&streets.nodes[&NodeId(b)]);
+            streets.streetgraph.add_edge(NodeId(a), NodeId(b), length);
+        }
+        let mut house = PopulatedCentroid::poi(streets.nodes[&NodeId(3)], 10);
+        house.bike_graph_id = Some(NodeId(3));
+        let station = streets.nodes[&NodeId(1)];
+        let distance = |streets: &Streets| {
+            NetworkDistanceCalculator::cycling(streets, 1000.0).distance(&house, &station)
+        };
+
+        let straight = distance(&streets);
+        streets.restrictions.prohibit(Turn {
+            from: (NodeId(1), NodeId(2)),
+            to: (NodeId(2), NodeId(3)),
+        });
+        let detour = distance(&streets);
+        assert!((straight - 148.3).abs() < 1.0, "{}", straight);
+        assert!(detour > straight + 50.0, "{} vs {}", detour, straight);
+        let expected = streets.nodes[&NodeId(1)].haversine_distance(&streets.nodes[&NodeId(4)])
+            + streets.nodes[&NodeId(4)].haversine_distance(&streets.nodes[&NodeId(3)]);
+        assert!((detour - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_distance_matrix_is_symmetric_with_a_zero_diagonal() {
+        let streets = grid();
+        // off the nodes, so the snapping distances are part of the distances
+        let mut points: Vec<Point> = [1, 6, 11, 12, 15]
             .iter()
-            .min_by_key(|(_, node)| node.haversine_distance(&origin) as u32)
-            .map(|(id, node)| (id.clone(), node.haversine_distance(&origin)))
-            .unwrap()
+            .map(|id| {
+                let node = streets.nodes[&NodeId(*id)];
+                Point::new(node.x() + 0.0001, node.y() - 0.00005)
+            })
+            .collect();
+        // snaps to the node without any street
+        points.push(Point::new(16.3801, 48.2101));
+
+        let matrix = network_distance_matrix(&streets, &points);
+        assert_eq!(matrix.len(), points.len());
+        for (i, row) in matrix.iter().enumerate() {
+            assert_eq!(row.len(), points.len());
+            assert_eq!(row[i], Some(0.0));
+            for (j, distance) in row.iter().enumerate() {
+                assert_eq!(*distance, matrix[j][i], "{} to {}", i, j);
+                let unconnected = i == points.len() - 1 || j == points.len() - 1;
+                assert_eq!(distance.is_none(), unconnected && i != j, "{} to {}", i, j);
+            }
+        }
+        assert!(matrix[0][1].unwrap() > points[0].haversine_distance(&points[1]));
+    }
+
+    #[test]
+    fn unsnapped_centroids_are_reached_in_a_straight_line() {
+        let streets = grid();
+        let station = Point::new(16.3701, 48.2001);
+        // about 1 km west of the grid, without a street node
+        let isolated = PopulatedCentroid::poi(Point::new(16.356, 48.201), 10);
+        let straight = isolated.haversine_distance(&station);
+
+        let calculator = OsmDistanceCalculator::new(&streets);
+        let fixed = calculator.fix_point(&station);
+        assert_eq!(
+            fixed.distance(&isolated),
+            station.haversine_distance(&isolated.geometry)
+        );
+        assert!((calculator.distance(&isolated, &station) - straight).abs() < 1e-9);
+        for network in [
+            NetworkDistanceCalculator::new(&streets, 2000.0).fix_point(&station),
+            NetworkDistanceCalculator::new(&Streets::new(), 2000.0).fix_point(&station),
+        ] {
+            assert!((network.distance(&isolated) - straight).abs() < 1e-9);
+        }
+
+        // snapped centroids are still routed over the streets
+        let mut snapped = PopulatedCentroid::poi(streets.nodes[&NodeId(15)], 10);
+        snapped.street_graph_id = Some(NodeId(15));
+        let routed = fixed.distance(&snapped);
+        assert!(routed < f64::MAX);
+        assert!(routed > snapped.haversine_distance(&station) + 1.0);
+    }
+
+    #[test]
+    fn service_areas_follow_a_u_shape() {
+        use geo::ConvexHull;
+
+        // scattered houses in a U open to the north, 1 km wide and high with 300 m
+        // wide arms and bottom
+        let mut seed: u64 = 7;
+        let mut random = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let points: Vec<Point> = std::iter::repeat_with(|| (random() * 10.0, random() * 10.0))
+            .filter(|(x, y)| *x <= 3.0 || *x >= 7.0 || *y <= 3.0)
+            .take(300)
+            .map(|(x, y)| Point::new(16.37 + x * 0.00135, 48.2 + y * 0.0009))
+            .collect();
+        let Geometry::Polygon(area) = service_area(&points, 10) else {
+            panic!("the houses span a polygon");
+        };
+        let convex = MultiPoint::new(points.clone()).convex_hull();
+        assert!(area.unsigned_area() < convex.unsigned_area() * 0.8);
+        for point in &points {
+            assert!(area.intersects(point));
+        }
+        // in the opening of the U
+        let notch = Point::new(16.37 + 5.0 * 0.00135, 48.2 + 7.0 * 0.0009);
+        assert!(convex.contains(&notch));
+        assert!(!area.contains(&notch));
+    }
+
+    #[test]
+    fn service_areas_of_few_or_collinear_houses_are_points_or_lines() {
+        let a = Point::new(16.37, 48.2);
+        let b = Point::new(16.371, 48.2);
+        let c = Point::new(16.372, 48.2);
+        assert_eq!(
+            service_area(&[], 10),
+            Geometry::MultiPoint(MultiPoint::new(Vec::new()))
+        );
+        assert_eq!(
+            service_area(&[a, b, a], 10),
+            Geometry::MultiPoint(MultiPoint::new(vec![a, b]))
+        );
+        assert_eq!(
+            service_area(&[b, c, a, b], 10),
+            Geometry::LineString(LineString::from(vec![a, c]))
+        );
+    }
+
+    #[test]
+    fn distances_to_lines_at_high_latitudes_are_great_circle_distances() {
+        // an oblique street in the north of Norway, a degree of longitude is about 38 km
+        let line = Line::new(Point::new(23.0, 70.0), Point::new(23.1, 70.02));
+        let point = Point::new(23.03, 70.015);
+        // closest of many points along the street
+        let sampled = (0..=10_000)
+            .map(|step| {
+                line.line_interpolate_point(step as f64 / 10_000.0)
+                    .unwrap()
+                    .haversine_distance(&point)
+            })
+            .fold(f64::MAX, f64::min);
+
+        let distance = haversine_distance_to(&line, &point).unwrap();
+        assert!((distance - sampled).abs() < 1.0, "{} {}", distance, sampled);
+        // searching on raw degrees picks a point further away
+        let Closest::SinglePoint(planar) = line.closest_point(&point) else {
+            panic!("the closest point is on the street");
+        };
+        assert!(planar.haversine_distance(&point) > sampled + 100.0);
+    }
+
+    #[test]
+    fn buildings_are_counted_inside_of_their_outline() {
+        use geo::Polygon;
+
+        let building = |corners: Vec<(f64, f64)>| {
+            Polygon::new(
+                corners
+                    .into_iter()
+                    .map(|(x, y)| (16.37 + x * 0.0001, 48.2 + y * 0.0001))
+                    .collect(),
+                Vec::new(),
+            )
+        };
+        // 10 long and 1 wide arms, the centroid at about (2.9, 2.9) is between them
+        let l_shape = building(vec![
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 10.0),
+            (0.0, 10.0),
+        ]);
+        let centroid = l_shape.centroid().unwrap();
+        assert!(!l_shape.contains(&centroid));
+        let point = representative_point(&Geometry::Polygon(l_shape.clone())).unwrap();
+        assert!(l_shape.contains(&point));
+        let multi = Geometry::MultiPolygon(MultiPolygon::new(vec![l_shape.clone()]));
+        assert!(l_shape.contains(&representative_point(&multi).unwrap()));
+
+        let square = building(vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+        assert_eq!(
+            representative_point(&Geometry::Polygon(square.clone())),
+            square.centroid()
+        );
+    }
+
+    #[test]
+    fn streets_are_snapped_to_the_nearest_node_and_the_lowest_id_on_ties() {
+        let origin = Point::new(16.37, 48.2);
+        let mut streets = Streets::new();
+        // about 10.2 m and 10.7 m east
+        streets.nodes.insert(NodeId(1), Point::new(16.370143, 48.2));
+        streets.nodes.insert(NodeId(2), Point::new(16.370138, 48.2));
+        let (id, distance) = snap_to_street(&streets, &origin).unwrap();
+        assert_eq!(id, NodeId(2));
+        assert!((distance - 10.2).abs() < 0.1, "{}", distance);
+
+        // the same distance to the west and east, the offsets are exact in binary
+        let origin = Point::new(16.375, 48.25);
+        let west = Point::new(16.375 - 0.0009765625, 48.25);
+        let east = Point::new(16.375 + 0.0009765625, 48.25);
+        assert_eq!(
+            origin.haversine_distance(&west),
+            origin.haversine_distance(&east)
+        );
+        let mut streets = Streets::new();
+        for (id, location) in [(7, west), (3, east), (9, west), (5, east)] {
+            streets.nodes.insert(NodeId(id), location);
+        }
+        assert_eq!(snap_to_street(&streets, &origin).unwrap().0, NodeId(3));
+        streets.nodes.insert(NodeId(2), west);
+        assert_eq!(snap_to_street(&streets, &origin).unwrap().0, NodeId(2));
+        assert_eq!(
+            snap_to_street_avoiding(&streets, &StreetIndex::new(&streets), &origin, &[])
+                .unwrap()
+                .0,
+            NodeId(2)
+        );
+    }
+
+    #[test]
+    fn houses_are_not_snapped_across_barriers() {
+        let house = Point::new(16.37, 48.2);
+        // a river running east to west about 5 m south of the house
+        let river = [LineString::from(vec![(16.36, 48.19995), (16.38, 48.19995)])];
+        let mut streets = Streets::new();
+        // about 11 m south, across the river, on a street along the other bank
+        streets.nodes.insert(NodeId(1), Point::new(16.37, 48.1999));
+        streets.nodes.insert(NodeId(2), Point::new(16.371, 48.1999));
+        streets.streetgraph.add_edge(NodeId(1), NodeId(2), 75.0);
+        let index = StreetIndex::new(&streets);
+        assert_eq!(snap_to_street(&streets, &house).unwrap().0, NodeId(1));
+        assert_eq!(
+            snap_to_street_avoiding(&streets, &index, &house, &river),
+            None
+        );
+
+        // about 330 m north, further than the first searched envelope
+        streets.nodes.insert(NodeId(3), Point::new(16.37, 48.203));
+        streets.streetgraph.add_edge(NodeId(3), NodeId(2), 400.0);
+        let index = StreetIndex::new(&streets);
+        let (id, distance) = snap_to_street_avoiding(&streets, &index, &house, &river).unwrap();
+        assert_eq!(id, NodeId(3));
+        assert!((distance - 333.6).abs() < 1.0, "{}", distance);
+        assert_eq!(
+            snap_to_street_avoiding(&streets, &index, &house, &[])
+                .unwrap()
+                .0,
+            NodeId(1)
+        );
     }
 }
@@ -0,0 +1,168 @@
+use geo::{HaversineDistance, Point};
+use osmpbfreader::NodeId;
+use rstar::{
+    primitives::{GeomWithData, Line},
+    RTree, AABB,
+};
+use datatypes::Streets;
+
+use super::PopulatedCentroid;
+use crate::geometry::haversine_distance_to;
+
+/// Lower bound for the length of a degree in meters, so the searched envelope is never too small
+const MIN_METERS_PER_DEGREE: f64 = 110_000.0;
+/// Meters around the point searched first for the closest street segments
+const INITIAL_STREET_SEARCH_RADIUS: f64 = 100.0;
+
+/// R-tree over the centroids of a layer, storing the position of each centroid in the layer
+#[derive(Debug, Clone)]
+pub struct CentroidIndex {
+    tree: RTree<GeomWithData<[f64; 2], usize>>,
+    /// largest distance between a centroid and the street node it is snapped to
+    max_snap_distance: f64,
+}
+
+impl CentroidIndex {
+    /// `bike_streets` is the network the `bike_graph_id` of the centroids refers to
+    pub fn new(centroids: &[PopulatedCentroid], streets: &Streets, bike_streets: &Streets) -> Self {
+        let tree = RTree::bulk_load(
+            centroids
+                .iter()
+                .enumerate()
+                .map(|(index, centroid)| {
+                    GeomWithData::new([centroid.geometry.x(), centroid.geometry.y()], index)
+                })
+                .collect(),
+        );
+        let max_snap_distance = centroids
+            .iter()
+            .flat_map(|centroid| {
+                [
+                    centroid
+                        .street_graph_id
+                        .and_then(|id| streets.nodes.get(&id)),
+                    centroid
+                        .bike_graph_id
+                        .and_then(|id| bike_streets.nodes.get(&id)),
+                ]
+                .into_iter()
+                .flatten()
+                .map(|node| centroid.haversine_distance(node))
+            })
+            .fold(0f64, f64::max);
+        Self {
+            tree,
+            max_snap_distance,
+        }
+    }
+
+    /// Positions of all centroids that can be within `coverage` meters of `origin`.
+    /// Walking distances start at the street node of a centroid, so the straight line
+    /// distance can exceed them by up to the snap distance of the centroid.
+    pub fn candidates(&self, origin: &Point, coverage: f64) -> Vec<usize> {
+        let envelope = envelope(origin, coverage + self.max_snap_distance);
+        self.tree
+            .locate_in_envelope(&envelope)
+            .map(|centroid| centroid.data)
+            .collect()
+    }
+}
+
+/// A street segment with the street nodes at its ends
+type Segment = GeomWithData<Line<[f64; 2]>, (NodeId, NodeId)>;
+
+/// R-tree over the street segments of a layer
+#[derive(Debug, Clone)]
+pub struct StreetIndex {
+    tree: RTree<Segment>,
+}
+
+impl StreetIndex {
+    pub fn new(streets: &Streets) -> Self {
+        let tree = RTree::bulk_load(
+            streets
+                .streetgraph
+                .all_edges()
+                .filter_map(|(from, to, _)| {
+                    let (a, b) = (streets.nodes.get(&from)?, streets.nodes.get(&to)?);
+                    Some(GeomWithData::new(
+                        Line::new([a.x(), a.y()], [b.x(), b.y()]),
+                        (from, to),
+                    ))
+                })
+                .collect(),
+        );
+        Self { tree }
+    }
+
+    /// The `k` street segments closest to `point` together with their distance in
+    /// meters, closest first. Every segment within the radius of the searched envelope
+    /// intersects it, so the envelope is widened until `k` segments are within its radius.
+    pub fn nearest(&self, point: &Point, k: usize) -> Vec<(NodeId, NodeId, f64)> {
+        let mut radius = INITIAL_STREET_SEARCH_RADIUS;
+        loop {
+            let mut segments: Vec<(NodeId, NodeId, f64)> = self
+                .tree
+                .locate_in_envelope_intersecting(&envelope(point, radius))
+                .map(|segment| {
+                    let line = geo::Line::new(segment.geom().from, segment.geom().to);
+                    let distance = haversine_distance_to(&line, point)
+                        .unwrap_or_else(|| line.start_point().haversine_distance(point));
+                    (segment.data.0, segment.data.1, distance)
+                })
+                .collect();
+            segments.sort_by(|a, b| a.2.total_cmp(&b.2));
+            let within = segments.iter().take_while(|segment| segment.2 <= radius);
+            if within.count() >= k || segments.len() == self.tree.size() {
+                segments.truncate(k);
+                return segments;
+            }
+            radius *= 4.0;
+        }
+    }
+
+    /// The street node closest to `point` that `reachable` accepts, with its distance in
+    /// meters. Nodes without any street segment are not indexed and never found. Of nodes
+    /// at the same distance the one with the lowest id is taken, like `snap_to_street`.
+    pub fn nearest_node(
+        &self,
+        point: &Point,
+        reachable: impl Fn(&Point) -> bool,
+    ) -> Option<(NodeId, f64)> {
+        let mut radius = INITIAL_STREET_SEARCH_RADIUS;
+        loop {
+            let segments: Vec<&Segment> = self
+                .tree
+                .locate_in_envelope_intersecting(&envelope(point, radius))
+                .collect();
+            // every node within the radius ends a segment intersecting the envelope
+            let nearest = segments
+                .iter()
+                .flat_map(|segment| {
+                    let line = segment.geom();
+                    [(segment.data.0, line.from), (segment.data.1, line.to)]
+                })
+                .map(|(id, [x, y])| (id, Point::new(x, y)))
+                .filter(|(_, node)| reachable(node))
+                .map(|(id, node)| (id, node.haversine_distance(point)))
+                .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            let all_searched = segments.len() == self.tree.size();
+            match nearest {
+                Some((_, distance)) if distance <= radius => return nearest,
+                _ if all_searched => return nearest,
+                _ => radius *= 4.0,
+            }
+        }
+    }
+}
+
+/// Envelope holding all points within `radius` meters of `origin`
+fn envelope(origin: &Point, radius: f64) -> AABB<[f64; 2]> {
+    let lat_delta = radius / MIN_METERS_PER_DEGREE;
+    let max_lat = (origin.y().abs() + lat_delta).min(89.9);
+    let lon_delta = radius / (MIN_METERS_PER_DEGREE * max_lat.to_radians().cos());
+    AABB::from_corners(
+        [origin.x() - lon_delta, origin.y() - lat_delta],
+        [origin.x() + lon_delta, origin.y() + lat_delta],
+    )
+}
@@ -1,16 +1,16 @@
 use actix_web::{body::BoxBody, http::header::ContentType, HttpResponse, Responder};
-use geo::{Point, Polygon};
+use geo::{BoundingRect, Point, Polygon};
 use geojson::{
     feature::Id,
     ser::{serialize_geometry, to_feature_collection_string},
     Feature, GeoJson,
 };
 use serde::Serialize;
-use tinytemplate::TinyTemplate;
 
 use crate::error::OLPError;
+use crate::validation;
 
-use super::overpass::query_overpass;
+use super::overpass::{query_overpass, OverpassQuery};
 
 #[derive(Serialize)]
 pub struct AdminArea {
@@ -39,6 +39,7 @@ impl TryFrom<Feature> for AdminArea {
             log::info!("Area dropped due to wrong geometry: {:?}", properties);
             return Err(OLPError::GeometryError)
         };
+        validation::validate_geometry(&geometry)?;
         Ok(AdminArea {
             name: format!(
                 "{} {}",
@@ -62,15 +63,6 @@ impl TryFrom<Feature> for AdminArea {
     }
 }
 
-static OVP_QUERY_TEMPLATE: &'static str = "[out:json][timeout:25];
-is_in({lat}, {lon}) -> .a;
-(
-  relation[\"boundary\" = \"administrative\"][\"admin_level\"=\"8\"](pivot.a);
-  relation[\"boundary\" = \"administrative\"][\"admin_level\"=\"9\"](pivot.a);
-);
-
-out geom;";
-
 pub struct AdminAreas(Vec<AdminArea>);
 
 impl TryFrom<GeoJson> for AdminAreas {
@@ -103,31 +95,78 @@ impl Responder for AdminAreas {
     }
 }
 
+/// Most areas returned for a name search
+static MAX_NAME_RESULTS: usize = 20;
+
+/// Admin area without its geometry, for choosing an area before loading it
 #[derive(Serialize)]
-struct Context {
-    lat: f64,
-    lon: f64,
+pub struct AdminAreaSummary {
+    pub name: String,
+    pub id: u64,
+    pub admin_level: u16,
+    /// [min lon, min lat, max lon, max lat]
+    pub bbox: Option<[f64; 4]>,
 }
 
-pub fn render_ovp_query_template(point: Point) -> Result<String, OLPError> {
-    let mut tt = TinyTemplate::new();
-    tt.add_template("query", OVP_QUERY_TEMPLATE)
-        .map_err(OLPError::from_error)?;
-
-    let context = Context {
-        lon: point.x(),
-        lat: point.y(),
-    };
+impl From<AdminArea> for AdminAreaSummary {
+    fn from(value: AdminArea) -> Self {
+        AdminAreaSummary {
+            bbox: value
+                .geometry
+                .bounding_rect()
+                .map(|rect| [rect.min().x, rect.min().y, rect.max().x, rect.max().y]),
+            name: value.name,
+            id: value.id,
+            admin_level: value.admin_level,
+        }
+    }
+}
 
-    Ok(tt.render("query", &context).map_err(OLPError::from_error)?)
+/// Municipalities and their districts around `point`
+fn admin_boundaries_query(point: Point) -> String {
+    OverpassQuery::relations()
+        .with_tag("boundary", "administrative")
+        .with_tag("admin_level", "8")
+        .or_relations()
+        .with_tag("boundary", "administrative")
+        .with_tag("admin_level", "9")
+        .containing(point)
+        .build()
 }
 
 pub async fn find_admin_boundaries_for_point(point: Point) -> Result<AdminAreas, OLPError> {
-    let ovp_query = render_ovp_query_template(point)?;
+    let ovp_query = admin_boundaries_query(point);
 
-    let ovp_response = query_overpass(ovp_query)
-        .await
-        .map_err(OLPError::from_error)?;
+    let ovp_response = query_overpass(ovp_query).await?;
 
     Ok(ovp_response.try_into().map_err(OLPError::from_error)?)
 }
+
+/// Only letters, digits, spaces and dashes are kept from the name, so the name can't
+/// change the query or be read as a regular expression
+fn admin_boundaries_by_name_query(name: &str) -> Result<String, OLPError> {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-')
+        .collect();
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(OLPError::InvalidInput(
+            "the name to search for must contain letters or digits".to_owned(),
+        ));
+    }
+    Ok(OverpassQuery::relations()
+        .with_tag("boundary", "administrative")
+        .with_tag_matching_ignoring_case("name", name)
+        .limit(MAX_NAME_RESULTS)
+        .build())
+}
+
+pub async fn find_admin_boundaries_by_name(name: &str) -> Result<Vec<AdminAreaSummary>, OLPError> {
+    let ovp_query = admin_boundaries_by_name_query(name)?;
+
+    let ovp_response = query_overpass(ovp_query).await?;
+
+    let areas: AdminAreas = ovp_response.try_into().map_err(OLPError::from_error)?;
+    Ok(areas.0.into_iter().map(AdminAreaSummary::from).collect())
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Centroid, Contains, Geometry, Point, Polygon};
+use geojson::GeoJson;
+
+use crate::error::OLPError;
+
+use super::overpass::{query_overpass, OverpassQuery};
+
+/// Points of interest with one of the given `amenity` tags, ways and relations by their center
+fn amenities_query(area: &Polygon, amenities: &[&String]) -> Result<String, OLPError> {
+    let bbox = area.bounding_rect().ok_or(OLPError::GeometryError)?;
+    let amenities = amenities
+        .iter()
+        .map(|amenity| amenity.as_str())
+        .collect::<Vec<&str>>()
+        .join("|");
+    Ok(OverpassQuery::elements()
+        .with_tag_matching("amenity", &format!("^({})$", amenities))
+        .in_bbox(bbox)
+        .out_center()
+        .build())
+}
+
+/// Finds all amenities in `area` whose tag is a key of `weights`, together with the weight of their tag
+pub async fn find_amenities(
+    area: &Polygon,
+    weights: &HashMap<String, u32>,
+) -> Result<Vec<(Point, u32)>, OLPError> {
+    let amenities: Vec<&String> = weights.keys().collect();
+    if amenities.is_empty() {
+        return Err(OLPError::InvalidInput(
+            "at least one amenity tag is required".to_owned(),
+        ));
+    }
+    if let Some(invalid) = amenities.iter().find(|amenity| {
+        amenity.is_empty()
+            || !amenity
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    }) {
+        return Err(OLPError::InvalidInput(format!(
+            "{:?} is not a valid amenity tag",
+            invalid
+        )));
+    }
+
+    let ovp_query = amenities_query(area, &amenities)?;
+    let ovp_response = query_overpass(ovp_query).await?;
+
+    match ovp_response {
+        GeoJson::FeatureCollection(feature_collection) => Ok(feature_collection
+            .into_iter()
+            .filter_map(|feature| {
+                let weight = feature
+                    .property("amenity")
+                    .and_then(|amenity| amenity.as_str())
+                    .and_then(|amenity| weights.get(amenity))
+                    .copied()?;
+                let geometry = TryInto::<Geometry>::try_into(feature.geometry?.value).ok()?;
+                Some((geometry.centroid()?, weight))
+            })
+            .filter(|(point, _)| area.contains(point))
+            .collect()),
+        _ => Err(OLPError::GeometryError),
+    }
+}
@@ -0,0 +1,41 @@
+use geo::{BoundingRect, Geometry, LineString, Polygon};
+use geojson::GeoJson;
+
+use crate::error::OLPError;
+
+use super::overpass::{query_overpass, OverpassQuery};
+
+/// Rivers, canals and railway tracks, which pedestrians can only cross where
+/// the street network does. Wide rivers are also mapped as water areas, their
+/// outline is a barrier as well.
+fn barriers_query(area: &Polygon) -> Result<String, OLPError> {
+    let bbox = area.bounding_rect().ok_or(OLPError::GeometryError)?;
+    Ok(OverpassQuery::ways()
+        .with_tag_matching("waterway", "^(river|canal)$")
+        .or_ways()
+        .with_tag("natural", "water")
+        .with_tag_matching("water", "^(river|canal)$")
+        .or_ways()
+        .with_tag("railway", "rail")
+        .in_bbox(bbox)
+        .build())
+}
+
+pub async fn find_barriers(area: &Polygon) -> Result<Vec<LineString>, OLPError> {
+    let ovp_query = barriers_query(area)?;
+
+    let ovp_response = query_overpass(ovp_query).await?;
+
+    match ovp_response {
+        GeoJson::FeatureCollection(feature_collection) => Ok(feature_collection
+            .into_iter()
+            .filter_map(|feature| feature.geometry)
+            .filter_map(|geometry| match Geometry::try_from(geometry.value).ok()? {
+                Geometry::LineString(line) => Some(line),
+                Geometry::Polygon(polygon) => Some(polygon.exterior().clone()),
+                _ => None,
+            })
+            .collect()),
+        _ => Err(OLPError::GeometryError),
+    }
+}
@@ -1,14 +1,21 @@
 mod admin_area;
+mod amenities;
+mod barriers;
 mod overpass;
 
 use actix_web::{web, Responder, Scope};
 use geo::Point;
+use serde::Deserialize;
 
-use crate::error::OLPError;
-
-use self::admin_area::find_admin_boundaries_for_point;
+use self::admin_area::{
+    find_admin_boundaries_by_name, find_admin_boundaries_for_point, AdminAreaSummary,
+};
+use crate::{envelope::ListResponse, error::OLPError};
 
 pub use self::admin_area::AdminArea;
+pub use self::amenities::find_amenities;
+pub use self::barriers::find_barriers;
+pub use self::overpass::{configure_circuit_breaker, configure_timeouts};
 
 /// Defining /osm endpoint for arcix-web router
 pub fn osm() -> Scope {
@@ -18,6 +25,19 @@ pub fn osm() -> Scope {
 /// Handler for admin_bounds endpoint
 async fn get_admin_bounds(coords: web::Path<(f64, f64)>) -> impl Responder {
     let (lat, lon) = coords.into_inner();
-    let admin_areas = find_admin_boundaries_for_point(Point::new(lon, lat)).await;
-    admin_areas.map_err(|err| OLPError::GenericError(err.to_string()))
+    find_admin_boundaries_for_point(Point::new(lon, lat)).await
+}
+
+#[derive(Deserialize)]
+pub struct AdminAreaSearch {
+    q: String,
+}
+
+/// Handler for admin-areas endpoint, finds administrative boundaries by name
+pub async fn search_admin_areas(
+    query: web::Query<AdminAreaSearch>,
+) -> Result<ListResponse<AdminAreaSummary>, OLPError> {
+    Ok(ListResponse::new(
+        find_admin_boundaries_by_name(&query.q).await?,
+    ))
 }
@@ -1,22 +1,139 @@
 use std::{
     io::Write,
     process::{Command, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use geo::{Point, Rect};
 use geojson::GeoJson;
+use reqwest::StatusCode;
 
 use crate::error::OLPError;
 
-pub async fn query_overpass(query: String) -> Result<GeoJson> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://overpass-api.de/api/interpreter")
-        .body(query)
-        .send()
-        .await?
-        .text()
-        .await?;
+static CIRCUIT_BREAKER: Mutex<CircuitBreaker> = Mutex::new(CircuitBreaker::new(3, 60));
+static TIMEOUTS: Mutex<Timeouts> = Mutex::new(Timeouts {
+    connect: Duration::from_secs(10),
+    total: Duration::from_secs(60),
+});
+/// How long to wait before retrying a query overpass was too busy for
+static RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// Length of the response body included in errors
+static MAX_ERROR_BODY: usize = 200;
+/// Seconds overpass may spend on a query
+static QUERY_TIMEOUT: u32 = 25;
+static OVERPASS_URL: &str = "https://overpass-api.de/api/interpreter";
+
+/// Stops calling overpass for a cooldown period after too many consecutive failures,
+/// so requests fail fast instead of waiting on an upstream that is down
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    const fn new(failure_threshold: u32, cooldown_secs: u64) -> Self {
+        Self {
+            failure_threshold,
+            cooldown: Duration::from_secs(cooldown_secs),
+            consecutive_failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// Returns the remaining cooldown if calls are currently short-circuited
+    fn remaining_cooldown(&self) -> Option<Duration> {
+        self.open_until
+            .and_then(|open_until| open_until.checked_duration_since(Instant::now()))
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.open_until = None;
+    }
+
+    fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= self.failure_threshold {
+            log::warn!(
+                "overpass failed {} times in a row, pausing requests for {}s",
+                self.consecutive_failures,
+                self.cooldown.as_secs()
+            );
+            self.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Limits for a single request to overpass, so a hung connection can't block the
+/// request handler waiting on it
+#[derive(Clone, Copy)]
+struct Timeouts {
+    connect: Duration,
+    /// for the whole request including reading the response
+    total: Duration,
+}
+
+/// Sets how long connecting to overpass and a whole query may take
+pub fn configure_timeouts(connect: Duration, total: Duration) {
+    let mut timeouts = TIMEOUTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    timeouts.connect = connect;
+    timeouts.total = total;
+}
+
+/// Sets after how many consecutive failures overpass is considered down and for how long
+pub fn configure_circuit_breaker(failure_threshold: u32, cooldown: Duration) {
+    let mut breaker = CIRCUIT_BREAKER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    breaker.failure_threshold = failure_threshold.max(1);
+    breaker.cooldown = cooldown;
+}
+
+pub async fn query_overpass(query: String) -> Result<GeoJson, OLPError> {
+    if let Some(remaining) = CIRCUIT_BREAKER
+        .lock()
+        .map_err(OLPError::from_error)?
+        .remaining_cooldown()
+    {
+        return Err(OLPError::UpstreamUnavailable(format!(
+            "overpass is unavailable, retrying in {}s",
+            remaining.as_secs() + 1
+        )));
+    }
+
+    let result = fetch_overpass(query).await;
+    let mut breaker = CIRCUIT_BREAKER.lock().map_err(OLPError::from_error)?;
+    match result {
+        Ok(geometry) => {
+            breaker.record_success();
+            Ok(geometry)
+        }
+        Err(error) => {
+            breaker.record_failure();
+            Err(upstream_error(error))
+        }
+    }
+}
+
+/// Timed out queries are reported as overpass being unavailable
+fn upstream_error(error: anyhow::Error) -> OLPError {
+    let timed_out = error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(reqwest::Error::is_timeout);
+    if timed_out {
+        return OLPError::UpstreamUnavailable("overpass did not answer in time".to_owned());
+    }
+    OLPError::from_error(error)
+}
+
+async fn fetch_overpass(query: String) -> Result<GeoJson> {
+    let response = request_overpass(&query).await?;
 
     let mut child = Command::new("osmtogeojson")
         .stdin(Stdio::piped())
@@ -40,3 +157,258 @@ pub async fn query_overpass(query: String) -> Result<GeoJson> {
 
     Ok(geometry)
 }
+
+/// Sends the query to overpass, retrying once if it is rate limited or timed out.
+/// Answers other than JSON (e.g. the HTML error pages of overpass) are errors.
+async fn request_overpass(query: &str) -> Result<String> {
+    let timeouts = *TIMEOUTS.lock().map_err(OLPError::from_error)?;
+    request_overpass_at(OVERPASS_URL, query, timeouts).await
+}
+
+async fn request_overpass_at(url: &str, query: &str, timeouts: Timeouts) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(timeouts.connect)
+        .timeout(timeouts.total)
+        .build()?;
+    let mut retried = false;
+    loop {
+        let response = client.post(url).body(query.to_owned()).send().await?;
+        let status = response.status();
+        if matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::GATEWAY_TIMEOUT
+        ) && !retried
+        {
+            log::warn!(
+                "overpass answered {}, retrying in {}s",
+                status,
+                RETRY_BACKOFF.as_secs()
+            );
+            retried = true;
+            actix_web::rt::time::sleep(RETRY_BACKOFF).await;
+            continue;
+        }
+        let is_json = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+        let body = response.text().await?;
+        if !status.is_success() || !is_json {
+            bail!(
+                "overpass answered {} with {}",
+                status,
+                body.chars().take(MAX_ERROR_BODY).collect::<String>()
+            );
+        }
+        return Ok(body);
+    }
+}
+
+/// Builds Overpass QL queries returning json, e.g.
+/// `OverpassQuery::relations().with_tag("boundary", "administrative").in_bbox(bbox).build()`.
+///
+/// The query is a union of statements, each starting with the element type to look
+/// for (`ways`, `or_relations`, ...) followed by its tag filters. The area set with
+/// `in_bbox` or `containing` applies to all statements.
+pub struct OverpassQuery {
+    bbox: Option<Rect>,
+    containing: Option<Point>,
+    statements: Vec<String>,
+    output: &'static str,
+    limit: Option<usize>,
+}
+
+impl OverpassQuery {
+    fn new(element: &str) -> Self {
+        OverpassQuery {
+            bbox: None,
+            containing: None,
+            statements: vec![element.to_owned()],
+            output: "geom",
+            limit: None,
+        }
+    }
+
+    pub fn ways() -> Self {
+        Self::new("way")
+    }
+
+    pub fn relations() -> Self {
+        Self::new("relation")
+    }
+
+    /// Nodes, ways and relations
+    pub fn elements() -> Self {
+        Self::new("nwr")
+    }
+
+    /// Starts another statement of the union looking for ways
+    pub fn or_ways(mut self) -> Self {
+        self.statements.push("way".to_owned());
+        self
+    }
+
+    /// Starts another statement of the union looking for relations
+    pub fn or_relations(mut self) -> Self {
+        self.statements.push("relation".to_owned());
+        self
+    }
+
+    fn with_filter(mut self, filter: String) -> Self {
+        if let Some(statement) = self.statements.last_mut() {
+            statement.push_str(&filter);
+        }
+        self
+    }
+
+    pub fn with_tag(self, key: &str, value: &str) -> Self {
+        self.with_filter(format!("[\"{}\"=\"{}\"]", escape(key), escape(value)))
+    }
+
+    /// Tag whose value matches the regular expression `pattern`
+    pub fn with_tag_matching(self, key: &str, pattern: &str) -> Self {
+        self.with_filter(format!("[\"{}\"~\"{}\"]", escape(key), escape(pattern)))
+    }
+
+    /// Like `with_tag_matching`, ignoring the case of the value
+    pub fn with_tag_matching_ignoring_case(self, key: &str, pattern: &str) -> Self {
+        self.with_filter(format!("[\"{}\"~\"{}\",i]", escape(key), escape(pattern)))
+    }
+
+    pub fn in_bbox(mut self, bbox: Rect) -> Self {
+        self.bbox = Some(bbox);
+        self
+    }
+
+    /// Only elements whose area contains `point`, e.g. the boundaries around it
+    pub fn containing(mut self, point: Point) -> Self {
+        self.containing = Some(point);
+        self
+    }
+
+    /// Returns ways and relations by their center instead of their full geometry
+    pub fn out_center(mut self) -> Self {
+        self.output = "center";
+        self
+    }
+
+    /// Returns at most `limit` elements
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let mut query = format!("[out:json][timeout:{}]", QUERY_TIMEOUT);
+        if let Some(bbox) = self.bbox {
+            query.push_str(&format!(
+                "[bbox:{},{},{},{}]",
+                bbox.min().y,
+                bbox.min().x,
+                bbox.max().y,
+                bbox.max().x
+            ));
+        }
+        query.push_str(";\n");
+        let area = match self.containing {
+            Some(point) => {
+                query.push_str(&format!("is_in({}, {}) -> .a;\n", point.y(), point.x()));
+                "(pivot.a)"
+            }
+            None => "",
+        };
+        query.push_str("(\n");
+        for statement in &self.statements {
+            query.push_str(&format!("  {}{};\n", statement, area));
+        }
+        query.push_str(");\n\n");
+        match self.limit {
+            Some(limit) => query.push_str(&format!("out {} {};", self.output, limit)),
+            None => query.push_str(&format!("out {};", self.output)),
+        }
+        query
+    }
+}
+
+/// Escapes a tag key or value for use inside double quotes
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_of_statements_in_a_bbox() {
+        let bbox = Rect::new((16.1, 48.1), (16.6, 48.4));
+        let query = OverpassQuery::ways()
+            .with_tag_matching("waterway", "^(river|canal)$")
+            .or_ways()
+            .with_tag("railway", "rail")
+            .in_bbox(bbox)
+            .build();
+        assert_eq!(
+            query,
+            "[out:json][timeout:25][bbox:48.1,16.1,48.4,16.6];\n\
+             (\n  way[\"waterway\"~\"^(river|canal)$\"];\n  way[\"railway\"=\"rail\"];\n);\n\n\
+             out geom;"
+        );
+    }
+
+    #[test]
+    fn areas_containing_a_point_by_their_center() {
+        let query = OverpassQuery::relations()
+            .with_tag("boundary", "administrative")
+            .with_tag_matching_ignoring_case("name", "wien")
+            .containing(Point::new(16.37, 48.2))
+            .out_center()
+            .limit(10)
+            .build();
+        assert_eq!(
+            query,
+            "[out:json][timeout:25];\n\
+             is_in(48.2, 16.37) -> .a;\n\
+             (\n  relation[\"boundary\"=\"administrative\"][\"name\"~\"wien\",i](pivot.a);\n);\n\n\
+             out center 10;"
+        );
+    }
+
+    #[test]
+    fn quotes_in_tags_are_escaped() {
+        let query = OverpassQuery::elements()
+            .with_tag("name", "say \"hi\" \\")
+            .build();
+        let escaped = r#"nwr["name"="say \"hi\" \\"];"#;
+        assert!(query.contains(escaped), "{}", query);
+    }
+
+    #[actix_web::test]
+    async fn queries_overpass_does_not_answer_in_time_are_unavailable() {
+        use std::{io::Read, net::TcpListener};
+
+        // accepts the request but never answers it
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/interpreter", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            std::thread::sleep(Duration::from_secs(5));
+        });
+        let timeouts = Timeouts {
+            connect: Duration::from_secs(1),
+            total: Duration::from_millis(200),
+        };
+
+        let started = Instant::now();
+        let error = request_overpass_at(&url, "[out:json];", timeouts)
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(
+            matches!(upstream_error(error), OLPError::UpstreamUnavailable(_)),
+            "a timeout is reported as overpass being unavailable"
+        );
+    }
+}
@@ -1,57 +1,101 @@
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
     fmt::Display,
     fs,
+    hash::{Hash, Hasher},
     path::PathBuf,
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock, RwLock,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use actix_web::{
     body::BoxBody,
     http::header::ContentType,
+    middleware::Condition,
     web::{self, Data, Json},
     HttpResponse, Responder, Scope,
 };
 
 use config::Config;
 use geo::{
-    point, BooleanOps, Centroid, HaversineDistance, LineString, MultiPolygon, Point, Polygon,
+    point, BooleanOps, BoundingRect, Centroid, HaversineDistance, LineString, MultiPolygon, Point,
+    Polygon, Rect,
 };
 use geojson::{
     de::deserialize_geometry,
     ser::{serialize_geometry, to_feature_collection_string},
-    Feature,
+    Feature, FeatureCollection,
 };
 use osmpbfreader::NodeId;
 use petgraph::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use datatypes::Streets;
+use datatypes::{Streets, TurnRestrictions};
 
+mod index;
 mod loading;
 mod merge;
-pub use loading::osm;
+use loading::{find_amenities, find_barriers};
+pub use loading::{configure_circuit_breaker, configure_timeouts, osm, search_admin_areas};
+pub use index::{CentroidIndex, StreetIndex};
 pub use merge::*;
 use uuid::Uuid;
 
 use self::{loading::AdminArea};
 use crate::{
-    error::OLPError,
+    envelope::ListResponse,
+    error::{invalid_input_handler, OLPError},
+    geometry::{representative_point, snap_to_street_avoiding},
     persistence::{self, save_layers},
+    ratelimit::RateLimit,
+    validation,
 };
 use openhousepopulator::{Building, GenericGeometry};
 
-pub fn layers() -> Scope {
+/// `overpass_rate_limit` limits the routes querying Overpass, the calculation only
+/// does when `import_barriers` is set
+pub fn layers(overpass_rate_limit: RateLimit, import_barriers: bool) -> Scope {
     web::scope("/layer")
         .app_data(Data::new(Layers::new()))
         .route("/center", web::get().to(find_center))
-        .route("/calculate", web::post().to(calculate_new_layer))
+        .service(
+            web::resource("/calculate")
+                .wrap(Condition::new(import_barriers, overpass_rate_limit.clone()))
+                .route(web::post().to(calculate_new_layer)),
+        )
+        .service(
+            web::resource("/amenities")
+                .wrap(overpass_rate_limit)
+                .route(web::post().to(calculate_amenity_layer)),
+        )
+        .service(
+            web::resource("/geojson")
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(MAX_GEOJSON_UPLOAD_SIZE)
+                        .error_handler(invalid_input_handler),
+                )
+                .route(web::post().to(upload_geojson_layer)),
+        )
+        .service(
+            web::resource("/{layer_id}/population")
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(MAX_GEOJSON_UPLOAD_SIZE)
+                        .error_handler(invalid_input_handler),
+                )
+                .route(web::put().to(replace_population)),
+        )
         .route("/methods", web::get().to(get_layer_methods))
         .route(
             "/by_type/{layer_type}",
             web::get().to(get_merged_layers_by_type),
         )
         .route("/{layer_id}", web::get().to(get_layer))
+        .route("/{layer_id}", web::patch().to(patch_layer))
         .route("/{layer_id}", web::delete().to(delete_layer))
         .route("", web::get().to(summarize_layers))
 }
@@ -67,17 +111,111 @@ pub async fn find_center(layers: web::Data<RwLock<Layers>>) -> Result<Json<Point
     Ok(Json(point))
 }
 
+#[derive(Deserialize)]
+pub struct PrecomputeQuery {
+    #[serde(default)]
+    merge: MergeStrategy,
+}
+
+#[derive(Serialize)]
+pub struct Precomputed {
+    layer_types: usize,
+    centroids: usize,
+    /// whether the merged layers of this generation were already built
+    cached: bool,
+    duration_ms: u128,
+}
+
+/// Builds the merged layers and their indexes for `merge` ahead of the first request,
+/// see `Layers::merged_by_type`. Calling it again only reports the cached layers.
+pub async fn precompute(
+    query: web::Query<PrecomputeQuery>,
+    layers: web::Data<RwLock<Layers>>,
+) -> Result<Json<Precomputed>, OLPError> {
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    layers.ensure_loaded()?;
+    let start = Instant::now();
+    let (merged, cached) = layers.merged_by_type_cached(query.merge);
+    let precomputed = Precomputed {
+        layer_types: merged.len(),
+        centroids: merged.iter().map(|layer| layer.centroids.len()).sum(),
+        cached,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    log::info!(
+        "precomputed {} merged layers in {} ms",
+        precomputed.layer_types,
+        precomputed.duration_ms
+    );
+    Ok(Json(precomputed))
+}
+
+/// Extent of the loaded data, see `Layers::data_bounds`
+#[derive(Serialize, Clone, Debug)]
+pub struct DataBounds {
+    /// false while no layer is loaded, `bbox` is null then
+    loaded: bool,
+    /// `[west, south, east, north]` around the street nodes and buildings of all layers
+    bbox: Option<[f64; 4]>,
+    buildings: usize,
+    inhabitants: u64,
+}
+
+#[derive(Serialize)]
+struct DataBoundsFeature {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: Polygon,
+    buildings: usize,
+    inhabitants: u64,
+}
+
+pub async fn data_bounds(layers: web::Data<RwLock<Layers>>) -> Result<Json<DataBounds>, OLPError> {
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    Ok(Json(layers.data_bounds()))
+}
+
+/// The bounds as a GeoJSON FeatureCollection with one polygon, e.g. to mask the map
+/// outside of them. Without data the collection is empty.
+pub async fn data_bounds_geojson(
+    layers: web::Data<RwLock<Layers>>,
+) -> Result<HttpResponse, OLPError> {
+    let bounds = layers.read().map_err(OLPError::from_error)?.data_bounds();
+    let features: Vec<DataBoundsFeature> = bounds
+        .bbox
+        .map(|[west, south, east, north]| DataBoundsFeature {
+            geometry: Rect::new((west, south), (east, north)).to_polygon(),
+            buildings: bounds.buildings,
+            inhabitants: bounds.inhabitants,
+        })
+        .into_iter()
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(to_feature_collection_string(&features).map_err(OLPError::from_error)?))
+}
+
 pub async fn summarize_layers(
     layers: web::Data<RwLock<Layers>>,
-) -> Result<Json<Vec<Value>>, OLPError> {
-    let layer_summary = layers
-        .read()
-        .map_err(OLPError::from_error)?
-        .0
-        .iter()
-        .map(|(_, layer)| layer.serialize_info())
+) -> Result<ListResponse<Value>, OLPError> {
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    // sorted by id so paginating the summary is stable
+    let mut sorted: Vec<&Layer> = layers.0.values().collect();
+    sorted.sort_by_key(|layer| layer.id);
+    let layer_summary = sorted
+        .into_iter()
+        .map(|layer| layer.serialize_info())
         .collect::<Vec<_>>();
-    Ok(Json(layer_summary))
+    let warnings = layers
+        .0
+        .values()
+        .flat_map(|layer| {
+            layer
+                .inhabitant_warnings()
+                .into_iter()
+                .map(|warning| format!("layer {}: {}", layer.id, warning))
+        })
+        .collect();
+    Ok(ListResponse::new(layer_summary).with_warnings(warnings))
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -90,6 +228,9 @@ pub struct PopulatedCentroid {
     flats: u32,
     pub pop: u32,
     pub street_graph_id: Option<NodeId>,
+    /// node of the bike network of the layer, see `Routing::Bike`
+    #[serde(default)]
+    pub bike_graph_id: Option<NodeId>,
 }
 
 impl TryFrom<Building> for PopulatedCentroid {
@@ -101,6 +242,7 @@ impl TryFrom<Building> for PopulatedCentroid {
                 flats: value.flats as u32,
                 pop: value.pop as u32,
                 street_graph_id: None,
+                bike_graph_id: None,
             });
         }
         return Err(OLPError::GeometryError);
@@ -108,36 +250,110 @@ impl TryFrom<Building> for PopulatedCentroid {
 }
 
 impl PopulatedCentroid {
+    /// A point of interest counted with `weight` instead of its inhabitants
+    pub fn poi(geometry: Point, weight: u32) -> Self {
+        Self {
+            geometry,
+            flats: 0,
+            pop: weight,
+            street_graph_id: None,
+            bike_graph_id: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_flats(self, flats: u32) -> Self {
+        Self { flats, ..self }
+    }
+
     pub fn haversine_distance(&self, rhs: &Point) -> f64 {
         self.geometry.haversine_distance(rhs)
     }
+
+    pub fn house_type(&self) -> HouseType {
+        match self.flats {
+            0 => HouseType::Unknown,
+            1 => HouseType::SingleFamily,
+            flats if flats < HIGH_RISE_FLATS => HouseType::MultiFamily,
+            _ => HouseType::HighRise,
+        }
+    }
+}
+
+/// Buildings with at least this many flats count as high-rises
+const HIGH_RISE_FLATS: u32 = 20;
+
+/// Kind of building a centroid stands for. The cached buildings only keep their
+/// number of flats, so the type is derived from that.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Eq, Hash, Clone, Copy)]
+pub enum HouseType {
+    /// points of interest and uploaded population, which have no flats
+    #[serde(rename = "unknown")]
+    Unknown,
+    #[serde(rename = "single_family")]
+    SingleFamily,
+    #[serde(rename = "multi_family")]
+    MultiFamily,
+    #[serde(rename = "high_rise")]
+    HighRise,
 }
 
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Layers(HashMap<Uuid, Layer>);
+#[serde(transparent)]
+pub struct Layers(HashMap<Uuid, Layer>, #[serde(skip)] DerivedData);
+
+/// What is computed from all layers, on first use and again after every change
+#[derive(Debug, Clone, Default)]
+struct DerivedData {
+    data_bounds: OnceLock<DataBounds>,
+    merged_by_type: MergedByType,
+}
+
+/// The layers merged by type and their indexes, per merge strategy
+#[derive(Debug, Clone, Default)]
+struct MergedByType {
+    sum: OnceLock<Vec<Layer>>,
+    max: OnceLock<Vec<Layer>>,
+    newest: OnceLock<Vec<Layer>>,
+}
+
+impl MergedByType {
+    fn get(&self, strategy: MergeStrategy) -> &OnceLock<Vec<Layer>> {
+        match strategy {
+            MergeStrategy::Sum => &self.sum,
+            MergeStrategy::Max => &self.max,
+            MergeStrategy::Newest => &self.newest,
+        }
+    }
+}
 
 impl Layers {
     pub fn by_type(&self, layer_type: LayerType) -> Layer {
-        let streetgraph = UnGraphMap::from_edges(
+        let streets = merge_streets(
             self.0
-                .iter()
-                .filter(|(_, layer)| layer.layer_type == layer_type)
-                .flat_map(|(_, layer)| layer.streets.streetgraph.all_edges()),
+                .values()
+                .filter(|layer| layer.layer_type == layer_type)
+                .map(|layer| &layer.streets),
+        );
+        let bike_streets = merge_streets(
+            self.0
+                .values()
+                .filter(|layer| layer.layer_type == layer_type)
+                .map(|layer| &layer.bike_streets),
         );
-        let nodes = self
-            .0
-            .iter()
-            .filter(|(_, layer)| layer.layer_type == layer_type)
-            .flat_map(|(_, layer)| layer.streets.nodes.clone())
-            .collect();
-        let streets = Streets { nodes, streetgraph };
 
-        let centroids = self
+        let centroids = merge_centroids(
+            self.0
+                .values()
+                .filter(|layer| layer.layer_type == layer_type),
+            MergeStrategy::default(),
+        );
+        let barriers = self
             .0
             .iter()
             .filter(|(_, layer)| layer.layer_type == layer_type)
-            .flat_map(|(_, layer)| layer.centroids.clone())
+            .flat_map(|(_, layer)| layer.barriers.clone())
             .collect();
         let bbox = self
             .0
@@ -154,28 +370,88 @@ impl Layers {
             bbox,
             centroids,
             streets,
+            bike_streets,
+            barriers,
             layer_type,
             layer_name: layer_type.to_string(),
+            updated: 0,
+            index: OnceLock::new(),
+            street_index: OnceLock::new(),
         }
     }
 
     pub fn all_merged_by_type(&self) -> Vec<Layer> {
+        self.all_merged_by_type_with(MergeStrategy::default())
+    }
+
+    /// Like `all_merged_by_type`, centroids of different layers at the same place are
+    /// combined according to `strategy`
+    pub fn all_merged_by_type_with(&self, strategy: MergeStrategy) -> Vec<Layer> {
         let mut layers_map: HashMap<LayerType, Layer> = HashMap::new();
         for (_, layer) in &self.0 {
             layers_map
                 .entry(layer.layer_type.clone())
                 .and_modify(|elem| {
-                    elem.centroids.append(&mut layer.centroids.clone());
+                    elem.barriers.append(&mut layer.barriers.clone());
                     elem.bbox.union(&layer.bbox);
                     elem.streets.streetgraph.extend(layer.streets.streetgraph.all_edges());
                     elem.streets.nodes.extend(layer.streets.nodes.iter());
+                    elem.bike_streets
+                        .streetgraph
+                        .extend(layer.bike_streets.streetgraph.all_edges());
+                    elem.bike_streets
+                        .nodes
+                        .extend(layer.bike_streets.nodes.iter());
+                    elem.bike_streets
+                        .restrictions
+                        .extend(&layer.bike_streets.restrictions);
                 })
                 .or_insert(layer.clone());
         }
-        layers_map.into_iter().map(|(_, elem)| elem).collect()
+        layers_map
+            .into_iter()
+            .map(|(layer_type, mut elem)| {
+                elem.centroids = merge_centroids(
+                    self.0
+                        .values()
+                        .filter(|layer| layer.layer_type == layer_type),
+                    strategy,
+                );
+                elem.index = OnceLock::new();
+                elem.street_index = OnceLock::new();
+                elem
+            })
+            .collect()
+    }
+
+    /// Like `all_merged_by_type_with`, but the merged layers and their indexes are kept
+    /// until the layers change. The returned layers are copies callers can modify.
+    pub fn merged_by_type(&self, strategy: MergeStrategy) -> Vec<Layer> {
+        self.merged_by_type_cached(strategy).0
+    }
+
+    /// `merged_by_type` and whether the layers were cached already
+    fn merged_by_type_cached(&self, strategy: MergeStrategy) -> (Vec<Layer>, bool) {
+        let mut cached = true;
+        // concurrent requests wait for the first one to merge instead of merging as well
+        let layers = self.1.merged_by_type.get(strategy).get_or_init(|| {
+            cached = false;
+            let layers = self.all_merged_by_type_with(strategy);
+            for layer in &layers {
+                layer.get_index();
+            }
+            layers
+        });
+        (layers.clone(), cached)
     }
 
     pub fn all_merged(&self) -> Layer {
+        self.all_merged_with(MergeStrategy::default())
+    }
+
+    /// Like `all_merged`, centroids of different layers at the same place are combined
+    /// according to `strategy`
+    pub fn all_merged_with(&self, strategy: MergeStrategy) -> Layer {
         if self.0.is_empty() {
             // Return empty layer to stay restful
             return Layer {
@@ -183,25 +459,22 @@ impl Layers {
                 bbox: MultiPolygon::new(vec![]),
                 centroids: Vec::new(),
                 streets: Streets::new(),
+                bike_streets: Streets::new(),
+                barriers: Vec::new(),
                 layer_type: LayerType::Residential,
                 layer_name: "Residential".to_string(),
+                updated: 0,
+                index: OnceLock::new(),
+                street_index: OnceLock::new(),
             };
         }
-        let streetgraph = UnGraphMap::from_edges(
-            self.0
-                .iter()
-                .flat_map(|(_, layer)| layer.streets.streetgraph.all_edges()),
-        );
-        let nodes = self
+        let streets = merge_streets(self.0.values().map(|layer| &layer.streets));
+        let bike_streets = merge_streets(self.0.values().map(|layer| &layer.bike_streets));
+        let centroids = merge_centroids(self.0.values(), strategy);
+        let barriers = self
             .0
-            .iter()
-            .flat_map(|(_, layer)| layer.streets.nodes.clone())
-            .collect();
-        let streets = Streets { nodes, streetgraph };
-        let centroids = self
-            .0
-            .iter()
-            .flat_map(|(_, layer)| layer.centroids.clone())
+            .values()
+            .flat_map(|layer| layer.barriers.clone())
             .collect();
         let bbox = self
             .0
@@ -215,22 +488,242 @@ impl Layers {
             bbox,
             centroids,
             streets,
+            bike_streets,
+            barriers,
             layer_type: LayerType::Residential,
             layer_name: "Residential".to_string(),
+            updated: 0,
+            index: OnceLock::new(),
+            street_index: OnceLock::new(),
+        }
+    }
+
+    /// The centroids of all layers like `all_merged`, with their inhabitants multiplied
+    /// by the weight of their layer type
+    pub fn weighted_centroids(&self, weight: impl Fn(&LayerType) -> f64) -> Vec<PopulatedCentroid> {
+        self.0
+            .values()
+            .flat_map(|layer| {
+                let factor = weight(&layer.layer_type);
+                layer
+                    .centroids
+                    .iter()
+                    .map(move |centroid| PopulatedCentroid {
+                        pop: (centroid.pop as f64 * factor).round() as u32,
+                        ..centroid.clone()
+                    })
+            })
+            .collect()
+    }
+
+    /// Bounding rectangle around the areas of all loaded layers
+    pub fn bounding_rect(&self) -> Option<Rect> {
+        self.0
+            .values()
+            .filter_map(|layer| layer.bbox.bounding_rect())
+            .reduce(|acc, rect| {
+                Rect::new(
+                    (acc.min().x.min(rect.min().x), acc.min().y.min(rect.min().y)),
+                    (acc.max().x.max(rect.max().x), acc.max().y.max(rect.max().y)),
+                )
+            })
+    }
+
+    /// Bounds around the street nodes and buildings of all layers with their totals.
+    /// Computed on first use after every change of the layers.
+    pub fn data_bounds(&self) -> DataBounds {
+        self.1
+            .data_bounds
+            .get_or_init(|| self.compute_data_bounds())
+            .clone()
+    }
+
+    fn compute_data_bounds(&self) -> DataBounds {
+        let points = self.0.values().flat_map(|layer| {
+            layer
+                .streets
+                .nodes
+                .values()
+                .chain(layer.bike_streets.nodes.values())
+                .chain(layer.centroids.iter().map(|centroid| &centroid.geometry))
+        });
+        let bbox = points.fold(None, |bbox: Option<[f64; 4]>, point| {
+            let [west, south, east, north] =
+                bbox.unwrap_or([point.x(), point.y(), point.x(), point.y()]);
+            Some([
+                west.min(point.x()),
+                south.min(point.y()),
+                east.max(point.x()),
+                north.max(point.y()),
+            ])
+        });
+        DataBounds {
+            loaded: !self.is_empty(),
+            bbox,
+            buildings: self.0.values().map(|layer| layer.centroids.len()).sum(),
+            inhabitants: self.0.values().map(Layer::total_inhabitants).sum(),
+        }
+    }
+
+    /// Hash over the ids and centroids of all loaded layers, identifies the data a result was computed on
+    pub fn content_hash(&self) -> Result<u64, OLPError> {
+        let mut hasher = DefaultHasher::new();
+        let mut ids: Vec<&Uuid> = self.0.keys().collect();
+        ids.sort();
+        for id in ids {
+            id.hash(&mut hasher);
+            serde_json::to_vec(&self.0[id].centroids)
+                .map_err(OLPError::from_error)?
+                .hash(&mut hasher);
         }
+        Ok(hasher.finish())
     }
 
     pub fn contains_key(&self, key: &Uuid) -> bool {
         self.0.contains_key(key)
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Layer> {
+        self.0.values()
+    }
+
+    /// Fails with `OLPError::NoDataLoaded` while no layer has been calculated yet
+    pub fn ensure_loaded(&self) -> Result<(), OLPError> {
+        if self.is_empty() {
+            return Err(OLPError::NoDataLoaded);
+        }
+        Ok(())
+    }
+
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::default()
     }
 
     pub fn push(&mut self, layer: Layer) {
+        self.new_generation();
         self.0.insert(layer.id, layer);
     }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Layer> {
+        self.new_generation();
+        self.0.remove(id)
+    }
+
+    /// Layer `id` to change, the change starts a new generation
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut Layer> {
+        self.new_generation();
+        self.0.get_mut(id)
+    }
+
+    /// Starts a new generation of the layers and drops the data derived from them.
+    /// Takes `&mut self`, so the generation changes while the layers are locked for
+    /// writing, together with the change itself. Otherwise a result of the new layers
+    /// could be cached for the old generation.
+    pub(crate) fn new_generation(&mut self) {
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+        self.1 = DerivedData::default();
+    }
+}
+
+/// How centroids of different layers at the same place are combined when merging layers,
+/// e.g. for two population datasets of the same area
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MergeStrategy {
+    /// all centroids are kept, so the inhabitants at the same place add up
+    #[default]
+    #[serde(rename = "sum")]
+    Sum,
+    /// only the centroids of the layer with the most inhabitants there are kept
+    #[serde(rename = "max")]
+    Max,
+    /// only the centroids of the most recently changed layer are kept
+    #[serde(rename = "newest")]
+    Newest,
+}
+
+/// The centroids of `layers` combined with `strategy`. Centroids of the same layer
+/// at the same place are always kept.
+fn merge_centroids<'a>(
+    layers: impl Iterator<Item = &'a Layer>,
+    strategy: MergeStrategy,
+) -> Vec<PopulatedCentroid> {
+    let mut layers: Vec<&Layer> = layers.collect();
+    if strategy == MergeStrategy::Sum {
+        return layers
+            .iter()
+            .flat_map(|layer| layer.centroids.clone())
+            .collect();
+    }
+    layers.sort_by_key(|layer| layer.updated);
+    // centroids by place and position of their layer, places in the order they are first seen
+    let mut places: Vec<(u64, u64)> = Vec::new();
+    let mut by_place: HashMap<(u64, u64), BTreeMap<usize, Vec<&PopulatedCentroid>>> =
+        HashMap::new();
+    for (position, layer) in layers.iter().enumerate() {
+        for centroid in &layer.centroids {
+            let place = (
+                centroid.geometry.x().to_bits(),
+                centroid.geometry.y().to_bits(),
+            );
+            by_place
+                .entry(place)
+                .or_insert_with(|| {
+                    places.push(place);
+                    BTreeMap::new()
+                })
+                .entry(position)
+                .or_default()
+                .push(centroid);
+        }
+    }
+    places
+        .iter()
+        .flat_map(|place| {
+            let by_layer = &by_place[place];
+            // max_by_key picks the last of equal layers, which is the newer one
+            let kept = match strategy {
+                MergeStrategy::Max => by_layer.values().max_by_key(|centroids| {
+                    centroids.iter().map(|centroid| centroid.pop).sum::<u32>()
+                }),
+                _ => by_layer.values().next_back(),
+            };
+            kept.into_iter()
+                .flatten()
+                .map(|centroid| (*centroid).clone())
+        })
+        .collect()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// One network of the streets of all layers, the networks of adjacent areas share nodes
+fn merge_streets<'a>(streets: impl Iterator<Item = &'a Streets> + Clone) -> Streets {
+    let mut restrictions = TurnRestrictions::new();
+    for streets in streets.clone() {
+        restrictions.extend(&streets.restrictions);
+    }
+    Streets {
+        streetgraph: UnGraphMap::from_edges(
+            streets
+                .clone()
+                .flat_map(|streets| streets.streetgraph.all_edges()),
+        ),
+        nodes: streets.flat_map(|streets| streets.nodes.clone()).collect(),
+        restrictions,
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -239,14 +732,76 @@ pub struct Layer {
     bbox: MultiPolygon,
     centroids: Vec<PopulatedCentroid>,
     streets: Streets,
+    /// ways usable by bike, empty for layers of areas preprocessed without them
+    #[serde(default = "Streets::new")]
+    bike_streets: Streets,
+    /// ways that can't be crossed outside of the street network
+    #[serde(default)]
+    barriers: Vec<LineString>,
     layer_type: LayerType,
     layer_name: String,
+    /// seconds since the epoch of the last change to the centroids, orders the
+    /// layers for `MergeStrategy::Newest`
+    #[serde(default)]
+    updated: u64,
+    /// built on first use, has to be reset whenever the centroids change
+    #[serde(skip)]
+    index: OnceLock<CentroidIndex>,
+    /// built on first use, has to be reset whenever the streets change
+    #[serde(skip)]
+    street_index: OnceLock<StreetIndex>,
 }
 
 impl Layer {
+    /// Residential layer of `centroids` for the tests of other modules
+    #[cfg(test)]
+    pub fn fixture(
+        centroids: Vec<PopulatedCentroid>,
+        streets: Streets,
+        bike_streets: Streets,
+    ) -> Layer {
+        Layer {
+            id: Uuid::new_v4(),
+            bbox: MultiPolygon::new(Vec::new()),
+            centroids,
+            streets,
+            bike_streets,
+            barriers: Vec::new(),
+            layer_type: LayerType::Residential,
+            layer_name: "test".to_owned(),
+            updated: 0,
+            index: OnceLock::new(),
+            street_index: OnceLock::new(),
+        }
+    }
+
+    /// The fixture as a layer of another type
+    #[cfg(test)]
+    pub fn with_type(self, layer_type: LayerType) -> Layer {
+        Layer { layer_type, ..self }
+    }
+
     pub fn get_centroids(&self) -> &Vec<PopulatedCentroid> {
         &self.centroids
     }
+    /// Multiplies the inhabitants of every centroid with the factor of its house type,
+    /// the index stays valid as the centroids keep their place
+    pub fn weight_houses(&mut self, factor: impl Fn(HouseType) -> f64) {
+        for centroid in &mut self.centroids {
+            centroid.pop = (centroid.pop as f64 * factor(centroid.house_type())).round() as u32;
+        }
+    }
+
+    pub fn get_index(&self) -> &CentroidIndex {
+        self.index
+            .get_or_init(|| CentroidIndex::new(&self.centroids, &self.streets, &self.bike_streets))
+    }
+
+    pub fn get_street_index(&self) -> &StreetIndex {
+        self.street_index
+            .get_or_init(|| StreetIndex::new(&self.streets))
+    }
+
     pub fn get_type(&self) -> &LayerType {
         &self.layer_type
     }
@@ -255,11 +810,55 @@ impl Layer {
         &self.streets
     }
 
+    pub fn get_bike_streets(&self) -> &Streets {
+        &self.bike_streets
+    }
+
+    pub fn get_barriers(&self) -> &[LineString] {
+        &self.barriers
+    }
+
+    pub fn total_inhabitants(&self) -> u64 {
+        self.centroids
+            .iter()
+            .map(|centroid| centroid.pop as u64)
+            .sum()
+    }
+
+    /// Hints at implausible population data, e.g. a wrong unit or a missing property
+    pub fn inhabitant_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.centroids.is_empty() {
+            warnings.push("layer does not contain any features".to_owned());
+            return warnings;
+        }
+        if self.total_inhabitants() == 0 {
+            warnings.push("total number of inhabitants is zero".to_owned());
+            return warnings;
+        }
+        let unpopulated = self
+            .centroids
+            .iter()
+            .filter(|centroid| centroid.pop == 0)
+            .count();
+        if unpopulated * 2 > self.centroids.len() {
+            warnings.push(format!(
+                "{} of {} features have no inhabitants",
+                unpopulated,
+                self.centroids.len()
+            ));
+        }
+        warnings
+    }
+
     pub fn serialize_info(&self) -> Value {
         json! ({
             "id": self.id,
             "layer_type": self.layer_type,
             "name": self.layer_name,
+            "centroid_count": self.centroids.len(),
+            "total_inhabitants": self.total_inhabitants(),
+            "warnings": self.inhabitant_warnings(),
         })
     }
 }
@@ -285,6 +884,9 @@ pub enum LayerType {
     Workplace,
     #[serde(alias = "shopping")]
     Shopping,
+    /// points of interest weighted by their importance rather than inhabitants
+    #[serde(alias = "amenity")]
+    Amenity,
 }
 
 impl Display for LayerType {
@@ -350,7 +952,7 @@ async fn calculate_new_layer(
     let answers = request.answers;
     let layer_name = request.name;
 
-    let new_layer_id = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, &admin_area.id.to_le_bytes());
+    let new_layer_id = calculated_layer_id(admin_area.id);
 
     if layers
         .read()
@@ -361,15 +963,7 @@ async fn calculate_new_layer(
         return Ok(Json(new_layer_id));
     }
 
-    let data_path_str = config
-        .get_string("data.dir")
-        .map_err(OLPError::from_error)?;
-    let mut data_path = PathBuf::new();
-    data_path.push(data_path_str);
-    data_path.push(&admin_area.id.to_string());
-    data_path.set_extension("map");
-    log::info!("loading data from {:?}", data_path);
-    let mut data = persistence::load_preprocessed_data(&data_path)?;
+    let mut data = load_area_data(&admin_area, &config)?;
 
     if let Some(answer) = answers.get(0).map(|ans| ans.value) {
         data.buildings
@@ -384,27 +978,352 @@ async fn calculate_new_layer(
         .filter_map(|building| building.try_into().ok())
         .collect();
 
-    for centroid in &mut centroids {
-        let closest_street_node = data
-            .streets
-            .nodes
-            .iter()
-            .min_by_key(|(_, node)| node.haversine_distance(&centroid.geometry) as u32)
-            .map(|(id, _)| id)
-            .copied();
+    let barriers = if config
+        .get_bool("routing.import_barriers")
+        .map_err(OLPError::from_error)?
+    {
+        find_barriers(&admin_area.geometry).await?
+    } else {
+        Vec::new()
+    };
 
-        centroid.street_graph_id = closest_street_node;
+    let max_snap_distance = max_centroid_snap_distance(&config)?;
+    let street_index = StreetIndex::new(&data.streets);
+    for centroid in &mut centroids {
+        centroid.street_graph_id = snap_centroid(
+            &data.streets,
+            &street_index,
+            &centroid.geometry,
+            &barriers,
+            max_snap_distance,
+        );
     }
+    let bike_streets = load_bike_streets(&admin_area, &config)?;
+    snap_to_bike_streets(&mut centroids, &bike_streets, &barriers, max_snap_distance);
 
-    layers.write().map_err(OLPError::from_error)?.push(Layer {
+    let layer = Layer {
         id: new_layer_id.clone(),
         bbox: MultiPolygon::new(vec![admin_area.geometry]),
         streets: data.streets,
+        bike_streets,
+        barriers,
         centroids,
         layer_type,
         layer_name,
+        updated: unix_time(),
+        index: OnceLock::new(),
+        street_index: OnceLock::from(street_index),
+    };
+    log::info!(
+        "calculated layer {} with {} inhabitants",
+        new_layer_id,
+        layer.total_inhabitants()
+    );
+    for warning in layer.inhabitant_warnings() {
+        log::warn!("layer {}: {}", new_layer_id, warning);
+    }
+
+    layers.write().map_err(OLPError::from_error)?.push(layer);
+    cache_layers(&layers, &config)?;
+
+    Ok(Json(new_layer_id))
+}
+
+fn max_centroid_snap_distance(config: &Config) -> Result<f64, OLPError> {
+    config
+        .get_float("routing.max_centroid_snap_distance")
+        .map_err(OLPError::from_error)
+}
+
+/// The street node routing to a centroid ends at. Centroids further than
+/// `max_snap_distance` from any street get none, coverage then uses the straight line
+/// distance to them instead of dropping them. `index` has to be built from `streets`.
+fn snap_centroid(
+    streets: &Streets,
+    index: &StreetIndex,
+    point: &Point,
+    barriers: &[LineString],
+    max_snap_distance: f64,
+) -> Option<NodeId> {
+    match snap_to_street_avoiding(streets, index, point, barriers) {
+        Some((id, distance)) if distance <= max_snap_distance => Some(id),
+        _ => {
+            log::debug!(
+                "no street within {}m of centroid ({}, {}), using straight line distance",
+                max_snap_distance,
+                point.x(),
+                point.y()
+            );
+            None
+        }
+    }
+}
+
+/// Sets the `bike_graph_id` of all centroids, nothing to do for areas without bike network
+fn snap_to_bike_streets(
+    centroids: &mut [PopulatedCentroid],
+    bike_streets: &Streets,
+    barriers: &[LineString],
+    max_snap_distance: f64,
+) {
+    if bike_streets.nodes.is_empty() {
+        return;
+    }
+    let index = StreetIndex::new(bike_streets);
+    for centroid in centroids {
+        centroid.bike_graph_id = snap_centroid(
+            bike_streets,
+            &index,
+            &centroid.geometry,
+            barriers,
+            max_snap_distance,
+        );
+    }
+}
+
+/// Id of the layer calculated from the preprocessed data of an admin area, the same
+/// area always gives the same layer
+pub fn calculated_layer_id(admin_area_id: u64) -> Uuid {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, &admin_area_id.to_le_bytes())
+}
+
+fn load_area_data(
+    admin_area: &AdminArea,
+    config: &Config,
+) -> Result<persistence::PreProcessingData, OLPError> {
+    let data_path_str = config
+        .get_string("data.dir")
+        .map_err(OLPError::from_error)?;
+    let mut data_path = PathBuf::new();
+    data_path.push(data_path_str);
+    data_path.push(admin_area.id.to_string());
+    data_path.set_extension("map");
+    log::info!("loading data from {:?}", data_path);
+    persistence::load_preprocessed_data(&data_path)
+}
+
+/// The bike network written next to the `.map` file of the area, empty if the area was
+/// preprocessed before it was added. The turn restrictions of the `.restrictions` file
+/// are attached to it, bike routing is the only one honoring them.
+fn load_bike_streets(admin_area: &AdminArea, config: &Config) -> Result<Streets, OLPError> {
+    let mut data_path = PathBuf::from(
+        config
+            .get_string("data.dir")
+            .map_err(OLPError::from_error)?,
+    );
+    data_path.push(admin_area.id.to_string());
+    data_path.set_extension("bike");
+    let mut bike_streets = persistence::load_bike_streets(&data_path).unwrap_or_else(|err| {
+        log::info!("no bike network loaded from {:?} ({})", data_path, err);
+        Streets::new()
+    });
+    let restrictions_path = data_path.with_extension("restrictions");
+    match persistence::load_restrictions(&restrictions_path) {
+        Ok(restrictions) => bike_streets.restrictions = restrictions,
+        Err(err) => log::info!(
+            "no turn restrictions loaded from {:?} ({})",
+            restrictions_path,
+            err
+        ),
+    }
+    Ok(bike_streets)
+}
+
+#[derive(Deserialize)]
+struct AmenityLayerRequest {
+    name: String,
+    area: Feature,
+    /// weight of each included amenity tag, e.g. `{"hospital": 100, "school": 20}`
+    amenities: HashMap<String, u32>,
+}
+
+/// Calculates a destination layer from the OSM amenities in the area, each amenity
+/// counts with the weight of its tag
+async fn calculate_amenity_layer(
+    request: web::Json<AmenityLayerRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<Json<Uuid>, OLPError> {
+    let request = request.into_inner();
+    let admin_area: AdminArea = request.area.try_into()?;
+
+    let mut tags: Vec<&String> = request.amenities.keys().collect();
+    tags.sort();
+    let mut key = admin_area.id.to_le_bytes().to_vec();
+    for tag in tags {
+        key.extend(tag.as_bytes());
+        key.extend(request.amenities[tag].to_le_bytes());
+    }
+    let new_layer_id = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, &key);
+    if layers
+        .read()
+        .map_err(OLPError::from_error)?
+        .contains_key(&new_layer_id)
+    {
+        log::info!("layer {} is already calculated, reusing", new_layer_id);
+        return Ok(Json(new_layer_id));
+    }
+
+    let data = load_area_data(&admin_area, &config)?;
+    let max_snap_distance = max_centroid_snap_distance(&config)?;
+    let street_index = StreetIndex::new(&data.streets);
+    let mut centroids: Vec<PopulatedCentroid> =
+        find_amenities(&admin_area.geometry, &request.amenities)
+            .await?
+            .into_iter()
+            .map(|(point, weight)| {
+                let mut centroid = PopulatedCentroid::poi(point, weight);
+                centroid.street_graph_id =
+                    snap_centroid(&data.streets, &street_index, &point, &[], max_snap_distance);
+                centroid
+            })
+            .collect();
+    let bike_streets = load_bike_streets(&admin_area, &config)?;
+    snap_to_bike_streets(&mut centroids, &bike_streets, &[], max_snap_distance);
+    log::info!(
+        "calculated amenity layer {} with {} points of interest",
+        new_layer_id,
+        centroids.len()
+    );
+
+    layers.write().map_err(OLPError::from_error)?.push(Layer {
+        id: new_layer_id,
+        bbox: MultiPolygon::new(vec![admin_area.geometry]),
+        streets: data.streets,
+        bike_streets,
+        barriers: Vec::new(),
+        centroids,
+        layer_type: LayerType::Amenity,
+        layer_name: request.name,
+        updated: unix_time(),
+        index: OnceLock::new(),
+        street_index: OnceLock::from(street_index),
     });
+    cache_layers(&layers, &config)?;
+
+    Ok(Json(new_layer_id))
+}
+
+/// Uploaded population data is larger than the default json limit of actix
+static MAX_GEOJSON_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct GeoJsonLayerRequest {
+    name: String,
+    layer_type: LayerType,
+    /// admin area the streets for routing are loaded for
+    area: Feature,
+    /// features with a numeric `inhabitants` property, e.g. from OpenPopulationEstimator
+    population: FeatureCollection,
+}
 
+impl TryFrom<Feature> for PopulatedCentroid {
+    type Error = OLPError;
+
+    fn try_from(value: Feature) -> Result<Self, Self::Error> {
+        let inhabitants = value
+            .property("inhabitants")
+            .ok_or_else(|| OLPError::InvalidInput("inhabitants property is missing".to_owned()))?
+            .as_f64()
+            .filter(|inhabitants| inhabitants.is_finite() && *inhabitants >= 0.0)
+            .ok_or_else(|| {
+                OLPError::InvalidInput("inhabitants has to be a positive number".to_owned())
+            })?;
+        let invalid_geometry = || OLPError::InvalidInput("geometry is missing or empty".to_owned());
+        let geometry: geo::Geometry = value
+            .geometry
+            .ok_or_else(invalid_geometry)?
+            .value
+            .try_into()
+            .map_err(|_| invalid_geometry())?;
+        validation::validate_geometry(&geometry)?;
+        Ok(Self {
+            geometry: representative_point(&geometry).ok_or_else(invalid_geometry)?,
+            flats: 0,
+            pop: inhabitants.round() as u32,
+            street_graph_id: None,
+            bike_graph_id: None,
+        })
+    }
+}
+
+/// Centroids of uploaded features, errors name the position of the feature
+fn centroids_from_features(features: Vec<Feature>) -> Result<Vec<PopulatedCentroid>, OLPError> {
+    features
+        .into_iter()
+        .enumerate()
+        .map(|(position, feature)| {
+            PopulatedCentroid::try_from(feature).map_err(|error| match error {
+                OLPError::InvalidInput(message) => {
+                    OLPError::InvalidInput(format!("feature {}: {}", position, message))
+                }
+                error => error,
+            })
+        })
+        .collect()
+}
+
+/// Creates a layer from uploaded population data, polygons are counted at their
+/// `representative_point`
+async fn upload_geojson_layer(
+    request: web::Json<GeoJsonLayerRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<Json<Uuid>, OLPError> {
+    let request = request.into_inner();
+    let admin_area: AdminArea = request.area.try_into()?;
+    let mut centroids = centroids_from_features(request.population.features)?;
+
+    let data = load_area_data(&admin_area, &config)?;
+    let max_snap_distance = max_centroid_snap_distance(&config)?;
+    let street_index = StreetIndex::new(&data.streets);
+    for centroid in &mut centroids {
+        centroid.street_graph_id = snap_centroid(
+            &data.streets,
+            &street_index,
+            &centroid.geometry,
+            &[],
+            max_snap_distance,
+        );
+    }
+    let bike_streets = load_bike_streets(&admin_area, &config)?;
+    snap_to_bike_streets(&mut centroids, &bike_streets, &[], max_snap_distance);
+
+    let new_layer_id = Uuid::new_v4();
+    let layer = Layer {
+        id: new_layer_id,
+        bbox: MultiPolygon::new(vec![admin_area.geometry]),
+        streets: data.streets,
+        bike_streets,
+        barriers: Vec::new(),
+        centroids,
+        layer_type: request.layer_type,
+        layer_name: request.name,
+        updated: unix_time(),
+        index: OnceLock::new(),
+        street_index: OnceLock::from(street_index),
+    };
+    log::info!(
+        "uploaded layer {} with {} inhabitants",
+        new_layer_id,
+        layer.total_inhabitants()
+    );
+    layers.write().map_err(OLPError::from_error)?.push(layer);
+    cache_layers(&layers, &config)?;
+
+    Ok(Json(new_layer_id))
+}
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Counts the changes to the layers, results calculated from an older generation are outdated
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Writes the layers to the cache dir, so they are loaded again after a restart.
+/// Every change of the layers is persisted through here.
+pub(crate) fn cache_layers(layers: &RwLock<Layers>, config: &Config) -> Result<(), OLPError> {
     let mut path = PathBuf::from(config.get_string("cache.dir").unwrap());
     match fs::create_dir_all(&path) {
         Ok(_) => {
@@ -417,8 +1336,7 @@ async fn calculate_new_layer(
         }
         Err(e) => log::error!("failed to create directory {}: {}", path.display(), e),
     }
-
-    Ok(Json(new_layer_id))
+    Ok(())
 }
 
 async fn get_layer(
@@ -436,9 +1354,396 @@ async fn get_layer(
 async fn delete_layer(
     id: web::Path<Uuid>,
     layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
 ) -> Result<HttpResponse, OLPError> {
-    match layers.write().map_err(OLPError::from_error)?.0.remove(&id) {
-        Some(_) => Ok(HttpResponse::Ok().finish()),
-        None => Ok(HttpResponse::NotFound().finish()),
+    if layers
+        .write()
+        .map_err(OLPError::from_error)?
+        .remove(&id)
+        .is_none()
+    {
+        return Err(OLPError::NotFound(format!("layer {}", id)));
+    }
+    cache_layers(&layers, &config)?;
+    log::info!("deleted layer {}", id);
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Change of a single feature, `id` is its position in the feature collection of
+/// `GET /layer/{layer_id}` before the patch is applied
+#[derive(Deserialize)]
+struct FeatureUpdate {
+    id: usize,
+    inhabitants: Option<u32>,
+    #[serde(default)]
+    delete: bool,
+}
+
+#[derive(Deserialize)]
+struct PatchLayerRequest {
+    updates: Vec<FeatureUpdate>,
+}
+
+#[derive(Serialize)]
+struct PatchLayerResult {
+    updated: usize,
+    deleted: usize,
+    /// ids of updates that were skipped since the layer has no such feature
+    unknown_ids: Vec<usize>,
+    total_inhabitants: u64,
+}
+
+impl Layer {
+    /// Applies all updates of a patch at once, deletions are done last so
+    /// the ids of the other updates still refer to the same features
+    fn apply_updates(&mut self, updates: &[FeatureUpdate]) -> PatchLayerResult {
+        let mut result = PatchLayerResult {
+            updated: 0,
+            deleted: 0,
+            unknown_ids: Vec::new(),
+            total_inhabitants: 0,
+        };
+        let mut deleted = vec![false; self.centroids.len()];
+        for update in updates {
+            let Some(centroid) = self.centroids.get_mut(update.id) else {
+                result.unknown_ids.push(update.id);
+                continue;
+            };
+            if update.delete {
+                deleted[update.id] = true;
+            } else if let Some(inhabitants) = update.inhabitants {
+                centroid.pop = inhabitants;
+                result.updated += 1;
+            }
+        }
+        self.centroids = std::mem::take(&mut self.centroids)
+            .into_iter()
+            .zip(&deleted)
+            .filter(|(_, deleted)| !**deleted)
+            .map(|(centroid, _)| centroid)
+            .collect();
+        result.deleted = deleted.into_iter().filter(|deleted| *deleted).count();
+        result.total_inhabitants = self.total_inhabitants();
+        if result.updated > 0 || result.deleted > 0 {
+            self.updated = unix_time();
+        }
+        // the index holds positions of the centroids
+        self.index = OnceLock::new();
+        result
+    }
+}
+
+#[derive(Deserialize)]
+struct ReplacePopulationRequest {
+    /// features with a numeric `inhabitants` property, like for a new geojson layer
+    population: FeatureCollection,
+}
+
+impl Layer {
+    /// Replaces the centroids, snapping them to the streets already loaded for the layer
+    fn replace_centroids(&mut self, mut centroids: Vec<PopulatedCentroid>, max_snap_distance: f64) {
+        let street_index = self.get_street_index();
+        for centroid in &mut centroids {
+            centroid.street_graph_id = snap_centroid(
+                &self.streets,
+                street_index,
+                &centroid.geometry,
+                &self.barriers,
+                max_snap_distance,
+            );
+        }
+        snap_to_bike_streets(
+            &mut centroids,
+            &self.bike_streets,
+            &self.barriers,
+            max_snap_distance,
+        );
+        self.centroids = centroids;
+        self.updated = unix_time();
+        self.index = OnceLock::new();
+    }
+}
+
+/// Recomputes the centroids of a layer from updated building geometries and
+/// population, keeping its streets, barriers and name
+async fn replace_population(
+    id: web::Path<Uuid>,
+    request: web::Json<ReplacePopulationRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<Json<u64>, OLPError> {
+    let centroids = centroids_from_features(request.into_inner().population.features)?;
+    let max_snap_distance = max_centroid_snap_distance(&config)?;
+    let total_inhabitants = {
+        let mut layers = layers.write().map_err(OLPError::from_error)?;
+        let layer = layers
+            .get_mut(&id)
+            .ok_or_else(|| OLPError::NotFound(format!("layer {}", id)))?;
+        layer.replace_centroids(centroids, max_snap_distance);
+        layer.total_inhabitants()
+    };
+    cache_layers(&layers, &config)?;
+    log::info!(
+        "replaced the population of layer {}, now {} inhabitants",
+        id,
+        total_inhabitants
+    );
+    Ok(Json(total_inhabitants))
+}
+
+/// Changes or removes single features of a layer without uploading it again
+async fn patch_layer(
+    id: web::Path<Uuid>,
+    request: web::Json<PatchLayerRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<Json<PatchLayerResult>, OLPError> {
+    let result = {
+        // held for the whole patch, so concurrent patches are applied one after the other
+        let mut layers = layers.write().map_err(OLPError::from_error)?;
+        layers
+            .get_mut(&id)
+            .ok_or_else(|| OLPError::NotFound(format!("layer {}", id)))?
+            .apply_updates(&request.updates)
+    };
+    cache_layers(&layers, &config)?;
+    log::info!(
+        "patched layer {}: {} updated, {} deleted, {} unknown ids",
+        id,
+        result.updated,
+        result.deleted,
+        result.unknown_ids.len()
+    );
+    Ok(Json(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(updated: u64, centroids: Vec<PopulatedCentroid>) -> Layer {
+        Layer {
+            id: Uuid::new_v4(),
+            bbox: MultiPolygon::new(Vec::new()),
+            centroids,
+            streets: Streets::new(),
+            bike_streets: Streets::new(),
+            barriers: Vec::new(),
+            layer_type: LayerType::Residential,
+            layer_name: "test".to_owned(),
+            updated,
+            index: OnceLock::new(),
+            street_index: OnceLock::new(),
+        }
+    }
+
+    fn inhabitants_at(centroids: &[PopulatedCentroid], place: Point) -> u32 {
+        centroids
+            .iter()
+            .filter(|centroid| centroid.geometry == place)
+            .map(|centroid| centroid.pop)
+            .sum()
+    }
+
+    #[test]
+    fn merge_strategies_of_a_shared_place() {
+        let shared = Point::new(16.37, 48.2);
+        let other = Point::new(16.38, 48.2);
+        let older = layer(
+            1,
+            vec![
+                PopulatedCentroid::poi(shared, 10),
+                PopulatedCentroid::poi(other, 5),
+            ],
+        );
+        let newer = layer(2, vec![PopulatedCentroid::poi(shared, 3)]);
+        // the order the layers are passed in doesn't matter for newest
+        let layers = [&newer, &older];
+
+        let sum = merge_centroids(layers.into_iter(), MergeStrategy::Sum);
+        assert_eq!(sum.len(), 3);
+        assert_eq!(inhabitants_at(&sum, shared), 13);
+
+        let max = merge_centroids(layers.into_iter(), MergeStrategy::Max);
+        assert_eq!(max.len(), 2);
+        assert_eq!(inhabitants_at(&max, shared), 10);
+
+        let newest = merge_centroids(layers.into_iter(), MergeStrategy::Newest);
+        assert_eq!(newest.len(), 2);
+        assert_eq!(inhabitants_at(&newest, shared), 3);
+
+        // places only one layer has are kept by every strategy
+        for merged in [&sum, &max, &newest] {
+            assert_eq!(inhabitants_at(merged, other), 5);
+        }
+    }
+
+    fn update(id: usize, inhabitants: u32) -> FeatureUpdate {
+        FeatureUpdate {
+            id,
+            inhabitants: Some(inhabitants),
+            delete: false,
+        }
+    }
+
+    #[test]
+    fn patches_mark_the_layer_as_changed() {
+        let place = Point::new(16.37, 48.2);
+        let mut layer = layer(0, vec![PopulatedCentroid::poi(place, 10); 3]);
+
+        let result = layer.apply_updates(&[update(7, 1)]);
+        assert_eq!(result.unknown_ids, vec![7]);
+        assert_eq!(layer.updated, 0);
+
+        layer.apply_updates(&[update(0, 1)]);
+        assert!(layer.updated > 0);
+
+        layer.updated = 0;
+        layer.apply_updates(&[FeatureUpdate {
+            id: 1,
+            inhabitants: None,
+            delete: true,
+        }]);
+        assert!(layer.updated > 0);
+        assert_eq!(layer.centroids.len(), 2);
+    }
+
+    #[test]
+    fn concurrent_patches_are_all_applied() {
+        let features = 8;
+        let layer = layer(
+            0,
+            vec![PopulatedCentroid::poi(Point::new(16.37, 48.2), 0); features],
+        );
+        let id = layer.id;
+        let mut all = Layers::new();
+        all.push(layer);
+        let layers = Data::new(RwLock::new(all));
+        let cache_dir = std::env::temp_dir().join(format!("olp-test-{}", Uuid::new_v4()));
+        let config = Data::new(
+            Config::builder()
+                .set_override("cache.dir", cache_dir.to_str().unwrap())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let patches: Vec<_> = (0..features)
+            .map(|feature| {
+                let layers = layers.clone();
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    let request = PatchLayerRequest {
+                        updates: vec![update(feature, feature as u32 + 1)],
+                    };
+                    actix_web::rt::System::new().block_on(patch_layer(
+                        web::Path::from(id),
+                        Json(request),
+                        layers,
+                        config,
+                    ))
+                })
+            })
+            .collect();
+        for patch in patches {
+            assert_eq!(patch.join().unwrap().unwrap().updated, 1);
+        }
+        let _ = fs::remove_dir_all(cache_dir);
+
+        let layers = layers.read().unwrap();
+        let centroids = layers.0[&id].get_centroids();
+        assert_eq!(centroids.len(), features);
+        for (feature, centroid) in centroids.iter().enumerate() {
+            assert_eq!(centroid.pop, feature as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn changes_start_a_new_generation() {
+        let mut layers = Layers::new();
+        // other tests change the layers concurrently, so the generation only has to grow
+        let before = generation();
+        let layer = layer(0, Vec::new());
+        let id = layer.id;
+        layers.push(layer);
+        let pushed = generation();
+        assert!(pushed > before);
+        layers.get_mut(&id).unwrap().updated = 1;
+        let changed = generation();
+        assert!(changed > pushed);
+        layers.remove(&id).unwrap();
+        assert!(generation() > changed);
+    }
+
+    #[test]
+    fn bounds_and_merged_layers_follow_every_change() {
+        let mut layers = Layers::new();
+        assert!(!layers.data_bounds().loaded);
+        let first = layer(0, vec![PopulatedCentroid::poi(Point::new(16.37, 48.2), 10)]);
+        let id = first.id;
+        layers.push(first);
+        assert_eq!(layers.data_bounds().bbox, Some([16.37, 48.2, 16.37, 48.2]));
+        assert!(!layers.merged_by_type_cached(MergeStrategy::Sum).1);
+        assert!(layers.merged_by_type_cached(MergeStrategy::Sum).1);
+
+        let second = PopulatedCentroid::poi(Point::new(16.38, 48.21), 5);
+        layers.push(layer(0, vec![second]));
+        assert_eq!(layers.data_bounds().bbox, Some([16.37, 48.2, 16.38, 48.21]));
+        assert_eq!(layers.data_bounds().inhabitants, 15);
+        let (merged, cached) = layers.merged_by_type_cached(MergeStrategy::Sum);
+        assert!(!cached);
+        assert_eq!(merged[0].total_inhabitants(), 15);
+
+        layers.get_mut(&id).unwrap().centroids[0].pop = 20;
+        assert_eq!(layers.data_bounds().inhabitants, 25);
+        let merged = layers.merged_by_type(MergeStrategy::Sum);
+        assert_eq!(merged[0].total_inhabitants(), 25);
+
+        layers.remove(&id).unwrap();
+        let bounds = layers.data_bounds();
+        assert_eq!(bounds.bbox, Some([16.38, 48.21, 16.38, 48.21]));
+        let merged = layers.merged_by_type(MergeStrategy::Sum);
+        assert_eq!(merged[0].total_inhabitants(), 5);
+    }
+
+    #[test]
+    fn the_nearest_streets_are_found_outside_of_the_first_envelope() {
+        let point = Point::new(16.37, 48.2);
+        let mut streets = Streets::new();
+        // east-west segments 50 m, 1 km and 5 km north of the point
+        for (id, meters) in [(1, 50.0), (3, 1000.0), (5, 5000.0)] {
+            let lat = 48.2 + meters / 111_195.0;
+            let (west, east) = (NodeId(id), NodeId(id + 1));
+            streets.nodes.insert(west, Point::new(16.369, lat));
+            streets.nodes.insert(east, Point::new(16.371, lat));
+            streets.streetgraph.add_edge(west, east, 150.0);
+        }
+        let index = StreetIndex::new(&streets);
+
+        let nearest = index.nearest(&point, 2);
+        assert_eq!(nearest.len(), 2);
+        assert!((nearest[0].2 - 50.0).abs() < 1.0, "{:?}", nearest);
+        assert!((nearest[1].2 - 1000.0).abs() < 1.0, "{:?}", nearest);
+        assert_eq!(index.nearest(&point, 10).len(), 3);
+    }
+
+    #[test]
+    fn centroids_far_from_the_streets_are_not_snapped() {
+        let mut streets = Streets::new();
+        streets.nodes.insert(NodeId(1), Point::new(16.37, 48.2));
+        // about 100 m and 1 km north of the street node
+        let near = Point::new(16.37, 48.2009);
+        let far = Point::new(16.37, 48.209);
+        let index = StreetIndex::new(&streets);
+        assert_eq!(
+            snap_centroid(&streets, &index, &near, &[], 200.0),
+            Some(NodeId(1))
+        );
+        assert_eq!(snap_centroid(&streets, &index, &far, &[], 200.0), None);
+        let empty = Streets::new();
+        assert_eq!(
+            snap_centroid(&empty, &StreetIndex::new(&empty), &near, &[], 200.0),
+            None
+        );
     }
 }
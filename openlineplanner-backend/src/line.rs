@@ -0,0 +1,171 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::RwLock};
+
+use actix_web::{web, Scope};
+use config::Config;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    envelope::ListResponse,
+    error::OLPError,
+    export,
+    persistence::{self, save_lines},
+    validation, Station,
+};
+
+pub fn lines() -> Scope {
+    web::scope("/line")
+        .route("/{line_id}/gtfs", web::get().to(export::export_line_gtfs))
+        .route("/{line_id}", web::get().to(get_line))
+        .route("/{line_id}", web::put().to(update_line))
+        .route("", web::get().to(list_lines))
+        .route("", web::post().to(create_line))
+}
+
+/// A transit line with its stations in the order they are served
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Line {
+    id: Uuid,
+    name: String,
+    /// line type as used by the frontend, e.g. `tram` or `custom`
+    mode: Option<String>,
+    stations: Vec<Station>,
+}
+
+impl Line {
+    pub fn get_id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    pub fn get_stations(&self) -> &[Station] {
+        &self.stations
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Lines(HashMap<Uuid, Line>);
+
+impl Lines {
+    pub fn get(&self, id: &Uuid) -> Result<&Line, OLPError> {
+        self.0
+            .get(id)
+            .ok_or_else(|| OLPError::NotFound(format!("line {}", id)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[derive(Deserialize)]
+struct LineRequest {
+    name: String,
+    mode: Option<String>,
+    stations: Vec<Station>,
+}
+
+impl LineRequest {
+    fn into_line(self, id: Uuid, config: &Config) -> Result<Line, OLPError> {
+        validation::validate_not_empty(self.stations.len(), "stations")?;
+        validation::validate_station_count(self.stations.len(), config)?;
+        validation::validate_points(self.stations.iter().map(|station| &station.location), None)?;
+        Ok(Line {
+            id,
+            name: self.name,
+            mode: self.mode,
+            stations: self.stations,
+        })
+    }
+}
+
+async fn list_lines(lines: web::Data<RwLock<Lines>>) -> Result<ListResponse<Line>, OLPError> {
+    let lines = lines.read().map_err(OLPError::from_error)?;
+    Ok(ListResponse::new(lines.0.values().cloned().collect()))
+}
+
+async fn get_line(
+    id: web::Path<Uuid>,
+    lines: web::Data<RwLock<Lines>>,
+) -> Result<web::Json<Line>, OLPError> {
+    let lines = lines.read().map_err(OLPError::from_error)?;
+    Ok(web::Json(lines.get(&id)?.clone()))
+}
+
+async fn create_line(
+    request: web::Json<LineRequest>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+) -> Result<web::Json<Line>, OLPError> {
+    let line = request.into_inner().into_line(Uuid::new_v4(), &config)?;
+    lines
+        .write()
+        .map_err(OLPError::from_error)?
+        .0
+        .insert(line.id, line.clone());
+    cache_lines(&lines, &config)?;
+    log::info!("created line {}", line.id);
+    Ok(web::Json(line))
+}
+
+async fn update_line(
+    id: web::Path<Uuid>,
+    request: web::Json<LineRequest>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+) -> Result<web::Json<Line>, OLPError> {
+    let line = request.into_inner().into_line(*id, &config)?;
+    {
+        let mut lines = lines.write().map_err(OLPError::from_error)?;
+        lines.get(&id)?;
+        lines.0.insert(line.id, line.clone());
+    }
+    cache_lines(&lines, &config)?;
+    Ok(web::Json(line))
+}
+
+pub(crate) fn cache_lines(lines: &RwLock<Lines>, config: &Config) -> Result<(), OLPError> {
+    let mut path = PathBuf::from(config.get_string("cache.dir").unwrap());
+    match fs::create_dir_all(&path) {
+        Ok(_) => {
+            path.push("lines");
+            if let Err(e) = save_lines(lines.read().as_ref().map_err(OLPError::from_error)?, &path)
+            {
+                log::error!("failed to save lines to cache: {}", e)
+            }
+        }
+        Err(e) => log::error!("failed to create directory {}: {}", path.display(), e),
+    }
+    Ok(())
+}
+
+pub fn load_lines(config: &Config) -> web::Data<RwLock<Lines>> {
+    let mut path = PathBuf::from(config.get_string("cache.dir").unwrap());
+    path.push("lines");
+    let lines = persistence::load_lines(&path).unwrap_or_else(|err| {
+        log::info!("no cached lines loaded from {:?} ({})", path, err);
+        Lines::default()
+    });
+    web::Data::new(RwLock::new(lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_without_stations_are_rejected() {
+        let config = crate::config_defaults().build().unwrap();
+        let request: LineRequest =
+            serde_json::from_value(serde_json::json!({"name": "U1", "stations": []})).unwrap();
+        let result = request.into_line(Uuid::new_v4(), &config);
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+    }
+}
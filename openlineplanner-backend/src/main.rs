@@ -1,34 +1,193 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::path::PathBuf;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use actix_cors::Cors;
-use actix_web::{web, App, HttpServer};
+use actix_web::{
+    http::header::{self, ContentType},
+    middleware::Compress,
+    web, App, CustomizeResponder, Either, HttpResponse, HttpServer, Responder,
+};
 use anyhow::Result;
-use config::Config;
+use config::{builder::DefaultState, Config, ConfigBuilder};
 use error::OLPError;
-use geo::Point;
+use geo::{HaversineDistance, Intersects, LineString, Point, Rect};
+use geojson::ser::{serialize_geometry, to_feature_collection_string};
 use log::info;
-use population::InhabitantsMap;
-use serde::Deserialize;
+use osmpbfreader::NodeId;
+use population::{
+    AccessDistances, CaptureCurve, CaptureRates, CoverageOverlap, HouseTypeWeights,
+    InhabitantsByLayerType, InhabitantsMap, InhabitantsScale, RidershipEstimate,
+    ScenarioComparison,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+mod auth;
 mod coverage;
+mod datasets;
+mod envelope;
 mod error;
+mod export;
 mod geometry;
 mod layers;
+mod line;
 mod persistence;
 mod population;
+mod ratelimit;
+mod state;
 mod station;
+mod tiles;
+mod timing;
+mod validation;
 
-use coverage::{CoverageMap, Method, Routing};
-use layers::{LayerType, Layers};
-use station::{OptimalStationResult, Station};
+use coverage::{
+    CoverageCache, CoverageDefaults, CoverageMap, Method, MethodSelection, RemovalImpactCache,
+    Routing,
+};
+use layers::{Layer, LayerType, Layers, MergeStrategy};
+use line::Lines;
+use ratelimit::RateLimit;
+use station::{
+    Exclusion, FindStationOptions, MinimalStationsOptions, MinimalStationsResult,
+    OptimalStationResult, Station, StationSetOptions, StationSetResult,
+};
+use tiles::TileCache;
+use timing::LayerTiming;
+use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct StationInfoRequest {
+    #[serde(default)]
     stations: Vec<Station>,
-    _separation_distance: Option<i32>,
-    method: Option<Method>,
+    /// id of a stored line to use the stations of instead of `stations`
+    line: Option<Uuid>,
+    /// stations closer than this (in meters) split their coverage instead of
+    /// counting shared houses for both, defaults to overlapping coverage areas
+    #[serde(alias = "_separation_distance")]
+    separation_distance: Option<f64>,
+    /// one method for all layer types or one per layer type, layer types missing
+    /// there use `coverage.default_method`
+    method: Option<MethodSelection>,
     routing: Option<Routing>,
+    /// routing per layer type, layer types missing here use `routing`
+    layer_routing: Option<HashMap<LayerType, Routing>>,
+    /// multiplier for the covered inhabitants, globally or per layer type. Also accepted
+    /// as `type_weights`, e.g. to count workplaces less than residents when ranking stations.
+    /// Requests with both are rejected as having a duplicate `scale` field.
+    #[serde(alias = "type_weights")]
+    scale: Option<InhabitantsScale>,
+    /// multiplier for the inhabitants of each house type, e.g. `{"high_rise": 0.8}`
+    house_type_weights: Option<HouseTypeWeights>,
+    /// how houses of overlapping layers at the same place count, defaults to `sum`
+    #[serde(default)]
+    merge: MergeStrategy,
+    /// minutes of walking, replaces the coverage of all stations
+    time_budget: Option<f64>,
+    /// count the expected wait of half the headway of each station against the time budget
+    #[serde(default)]
+    include_wait: bool,
+    /// also report the expected riders of each station, see `InhabitantsMap::estimate_riders`
+    #[serde(default)]
+    estimate_riders: bool,
+    /// also list the covered houses of each station, see `InhabitantsMap::include_buildings`
+    #[serde(default)]
+    include_buildings: bool,
+    /// only return these stations. All stations are still part of the calculation since
+    /// they compete for the same houses, this only keeps large responses small.
+    /// Left out of the cache key, the cached result covers all stations.
+    #[serde(skip_serializing)]
+    station_ids: Option<Vec<String>>,
+    /// overrides `routing.snap_tolerance_m` for the `X-OLP-Unsnapped-Stations` header,
+    /// left out of the cache key as it doesn't change the coverage
+    #[serde(skip_serializing)]
+    snap_tolerance_m: Option<f64>,
+}
+
+impl StationInfoRequest {
+    /// Replaces the stations with those of the requested line
+    fn with_line_stations(mut self, lines: &RwLock<Lines>) -> Result<Self, OLPError> {
+        let Some(line) = self.line else {
+            return Ok(self);
+        };
+        if !self.stations.is_empty() {
+            return Err(OLPError::InvalidInput(
+                "either stations or line can be given, not both".to_owned(),
+            ));
+        }
+        self.stations = lines
+            .read()
+            .map_err(OLPError::from_error)?
+            .get(&line)?
+            .get_stations()
+            .to_vec();
+        Ok(self)
+    }
+
+    /// Rejects requests without stations. Not part of `validate`, as one of the two
+    /// scenarios of a comparison may be empty.
+    fn validate_not_empty(&self) -> Result<(), OLPError> {
+        validation::validate_not_empty(self.stations.len(), "stations (or the stations of line)")
+    }
+
+    /// Checks shared by the calculations of the coverage of the stations. Takes the
+    /// locked layers, so the stations are checked against the data the coverage is
+    /// calculated on.
+    fn validate(&self, layers: &Layers, config: &Config) -> Result<CoverageDefaults, OLPError> {
+        validation::validate_station_count(self.stations.len(), config)?;
+        let defaults = CoverageDefaults::from_config(config)?;
+        self.validate_weights()?;
+        validation::validate_separation_distance(self.separation_distance)?;
+        layers.ensure_loaded()?;
+        validation::validate_points(
+            self.stations.iter().map(|station| &station.location),
+            validation::data_bounds(config, layers).as_ref(),
+        )?;
+        Ok(defaults)
+    }
+
+    fn validate_weights(&self) -> Result<(), OLPError> {
+        if let Some(scale) = &self.scale {
+            scale.validate()?;
+        }
+        if let Some(weights) = &self.house_type_weights {
+            weights.validate()?;
+        }
+        Ok(())
+    }
+
+    fn weigh_houses(&self, layers: &mut [Layer]) {
+        if let Some(weights) = &self.house_type_weights {
+            for layer in layers {
+                layer.weight_houses(|house_type| weights.factor(house_type));
+            }
+        }
+    }
+
+    fn method_for<'a>(
+        &'a self,
+        layer_type: &LayerType,
+        defaults: &'a CoverageDefaults,
+    ) -> &'a Method {
+        self.method
+            .as_ref()
+            .and_then(|method| method.for_layer_type(layer_type))
+            .unwrap_or(&defaults.method)
+    }
+
+    fn routing_for<'a>(
+        &'a self,
+        layer_type: &LayerType,
+        defaults: &'a CoverageDefaults,
+    ) -> &'a Routing {
+        self.layer_routing
+            .as_ref()
+            .and_then(|layer_routing| layer_routing.get(layer_type))
+            .or(self.routing.as_ref())
+            .unwrap_or(&defaults.routing)
+    }
 }
 
 #[derive(Deserialize)]
@@ -37,105 +196,1297 @@ struct FindStationRequest {
     route: Vec<Point>,
     method: Option<Method>,
     routing: Option<Routing>,
+    /// GeoJSON polygon new stations have to be placed in
+    service_area: Option<geojson::Geometry>,
+    /// places along the route new stations can't be placed at
+    #[serde(default)]
+    exclusions: Vec<Exclusion>,
+    /// radius in meters the new station is optimized for, defaults to 300
+    search_radius: Option<f64>,
+    /// radii in meters to report the coverage of the found station for
+    #[serde(default)]
+    radii: Vec<f64>,
+    /// return this many ranked positions instead of only the best one
+    count: Option<usize>,
+    /// multiplier for the inhabitants the positions are ranked by, globally or per layer
+    /// type, e.g. to prefer positions near workplaces. Also accepted as `type_weights`,
+    /// requests with both are rejected as having a duplicate `scale` field.
+    #[serde(alias = "type_weights")]
+    scale: Option<InhabitantsScale>,
+    /// minutes of walking, replaces search_radius and the coverage of the stations
+    time_budget: Option<f64>,
+    /// minutes between two departures at the new station
+    headway: Option<f64>,
+    /// count the expected wait of half the headway against the time budget
+    #[serde(default)]
+    include_wait: bool,
 }
 
+#[derive(Deserialize)]
+struct RidershipEstimateRequest {
+    #[serde(flatten)]
+    station_info: StationInfoRequest,
+    capture_rates: Option<HashMap<LayerType, f64>>,
+}
+
+/// Two station sets calculated with the same options
+#[derive(Deserialize)]
+struct ScenarioComparisonRequest {
+    a: Vec<Station>,
+    b: Vec<Station>,
+    #[serde(flatten)]
+    station_info: StationInfoRequest,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+enum StationInfoGrouping {
+    #[default]
+    #[serde(rename = "station")]
+    Station,
+    #[serde(rename = "layer_type")]
+    LayerType,
+}
+
+#[derive(Deserialize)]
+struct StationInfoQuery {
+    group_by: Option<StationInfoGrouping>,
+}
+
+type StationInfoResponse = Either<InhabitantsMap, web::Json<InhabitantsByLayerType>>;
+
+/// The mean access distance is sent in the `X-OLP-Mean-Access-Distance` header to
+/// keep the body a map of stations, stations too far from the street network to be
+/// routed from reliably are listed in `X-OLP-Unsnapped-Stations`
 async fn station_info(
     request: web::Json<StationInfoRequest>,
+    query: web::Query<StationInfoQuery>,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+    coverage_cache: web::Data<Mutex<CoverageCache>>,
+) -> Result<CustomizeResponder<StationInfoResponse>, OLPError> {
+    let request = request.into_inner().with_line_stations(&lines)?;
+    request.validate_not_empty()?;
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let (mut inhabitants, access_distances) =
+        calculate_inhabitants(&request, &layers, &config, &coverage_cache)?;
+    let unsnapped = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        coverage::unsnapped_stations(
+            &request.stations,
+            &layers,
+            |layer_type| request.routing_for(layer_type, &defaults),
+            coverage::snap_tolerance(&config, request.snap_tolerance_m)?,
+        )
+    };
+    for id in &unsnapped {
+        log::warn!(
+            "station {} is further than the snap tolerance from the street network",
+            id
+        );
+    }
+    if let Some(station_ids) = &request.station_ids {
+        if let Some(unknown) = station_ids
+            .iter()
+            .find(|id| !request.stations.iter().any(|station| &station.id == *id))
+        {
+            return Err(OLPError::InvalidInput(format!(
+                "station_ids contains {}, which is not one of the stations",
+                unknown
+            )));
+        }
+        inhabitants.retain_stations(station_ids);
+    }
+    let response = match query.group_by.unwrap_or_default() {
+        StationInfoGrouping::Station => Either::Left(inhabitants),
+        StationInfoGrouping::LayerType => {
+            Either::Right(web::Json(InhabitantsByLayerType::from(&inhabitants)))
+        }
+    };
+    let mut response = response.customize();
+    if !unsnapped.is_empty() {
+        response = response.insert_header(("X-OLP-Unsnapped-Stations", unsnapped.join(",")));
+    }
+    Ok(match access_distances.total().mean_distance() {
+        Some(distance) => {
+            response.insert_header(("X-OLP-Mean-Access-Distance", format!("{:.1}", distance)))
+        }
+        None => response,
+    })
+}
+
+/// Covered inhabitants of scenario `b` compared to scenario `a`
+async fn compare_scenarios(
+    request: web::Json<ScenarioComparisonRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+    coverage_cache: web::Data<Mutex<CoverageCache>>,
+) -> Result<web::Json<ScenarioComparison>, OLPError> {
+    let ScenarioComparisonRequest {
+        a,
+        b,
+        station_info: mut request,
+    } = request.into_inner();
+    if request.line.is_some() || !request.stations.is_empty() {
+        return Err(OLPError::InvalidInput(
+            "the stations of both scenarios are given as a and b".to_owned(),
+        ));
+    }
+    validation::validate_not_empty(a.len().max(b.len()), "stations of a and b")?;
+    request.stations = a;
+    let (a_inhabitants, _) = calculate_inhabitants(&request, &layers, &config, &coverage_cache)?;
+    let a = std::mem::replace(&mut request.stations, b);
+    let (b_inhabitants, _) = calculate_inhabitants(&request, &layers, &config, &coverage_cache)?;
+    Ok(web::Json(ScenarioComparison::new(
+        &a_inhabitants,
+        &a,
+        &b_inhabitants,
+        &request.stations,
+    )))
+}
+
+async fn access_distance(
+    request: web::Json<StationInfoRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+    coverage_cache: web::Data<Mutex<CoverageCache>>,
+) -> Result<web::Json<AccessDistances>, OLPError> {
+    let request = request.into_inner().with_line_stations(&lines)?;
+    request.validate_not_empty()?;
+    let (_, access_distances) = calculate_inhabitants(&request, &layers, &config, &coverage_cache)?;
+    Ok(web::Json(access_distances))
+}
+
+async fn ridership_estimate(
+    request: web::Json<RidershipEstimateRequest>,
     layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+    coverage_cache: web::Data<Mutex<CoverageCache>>,
+) -> Result<web::Json<RidershipEstimate>, OLPError> {
+    let request = request.into_inner();
+    let station_info = request.station_info.with_line_stations(&lines)?;
+    station_info.validate_not_empty()?;
+    let (inhabitants, _) = calculate_inhabitants(&station_info, &layers, &config, &coverage_cache)?;
+    let capture_rates = CaptureRates::from_config(&config, request.capture_rates.as_ref())?;
+    Ok(web::Json(RidershipEstimate::new(
+        &inhabitants,
+        &capture_rates,
+    )))
+}
+
+fn calculate_inhabitants(
+    request: &StationInfoRequest,
+    layers: &RwLock<Layers>,
+    config: &Config,
+    cache: &Mutex<CoverageCache>,
+) -> Result<(InhabitantsMap, AccessDistances), OLPError> {
+    let start = Instant::now();
+    let (defaults, generation, mut merged_layers) = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        // read with the layers locked, so it is the generation of the merged layers
+        let generation = layers::generation();
+        let defaults = request.validate(&layers, config)?;
+        let key = CoverageCache::key(request)?;
+        if let Some(result) = cache
+            .lock()
+            .map_err(OLPError::from_error)?
+            .get(key, generation)
+        {
+            log::debug!("reusing cached coverage for request {:016x}", key);
+            return Ok(result);
+        }
+        (defaults, generation, layers.merged_by_type(request.merge))
+    };
+    request.weigh_houses(&mut merged_layers);
+    let (stations, duplicates) = station::deduplicate(&request.stations);
+    duplicates.log_warnings();
+    let stations = coverage::with_time_budget(
+        &stations,
+        request.time_budget,
+        request.include_wait,
+        request.routing.as_ref().unwrap_or(&defaults.routing),
+        config,
+    )?;
+    // par_iter keeps the order of the layers when collecting
+    let (coverage_info, timings): (Vec<(LayerType, CoverageMap)>, Vec<LayerTiming>) = merged_layers
+        .par_iter()
+        .map(|layer| {
+            let (coverage_map, timing) = LayerTiming::measure(
+                "coverage",
+                layer.get_type().to_string(),
+                layer.get_centroids().len(),
+                stations.len(),
+                || {
+                    coverage::houses_for_stations(
+                        &stations,
+                        layer.get_centroids(),
+                        Some(layer.get_index()),
+                        request.method_for(layer.get_type(), &defaults),
+                        request.routing_for(layer.get_type(), &defaults),
+                        request
+                            .routing_for(layer.get_type(), &defaults)
+                            .streets(layer),
+                        layer.get_barriers(),
+                        request.separation_distance,
+                    )
+                },
+            );
+            ((*layer.get_type(), coverage_map), timing)
+        })
+        .unzip();
+    let coverage_slice: &[(LayerType, CoverageMap)] = &coverage_info;
+    let mut inhabitants = population::InhabitantsMap::from(coverage_slice);
+    if request.estimate_riders {
+        inhabitants.estimate_riders(
+            coverage_slice,
+            &CaptureRates::from_config(config, None)?,
+            &CaptureCurve::from_config(config)?,
+        );
+    }
+    if request.include_buildings {
+        inhabitants.include_buildings(coverage_slice);
+    }
+    if let Some(scale) = &request.scale {
+        inhabitants.scale(scale);
+    }
+    inhabitants.apply_capacities(&stations);
+    for (duplicate, original) in &duplicates.merged {
+        inhabitants.alias(duplicate, original);
+    }
+    let result = (inhabitants, AccessDistances::from(coverage_slice));
+    cache.lock().map_err(OLPError::from_error)?.insert(
+        CoverageCache::key(request)?,
+        generation,
+        result.clone(),
+    );
+    timing::log_summary("coverage", start, &timings);
+    Ok(result)
+}
+
+/// Inhabitants only covered by each station, i.e. those lost if it was removed
+async fn station_removal_impact(
+    request: web::Json<StationInfoRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+    cache: web::Data<Mutex<RemovalImpactCache>>,
 ) -> Result<InhabitantsMap, OLPError> {
-    let merged_layers = layers
-        .read()
+    let request = request.into_inner().with_line_stations(&lines)?;
+    request.validate_not_empty()?;
+    let key = CoverageCache::key(&request)?;
+    let (defaults, generation, mut merged_layers) = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        let generation = layers::generation();
+        let defaults = request.validate(&layers, &config)?;
+        if let Some(impact) = cache
+            .lock()
+            .map_err(OLPError::from_error)?
+            .get(key, generation)
+        {
+            log::debug!("reusing cached removal impact for request {:016x}", key);
+            return Ok(impact);
+        }
+        (defaults, generation, layers.merged_by_type(request.merge))
+    };
+    request.weigh_houses(&mut merged_layers);
+    let (stations, duplicates) = station::deduplicate(&request.stations);
+    duplicates.log_warnings();
+    let stations = &coverage::with_time_budget(
+        &stations,
+        request.time_budget,
+        request.include_wait,
+        request.routing.as_ref().unwrap_or(&defaults.routing),
+        &config,
+    )?;
+    let covered_inhabitants = |stations: &[Station], layer: &Layer| -> u32 {
+        coverage::houses_for_stations(
+            stations,
+            layer.get_centroids(),
+            Some(layer.get_index()),
+            request.method_for(layer.get_type(), &defaults),
+            request.routing_for(layer.get_type(), &defaults),
+            request
+                .routing_for(layer.get_type(), &defaults)
+                .streets(layer),
+            layer.get_barriers(),
+            request.separation_distance,
+        )
+        .0
+        .values()
+        .map(|coverage| coverage.inhabitants)
+        .sum()
+    };
+    // the exclusive coverage of a station is whatever the remaining stations can't pick up
+    let impact: Vec<(String, LayerType, u32)> = merged_layers
+        .par_iter()
+        .flat_map(|layer| {
+            let total = covered_inhabitants(stations, layer);
+            (0..stations.len()).into_par_iter().map(move |removed| {
+                let remaining: Vec<Station> = stations
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != removed)
+                    .map(|(_, station)| station.clone())
+                    .collect();
+                (
+                    stations[removed].id.clone(),
+                    *layer.get_type(),
+                    total.saturating_sub(covered_inhabitants(&remaining, layer)),
+                )
+            })
+        })
+        .collect();
+    let mut impact: InhabitantsMap = impact.into_iter().collect();
+    if let Some(scale) = &request.scale {
+        impact.scale(scale);
+    }
+    for (duplicate, original) in &duplicates.merged {
+        impact.alias(duplicate, original);
+    }
+    cache
+        .lock()
         .map_err(OLPError::from_error)?
-        .all_merged_by_type();
-    let coverage_info: Vec<(LayerType, CoverageMap)> = merged_layers
-        .iter()
-        .map(|layer| {
-            log::debug!("calculating for layer type: {}", layer.get_type());
-            (
-                layer.get_type().clone(),
-                coverage::houses_for_stations(
-                    &request.stations,
-                    layer.get_centroids(),
-                    &request.method.as_ref().unwrap_or(&Method::Relative),
-                    &request.routing.as_ref().unwrap_or(&Routing::Osm),
-                    layer.get_streets(),
-                ),
+        .insert(key, generation, impact.clone());
+    Ok(impact)
+}
+
+/// Exclusive and shared inhabitants of every station, helps to decide whether one of
+/// two close stations can be dropped
+async fn coverage_overlap(
+    request: web::Json<StationInfoRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+) -> Result<CoverageOverlap, OLPError> {
+    let request = request.into_inner().with_line_stations(&lines)?;
+    request.validate_not_empty()?;
+    let (defaults, mut merged_layers) = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        let defaults = request.validate(&layers, &config)?;
+        (defaults, layers.merged_by_type(request.merge))
+    };
+    request.weigh_houses(&mut merged_layers);
+    let (stations, duplicates) = station::deduplicate(&request.stations);
+    duplicates.log_warnings();
+    let stations = coverage::with_time_budget(
+        &stations,
+        request.time_budget,
+        request.include_wait,
+        request.routing.as_ref().unwrap_or(&defaults.routing),
+        &config,
+    )?;
+    let overlaps: Vec<(String, LayerType, coverage::StationOverlap)> = merged_layers
+        .par_iter()
+        .flat_map(|layer| {
+            let method = request.method_for(layer.get_type(), &defaults);
+            let routing = request.routing_for(layer.get_type(), &defaults);
+            let coverage_map = coverage::houses_for_stations(
+                &stations,
+                layer.get_centroids(),
+                Some(layer.get_index()),
+                method,
+                routing,
+                routing.streets(layer),
+                layer.get_barriers(),
+                request.separation_distance,
+            );
+            coverage::station_overlaps(
+                &coverage_map,
+                &stations,
+                method,
+                routing,
+                routing.streets(layer),
             )
+            .into_iter()
+            .map(|(station, overlap)| (station, *layer.get_type(), overlap))
+            .collect::<Vec<_>>()
         })
         .collect();
-    let coverage_slice: &[(LayerType, CoverageMap)] = &coverage_info;
-    Ok(population::InhabitantsMap::from(coverage_slice))
+    let mut overlap: CoverageOverlap = overlaps.into_iter().collect();
+    if let Some(scale) = &request.scale {
+        overlap.scale(scale);
+    }
+    for (duplicate, original) in &duplicates.merged {
+        overlap.alias(duplicate, original);
+    }
+    Ok(overlap)
 }
 
+/// The route is searched every `find_station.sampling_interval_m` meters (10 by default),
+/// routes giving more than `find_station.max_candidates` positions are rejected.
 async fn find_station(
     request: web::Json<FindStationRequest>,
     layers: web::Data<RwLock<Layers>>,
-) -> Result<OptimalStationResult, OLPError> {
-    let layer = layers.read().map_err(OLPError::from_error)?.all_merged();
-    Ok(station::find_optimal_station(
+    config: web::Data<Config>,
+) -> Result<Either<OptimalStationResult, web::Json<Vec<OptimalStationResult>>>, OLPError> {
+    // stations may be empty when placing the first station of a line
+    validation::validate_not_empty(request.route.len(), "route")?;
+    validation::validate_station_count(request.stations.len(), &config)?;
+    let defaults = CoverageDefaults::from_config(&config)?;
+    if let Some(scale) = &request.scale {
+        scale.validate()?;
+    }
+    if request.count == Some(0) {
+        return Err(OLPError::InvalidInput(
+            "count has to be at least 1".to_owned(),
+        ));
+    }
+    if request.time_budget.is_some() && request.search_radius.is_some() {
+        return Err(OLPError::InvalidInput(
+            "either search_radius or time_budget can be given, not both".to_owned(),
+        ));
+    }
+    let search_radius = match request.time_budget {
+        Some(time_budget) => coverage::travel_time_coverage(
+            time_budget,
+            request.headway,
+            request.include_wait,
+            request.routing.as_ref().unwrap_or(&defaults.routing),
+            &config,
+        )?,
+        None => request.search_radius.unwrap_or(defaults.radius),
+    };
+    if !search_radius.is_finite() || search_radius <= 0.0 {
+        return Err(OLPError::InvalidInput(
+            "search_radius has to be a positive number".to_owned(),
+        ));
+    }
+    if request
+        .radii
+        .iter()
+        .any(|radius| !radius.is_finite() || *radius <= 0.0)
+    {
+        return Err(OLPError::InvalidInput(
+            "radii have to be positive numbers".to_owned(),
+        ));
+    }
+    for exclusion in &request.exclusions {
+        exclusion.validate()?;
+    }
+    let sampling_interval = config
+        .get_float("find_station.sampling_interval_m")
+        .map_err(OLPError::from_error)?;
+    if !sampling_interval.is_finite() || sampling_interval <= 0.0 {
+        return Err(OLPError::GenericError(
+            "find_station.sampling_interval_m has to be a positive number".to_owned(),
+        ));
+    }
+    let max_candidates = max_candidates(&config)?;
+    let (layer, weighted_houses) = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        layers.ensure_loaded()?;
+        let bounds = validation::data_bounds(&config, &layers);
+        validation::validate_points(
+            request.stations.iter().map(|station| &station.location),
+            bounds.as_ref(),
+        )?;
+        validation::validate_points(&request.route, bounds.as_ref())?;
+        let weighted_houses = request
+            .scale
+            .as_ref()
+            .map(|scale| layers.weighted_centroids(|layer_type| scale.factor(layer_type)));
+        (layers.all_merged(), weighted_houses)
+    };
+    let houses = weighted_houses.as_deref().unwrap_or(layer.get_centroids());
+    let service_area = request
+        .service_area
+        .clone()
+        .map(geometry::multi_polygon_from_geojson)
+        .transpose()?;
+    let (stations, duplicates) = station::deduplicate(&request.stations);
+    duplicates.log_warnings();
+    let routing = request.routing.as_ref().unwrap_or(&defaults.routing);
+    let stations = coverage::with_time_budget(
+        &stations,
+        request.time_budget,
+        request.include_wait,
+        routing,
+        &config,
+    )?;
+    let options = FindStationOptions {
+        coverage: search_radius,
+        method: request.method.as_ref().unwrap_or(&defaults.method),
+        routing,
+        service_area: service_area.as_ref(),
+        exclusions: &request.exclusions,
+        radii: &request.radii,
+        sampling_interval,
+        max_candidates,
+    };
+    let start = Instant::now();
+    // without a count the single result is returned as before
+    let (result, timing) = LayerTiming::measure(
+        "find_station",
+        "merged".to_owned(),
+        houses.len(),
+        stations.len(),
+        || match request.count {
+            None => station::find_optimal_station(
+                request.route.clone(),
+                houses,
+                &stations,
+                routing.streets(&layer),
+                &options,
+            )
+            .map(Either::Left),
+            Some(count) => station::find_optimal_stations(
+                request.route.clone(),
+                houses,
+                &stations,
+                routing.streets(&layer),
+                &options,
+                count,
+            )
+            .map(|results| Either::Right(web::Json(results))),
+        },
+    );
+    timing::log_summary("find_station", start, &[timing]);
+    result
+}
+
+#[derive(Deserialize)]
+struct StationMatrixRequest {
+    stations: Vec<Station>,
+    routing: Option<Routing>,
+}
+
+#[derive(Serialize)]
+struct StationMatrix {
+    /// station ids in the order of the rows and columns
+    stations: Vec<String>,
+    /// distances in meters, `null` if there is no connection between two stations
+    distances: Vec<Vec<Option<f64>>>,
+}
+
+async fn station_matrix(
+    request: web::Json<StationMatrixRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<web::Json<StationMatrix>, OLPError> {
+    validation::validate_not_empty(request.stations.len(), "stations")?;
+    validation::validate_station_count(request.stations.len(), &config)?;
+    let locations: Vec<Point> = request
+        .stations
+        .iter()
+        .map(|station| station.location)
+        .collect();
+    {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        validation::validate_points(
+            &locations,
+            validation::data_bounds(&config, &layers).as_ref(),
+        )?;
+    }
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let routing = request.routing.as_ref().unwrap_or(&defaults.routing);
+    let distances = match routing {
+        Routing::Naive => locations
+            .iter()
+            .map(|a| {
+                locations
+                    .iter()
+                    .map(|b| Some(a.haversine_distance(b)))
+                    .collect()
+            })
+            .collect(),
+        Routing::Osm | Routing::Network | Routing::Bike => {
+            let layer = {
+                let layers = layers.read().map_err(OLPError::from_error)?;
+                layers.ensure_loaded()?;
+                layers.all_merged()
+            };
+            geometry::network_distance_matrix(routing.streets(&layer), &locations)
+        }
+    };
+    Ok(web::Json(StationMatrix {
+        stations: request
+            .stations
+            .iter()
+            .map(|station| station.id.clone())
+            .collect(),
+        distances,
+    }))
+}
+
+#[derive(Deserialize)]
+struct MinimalStationsRequest {
+    route: Vec<Point>,
+    /// share of the inhabitants in reach of the route to cover, between 0 and 1
+    target: f64,
+    coverage: Option<f64>,
+    /// defaults to the coverage radius
+    min_spacing: Option<f64>,
+    routing: Option<Routing>,
+}
+
+/// Positions along a route searched at most, see `find_station.max_candidates`
+fn max_candidates(config: &Config) -> Result<usize, OLPError> {
+    Ok(config
+        .get_int("find_station.max_candidates")
+        .map_err(OLPError::from_error)?
+        .max(0) as usize)
+}
+
+/// Routes giving more than `find_station.max_candidates` positions are rejected
+async fn find_minimal_stations(
+    request: web::Json<MinimalStationsRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<web::Json<MinimalStationsResult>, OLPError> {
+    validation::validate_not_empty(request.route.len(), "route")?;
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let coverage = request.coverage.unwrap_or(defaults.radius);
+    validation::validate_radius(coverage, "coverage")?;
+    let layer = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        layers.ensure_loaded()?;
+        validation::validate_points(
+            &request.route,
+            validation::data_bounds(&config, &layers).as_ref(),
+        )?;
+        layers.all_merged()
+    };
+    let routing = request.routing.as_ref().unwrap_or(&defaults.routing);
+    station::find_minimal_stations(
         request.route.clone(),
-        300f64,
         layer.get_centroids(),
-        &request.stations,
-        &request.method.as_ref().unwrap_or(&Method::Relative),
-        &request.routing.as_ref().unwrap_or(&Routing::Osm),
-        layer.get_streets(),
-    ))
+        routing.streets(&layer),
+        &MinimalStationsOptions {
+            coverage,
+            target: request.target,
+            min_spacing: request.min_spacing.unwrap_or(coverage),
+            routing,
+            max_candidates: max_candidates(&config)?,
+        },
+    )
+    .map(web::Json)
+}
+
+#[derive(Deserialize)]
+struct StationSetRequest {
+    route: Vec<Point>,
+    /// number of stations to place
+    count: usize,
+    coverage: Option<f64>,
+    /// defaults to the coverage radius
+    min_spacing: Option<f64>,
+    routing: Option<Routing>,
+}
+
+/// Routes giving more than `find_station.max_candidates` positions are rejected
+async fn find_station_set(
+    request: web::Json<StationSetRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<web::Json<StationSetResult>, OLPError> {
+    if request.count == 0 {
+        return Err(OLPError::InvalidInput(
+            "count has to be at least 1".to_owned(),
+        ));
+    }
+    validation::validate_station_count(request.count, &config)?;
+    validation::validate_not_empty(request.route.len(), "route")?;
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let coverage = request.coverage.unwrap_or(defaults.radius);
+    validation::validate_radius(coverage, "coverage")?;
+    let layer = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        layers.ensure_loaded()?;
+        validation::validate_points(
+            &request.route,
+            validation::data_bounds(&config, &layers).as_ref(),
+        )?;
+        layers.all_merged()
+    };
+    let routing = request.routing.as_ref().unwrap_or(&defaults.routing);
+    station::find_optimal_station_set(
+        request.route.clone(),
+        layer.get_centroids(),
+        routing.streets(&layer),
+        &StationSetOptions {
+            coverage,
+            count: request.count,
+            min_spacing: request.min_spacing.unwrap_or(coverage),
+            routing,
+            max_candidates: max_candidates(&config)?,
+        },
+    )
+    .map(web::Json)
+}
+
+#[derive(Deserialize)]
+struct NearestStreetsQuery {
+    k: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct NearestStreet {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: LineString,
+    from: NodeId,
+    to: NodeId,
+    distance: f64,
+}
+
+static MAX_NEAREST_STREETS: usize = 20;
+
+/// The `k` street segments nearest to the point as a GeoJSON FeatureCollection. The
+/// path takes the coordinates in `lon/lat` order like the other endpoints, `k` defaults
+/// to 5 and is capped at 20.
+async fn nearest_streets(
+    coords: web::Path<(f64, f64)>,
+    query: web::Query<NearestStreetsQuery>,
+    layers: web::Data<RwLock<Layers>>,
+) -> Result<HttpResponse, OLPError> {
+    let (lon, lat) = coords.into_inner();
+    let point = Point::new(lon, lat);
+    validation::validate_point(&point, None)?;
+    let k = query.k.unwrap_or(5).min(MAX_NEAREST_STREETS);
+    let layers = layers.read().map_err(OLPError::from_error)?;
+    layers.ensure_loaded()?;
+    // segments along the border of two areas are part of both layers
+    let mut seen = HashSet::new();
+    let mut nearest: Vec<NearestStreet> = Vec::new();
+    for layer in layers.iter() {
+        let streets = layer.get_streets();
+        for (from, to, distance) in layer.get_street_index().nearest(&point, k) {
+            nearest.push(NearestStreet {
+                geometry: LineString::from(vec![streets.nodes[&from], streets.nodes[&to]]),
+                from,
+                to,
+                distance,
+            });
+        }
+    }
+    nearest.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    nearest.retain(|street| seen.insert((street.from.min(street.to), street.from.max(street.to))));
+    nearest.truncate(k);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(to_feature_collection_string(&nearest).map_err(OLPError::from_error)?))
+}
+
+#[derive(Deserialize)]
+struct DebugStreetsQuery {
+    /// `west,south,east,north` in degrees, only edges with both ends inside are returned
+    bbox: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DebugStreetEdge {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: LineString,
+    from: NodeId,
+    to: NodeId,
+    /// length of the segment in meters as used for routing
+    length: f64,
+}
+
+fn parse_bbox(bbox: &str) -> Result<Rect, OLPError> {
+    let invalid = || OLPError::InvalidInput("bbox has to be west,south,east,north".to_owned());
+    let coordinates: Vec<f64> = bbox
+        .split(',')
+        .map(|coordinate| coordinate.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let [west, south, east, north] = coordinates[..] else {
+        return Err(invalid());
+    };
+    validation::validate_bbox([west, south, east, north])
+}
+
+/// The street graph of all layers as a GeoJSON FeatureCollection of its segments, to
+/// check which network was built from the data. Requests covering more than
+/// `debug.max_street_nodes` nodes, e.g. the whole graph without a `bbox`, are refused.
+async fn debug_streets(
+    query: web::Query<DebugStreetsQuery>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, OLPError> {
+    let bbox = query.bbox.as_deref().map(parse_bbox).transpose()?;
+    let max_nodes = config
+        .get_int("debug.max_street_nodes")
+        .map_err(OLPError::from_error)? as usize;
+    let layer = layers.read().map_err(OLPError::from_error)?.all_merged();
+    let streets = layer.get_streets();
+    let inside = |node: &NodeId| match (bbox, streets.nodes.get(node)) {
+        (Some(bbox), Some(location)) => bbox.intersects(location),
+        (None, Some(_)) => true,
+        (_, None) => false,
+    };
+    let nodes = streets.nodes.keys().filter(|node| inside(node)).count();
+    if nodes > max_nodes {
+        return Err(OLPError::PayloadTooLarge(format!(
+            "{} street nodes are in the requested area, at most {} are returned, pass a smaller bbox",
+            nodes, max_nodes
+        )));
+    }
+    let edges: Vec<DebugStreetEdge> = streets
+        .streetgraph
+        .all_edges()
+        .filter(|(from, to, _)| inside(from) && inside(to))
+        .map(|(from, to, length)| DebugStreetEdge {
+            geometry: LineString::from(vec![streets.nodes[&from], streets.nodes[&to]]),
+            from,
+            to,
+            length: *length,
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(to_feature_collection_string(&edges).map_err(OLPError::from_error)?))
+}
+
+#[derive(Deserialize)]
+struct IsochroneRequest {
+    /// center of the isochrone, alternatively `station` of the stored `line`
+    center: Option<Point>,
+    line: Option<Uuid>,
+    station: Option<String>,
+    /// minutes of walking
+    time_budget: f64,
+    /// defaults to `coverage.walking_speed_kmh`
+    walking_speed_kmh: Option<f64>,
+}
+
+impl IsochroneRequest {
+    fn center(&self, lines: &RwLock<Lines>) -> Result<Point, OLPError> {
+        match (self.center, self.line, &self.station) {
+            (Some(center), None, None) => Ok(center),
+            (None, Some(line), Some(station)) => lines
+                .read()
+                .map_err(OLPError::from_error)?
+                .get(&line)?
+                .get_stations()
+                .iter()
+                .find(|other| &other.id == station)
+                .map(|station| station.location)
+                .ok_or_else(|| OLPError::NotFound(format!("station {} on line {}", station, line))),
+            _ => Err(OLPError::InvalidInput(
+                "either center or line and station have to be given".to_owned(),
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IsochroneEdge {
+    #[serde(serialize_with = "serialize_geometry")]
+    geometry: LineString,
+    from: NodeId,
+    to: NodeId,
+    /// walking distance from the center to the farther end of the segment
+    distance: f64,
+}
+
+/// The street segments reachable within the time budget as a GeoJSON FeatureCollection.
+/// The walking distance is capped at `isochrone.max_distance` meters (5 km by default,
+/// about an hour of walking) so large budgets don't expand over the whole street graph.
+async fn isochrone(
+    request: web::Json<IsochroneRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, OLPError> {
+    let center = request.center(&lines)?;
+    let walking_speed_kmh = match request.walking_speed_kmh {
+        Some(speed) => speed,
+        None => config
+            .get_float("coverage.walking_speed_kmh")
+            .map_err(OLPError::from_error)?,
+    };
+    if !request.time_budget.is_finite() || request.time_budget <= 0.0 {
+        return Err(OLPError::InvalidInput(
+            "time_budget has to be a positive number of minutes".to_owned(),
+        ));
+    }
+    if !walking_speed_kmh.is_finite() || walking_speed_kmh <= 0.0 {
+        return Err(OLPError::InvalidInput(
+            "walking_speed_kmh has to be a positive number".to_owned(),
+        ));
+    }
+    let max_distance = config
+        .get_float("isochrone.max_distance")
+        .map_err(OLPError::from_error)?;
+    let distance =
+        coverage::walking_distance(request.time_budget, walking_speed_kmh).min(max_distance);
+    let layer = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        layers.ensure_loaded()?;
+        validation::validate_point(&center, validation::data_bounds(&config, &layers).as_ref())?;
+        layers.all_merged()
+    };
+    let streets = layer.get_streets();
+    let edges: Vec<IsochroneEdge> = geometry::reachable_edges(streets, &center, distance)
+        .into_iter()
+        .map(|(from, to, distance)| IsochroneEdge {
+            geometry: LineString::from(vec![streets.nodes[&from], streets.nodes[&to]]),
+            from,
+            to,
+            distance,
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("application/geo+json")
+        .body(to_feature_collection_string(&edges).map_err(OLPError::from_error)?))
+}
+
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    /// short hash of the commit the binary was built from, `unknown` outside of git
+    git_hash: &'static str,
+    /// unix seconds, see `build.rs`
+    build_timestamp: &'static str,
+}
+
+async fn version() -> web::Json<VersionInfo> {
+    web::Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP"),
+    })
 }
 
 async fn health() -> &'static str {
     "ok"
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    setup_logger().expect("failed to initialize logger");
-
-    info!("starting openlineplanner backend");
+/// Ready once the streets and buildings of at least one layer are loaded. Answers
+/// directly instead of through `OLPError`, so frequent probes don't fill the error log.
+async fn ready(layers: web::Data<RwLock<Layers>>) -> HttpResponse {
+    match layers.read().map(|layers| layers.ensure_loaded()) {
+        Ok(Ok(())) => HttpResponse::Ok().body("ok"),
+        Ok(Err(e)) => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
 
-    #[rustfmt::skip]
-    let config = Config::builder()
+/// Defaults of all settings, `Config.toml` overrides them
+#[rustfmt::skip]
+fn config_defaults() -> ConfigBuilder<DefaultState> {
+    Config::builder()
+        .set_default("log.level", "info").unwrap()
+        .set_default("server.host", "0.0.0.0").unwrap()
+        .set_default("server.port", 8080).unwrap()
         .set_default("cache.dir", "./cache/").unwrap()
         .set_default("data.dir", "./data/").unwrap()
+        .set_default("cors.allow_any", cfg!(debug_assertions)).unwrap()
+        .set_default("cors.allowed_origins", vec!["https://openlineplanner.com", "https://test.openlineplanner.com"]).unwrap()
+        .set_default("auth.api_key", "").unwrap()
+        .set_default("api.envelope", false).unwrap()
+        .set_default("tiles.max_cached_results", 16).unwrap()
+        .set_default("tiles.max_cached_tiles", 4096).unwrap()
+        .set_default("state.max_import_mb", 512).unwrap()
+        .set_default("overpass.circuit_breaker.failures", 3).unwrap()
+        .set_default("overpass.circuit_breaker.cooldown_secs", 60).unwrap()
+        .set_default("overpass.connect_timeout_secs", 10).unwrap()
+        .set_default("overpass.timeout_secs", 60).unwrap()
+        .set_default("validation.max_stations", 500).unwrap()
+        .set_default("coverage.walking_speed_kmh", 4.8).unwrap()
+        .set_default("coverage.cycling_speed_kmh", 15.0).unwrap()
+        .set_default("coverage.cache_entries", 64).unwrap()
+        .set_default("coverage.area_concavity", 10).unwrap()
+        .set_default("coverage.default_method", "relative").unwrap()
+        .set_default("coverage.default_routing", "osm").unwrap()
+        .set_default("coverage.default_radius", 300.0).unwrap()
+        .set_default("isochrone.max_distance", 5000.0).unwrap()
+        .set_default("find_station.sampling_interval_m", 10.0).unwrap()
+        .set_default("find_station.max_candidates", 20_000).unwrap()
+        .set_default("debug.max_street_nodes", 20_000).unwrap()
+        .set_default("population.grid_max_cells", 10_000).unwrap()
+        .set_default("routing.max_centroid_snap_distance", 200.0).unwrap()
+        .set_default("routing.import_barriers", false).unwrap()
+        .set_default("ratelimit.per_minute", 30).unwrap()
+        .set_default("ridership.capture_rate.residential", 0.1).unwrap()
+        .set_default("ridership.capture_rate.workplace", 0.05).unwrap()
+        .set_default("ridership.capture_rate.shopping", 0.02).unwrap()
+        .set_default("ridership.distance_decay.full_rate_distance", 200.0).unwrap()
+        .set_default("ridership.distance_decay.zero_rate_distance", 1000.0).unwrap()
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let config = config_defaults()
         .add_source(config::File::with_name("Config.toml").required(false))
         .build()
         .unwrap();
 
+    let log_level = config.get_string("log.level").unwrap();
+    let level = log_level.parse::<log::LevelFilter>();
+    setup_logger(*level.as_ref().unwrap_or(&log::LevelFilter::Info))
+        .expect("failed to initialize logger");
+
+    info!(
+        "starting openlineplanner backend {} ({})",
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH")
+    );
+    match level {
+        Ok(level) => info!("log level: {}", level),
+        Err(_) => log::warn!("unknown log.level {:?}, logging at info", log_level),
+    }
+
+    layers::configure_circuit_breaker(
+        config.get_int("overpass.circuit_breaker.failures").unwrap() as u32,
+        Duration::from_secs(
+            config
+                .get_int("overpass.circuit_breaker.cooldown_secs")
+                .unwrap() as u64,
+        ),
+    );
+    layers::configure_timeouts(
+        Duration::from_secs(config.get_int("overpass.connect_timeout_secs").unwrap() as u64),
+        Duration::from_secs(config.get_int("overpass.timeout_secs").unwrap() as u64),
+    );
+    let address = bind_address(&config)?;
+    validate_counts(&config)?;
+    if let Err(e) = CoverageDefaults::from_config(&config) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, e.to_string()));
+    }
+    let cors_allow_any = config.get_bool("cors.allow_any").unwrap();
+    let cors_allowed_origins: Vec<String> = config.get("cors.allowed_origins").unwrap();
     let layers = load_layers(&config);
+    let lines = line::load_lines(&config);
     let config = web::Data::new(config);
+    let tile_cache = web::Data::new(Mutex::new(TileCache::default()));
+    let cache_entries = config.get_int("coverage.cache_entries").unwrap().max(0) as usize;
+    let coverage_cache: web::Data<Mutex<CoverageCache>> =
+        web::Data::new(Mutex::new(CoverageCache::new(cache_entries)));
+    let removal_impact_cache = web::Data::new(Mutex::new(RemovalImpactCache::new(cache_entries)));
+    let overpass_rate_limit =
+        RateLimit::new(config.get_int("ratelimit.per_minute").unwrap().max(0) as u32);
+    let import_barriers = config.get_bool("routing.import_barriers").unwrap();
+    let state_import_limit = config.get_int("state.max_import_mb").unwrap() as usize * 1024 * 1024;
 
     log::info!("loading data done");
 
+    let (shutdown_layers, shutdown_lines, shutdown_config) =
+        (layers.clone(), lines.clone(), config.clone());
     HttpServer::new(move || {
-        #[cfg(debug_assertions)]
-        let cors = Cors::permissive();
-        #[cfg(not(debug_assertions))]
-        let cors = Cors::default()
-            .allowed_origin("https://openlineplanner.com")
-            .allowed_origin("https://test.openlineplanner.com")
-            .allowed_methods(vec!["GET", "POST", "DELETE", "PUT"])
-            .max_age(3600);
+        let cors = cors_middleware(&cors_allowed_origins, cors_allow_any);
 
+        // the last middleware wraps all others, CORS headers are added to the
+        // compressed response and preflight requests are answered uncompressed
         App::new()
+            .wrap(Compress::default())
             .wrap(cors)
             .app_data(layers.clone())
+            .app_data(lines.clone())
             .app_data(config.clone())
+            .app_data(tile_cache.clone())
+            .app_data(coverage_cache.clone())
+            .app_data(removal_impact_cache.clone())
+            .app_data(web::JsonConfig::default().error_handler(error::invalid_input_handler))
+            .app_data(web::QueryConfig::default().error_handler(error::invalid_input_handler))
+            .app_data(web::PathConfig::default().error_handler(error::invalid_input_handler))
+            .route("/precompute", web::post().to(layers::precompute))
             .route("/station-info", web::post().to(station_info))
+            .route("/station-info/compare", web::post().to(compare_scenarios))
+            .route(
+                "/station-info/access-distance",
+                web::post().to(access_distance),
+            )
+            .route(
+                "/station-removal-impact",
+                web::post().to(station_removal_impact),
+            )
+            .route("/coverage-overlap", web::post().to(coverage_overlap))
+            .route(
+                "/coverage-info/areas",
+                web::post().to(coverage::area_coverage_info),
+            )
+            .route(
+                "/coverage-info/barrier-hotspots",
+                web::post().to(coverage::barrier_hotspots),
+            )
             .route(
                 "/coverage-info/{router}",
                 web::post().to(coverage::coverage_info),
             )
+            .route(
+                "/coverage-info/{router}/geojson",
+                web::post().to(coverage::coverage_areas),
+            )
+            .route(
+                "/population/in-area",
+                web::post().to(coverage::population_in_area),
+            )
+            .route(
+                "/population/grid",
+                web::post().to(coverage::population_grid),
+            )
+            .route(
+                "/station-snapping",
+                web::post().to(coverage::station_snapping),
+            )
+            .route("/ridership-estimate", web::post().to(ridership_estimate))
+            .route("/station-matrix", web::post().to(station_matrix))
+            .route("/isochrone", web::post().to(isochrone))
             .route("/find-station", web::post().to(find_station))
-            .route("/health", web::get().to(health))
-            .service(layers::layers())
-            .service(layers::osm())
+            .route(
+                "/find-station/minimal-set",
+                web::post().to(find_minimal_stations),
+            )
+            .route("/find-station/set", web::post().to(find_station_set))
+            .route(
+                "/nearest-streets/{lon}/{lat}",
+                web::get().to(nearest_streets),
+            )
+            .route("/debug/streets.geojson", web::get().to(debug_streets))
+            .route("/tiles", web::post().to(tiles::create_tile_result))
+            .route(
+                "/tiles/{result}/{z}/{x}/{y}.mvt",
+                web::get().to(tiles::vector_tile),
+            )
+            .route("/export/bundle.zip", web::post().to(export::export_bundle))
+            .route("/export/graph.zip", web::get().to(export::export_graph))
+            .service(
+                web::resource(["/state/export", "/project/export"])
+                    .route(web::get().to(state::export_state)),
+            )
+            .service(
+                web::resource(["/state/import", "/project/import"])
+                    .app_data(web::PayloadConfig::new(state_import_limit))
+                    .wrap(auth::RequireApiKey)
+                    .route(web::post().to(state::import_state)),
+            )
+            .service(web::resource(["/health", "/healthz"]).route(web::get().to(health)))
+            .route("/readyz", web::get().to(ready))
+            .route("/version", web::get().to(version))
+            .service(
+                layers::layers(overpass_rate_limit.clone(), import_barriers)
+                    .wrap(auth::RequireApiKey),
+            )
+            .service(line::lines().wrap(auth::RequireApiKey))
+            .service(
+                web::resource("/admin-areas")
+                    .wrap(overpass_rate_limit.clone())
+                    .route(web::get().to(layers::search_admin_areas)),
+            )
+            .route("/layers", web::get().to(layers::summarize_layers))
+            .route("/datasets", web::get().to(datasets::list_datasets))
+            .route("/data/bounds", web::get().to(layers::data_bounds))
+            .route(
+                "/data/bounds.geojson",
+                web::get().to(layers::data_bounds_geojson),
+            )
+            .service(layers::osm().wrap(overpass_rate_limit.clone()))
     })
-    .bind(("0.0.0.0", 8080))?
+    .bind(&address)
+    .map_err(|e| io::Error::new(e.kind(), format!("failed to bind to {:?}: {}", address, e)))?
     .run()
-    .await
+    .await?;
+
+    // actix stops on SIGINT and SIGTERM after the running requests are done
+    flush_state(&shutdown_layers, &shutdown_lines, &shutdown_config);
+    Ok(())
+}
+
+/// Entries of `cors.allowed_origins` starting with `*.` allow all subdomains of the
+/// rest of the entry, e.g. `*.example.com` allows `https://maps.example.com`.
+/// `cors.allow_any` (the default for debug builds) allows every origin.
+fn cors_middleware(allowed_origins: &[String], allow_any: bool) -> Cors {
+    if allow_any {
+        return Cors::permissive();
+    }
+    let (suffixes, origins): (Vec<&String>, Vec<&String>) = allowed_origins
+        .iter()
+        .partition(|origin| origin.starts_with("*."));
+    let suffixes: Vec<String> = suffixes
+        .into_iter()
+        .map(|suffix| suffix.trim_start_matches('*').to_owned())
+        .collect();
+    origins
+        .into_iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allowed_origin_fn(move |origin, _| {
+            let Some(host) = origin
+                .to_str()
+                .ok()
+                .and_then(|origin| origin.split("://").nth(1))
+            else {
+                return false;
+            };
+            let host = host.split(':').next().unwrap_or_default();
+            suffixes
+                .iter()
+                .any(|suffix| host.ends_with(suffix.as_str()))
+        })
+        .allowed_methods(vec!["GET", "POST", "DELETE", "PUT", "PATCH"])
+        .allowed_headers(vec![
+            header::AUTHORIZATION,
+            header::ACCEPT,
+            header::CONTENT_TYPE,
+        ])
+        .allowed_header("X-API-Key")
+        .expose_headers(vec![
+            "X-OLP-Mean-Access-Distance",
+            "X-OLP-Unsnapped-Stations",
+        ])
+        .max_age(3600)
+}
+
+/// Integer settings that are cast to unsigned types
+static COUNT_KEYS: [&str; 13] = [
+    "state.max_import_mb",
+    "overpass.circuit_breaker.failures",
+    "overpass.circuit_breaker.cooldown_secs",
+    "overpass.connect_timeout_secs",
+    "overpass.timeout_secs",
+    "find_station.max_candidates",
+    "debug.max_street_nodes",
+    "population.grid_max_cells",
+    "validation.max_stations",
+    "tiles.max_cached_results",
+    "tiles.max_cached_tiles",
+    "coverage.cache_entries",
+    "ratelimit.per_minute",
+];
+
+/// Rejects negative values of the `COUNT_KEYS` at startup, and values too large for
+/// an `u32`, instead of wrapping around when they are cast
+fn validate_counts(config: &Config) -> io::Result<()> {
+    let invalid = |key: &str, e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid {}: {}", key, e),
+        )
+    };
+    for key in COUNT_KEYS {
+        let value = config.get_int(key).map_err(|e| invalid(key, &e))?;
+        if u32::try_from(value).is_err() {
+            let range = format!("{} is not between 0 and {}", value, u32::MAX);
+            return Err(invalid(key, &range));
+        }
+    }
+    Ok(())
+}
+
+/// Reads `server.host` and `server.port`, failing with a readable error on invalid values
+fn bind_address(config: &Config) -> io::Result<(String, u16)> {
+    let invalid = |key: &str, e: &dyn std::fmt::Display| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid {}: {}", key, e),
+        )
+    };
+    let host = config
+        .get_string("server.host")
+        .map_err(|e| invalid("server.host", &e))?;
+    if host.trim().is_empty() {
+        return Err(invalid("server.host", &"host must not be empty"));
+    }
+    let port = config
+        .get_int("server.port")
+        .map_err(|e| invalid("server.port", &e))?;
+    let port = u16::try_from(port)
+        .map_err(|_| invalid("server.port", &format!("{} is not a valid port", port)))?;
+    Ok((host, port))
 }
 
-fn setup_logger() -> Result<()> {
+fn setup_logger(level: log::LevelFilter) -> Result<()> {
     let colors = fern::colors::ColoredLevelConfig::new();
 
     fern::Dispatch::new()
@@ -147,15 +1498,733 @@ fn setup_logger() -> Result<()> {
                 message
             ))
         })
-        .level(log::LevelFilter::Info)
+        .level(level)
         .chain(std::io::stdout())
         .apply()?;
     Ok(())
 }
 
+/// Writes the layers and lines to the cache dir on shutdown. Every change is saved
+/// when it is made already, this covers saves that failed then. A lock still held
+/// by a worker that didn't stop is skipped instead of waiting on it forever.
+fn flush_state(layers: &RwLock<Layers>, lines: &RwLock<Lines>, config: &Config) {
+    let dir = PathBuf::from(config.get_string("cache.dir").unwrap());
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::error!("failed to create directory {}: {}", dir.display(), e);
+        return;
+    }
+    match layers.try_read() {
+        Ok(layers) => match persistence::save_layers(&layers, &dir.join("layers")) {
+            Ok(()) => info!("flushed {} layers to {}", layers.len(), dir.display()),
+            Err(e) => log::error!("failed to flush layers: {}", e),
+        },
+        Err(e) => log::error!("not flushing layers: {}", e),
+    }
+    match lines.try_read() {
+        Ok(lines) => match persistence::save_lines(&lines, &dir.join("lines")) {
+            Ok(()) => info!("flushed {} lines to {}", lines.len(), dir.display()),
+            Err(e) => log::error!("failed to flush lines: {}", e),
+        },
+        Err(e) => log::error!("not flushing lines: {}", e),
+    }
+}
+
 fn load_layers(config: &Config) -> web::Data<RwLock<Layers>> {
     let mut path = PathBuf::from(config.get_string("cache.dir").unwrap());
     path.push("layers");
-    let layers = persistence::load_layers(&path).unwrap_or_default();
+    let layers = persistence::load_layers(&path).unwrap_or_else(|err| {
+        log::warn!(
+            "no cached layers loaded from {:?} ({}), starting without data",
+            path,
+            err
+        );
+        Layers::default()
+    });
     web::Data::new(RwLock::new(layers))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, ResponseError};
+    use datatypes::Streets;
+    use layers::PopulatedCentroid;
+
+    fn defaults(config: &[(&str, &str)]) -> CoverageDefaults {
+        let mut builder = Config::builder()
+            .set_default("coverage.default_method", "relative")
+            .unwrap()
+            .set_default("coverage.default_routing", "osm")
+            .unwrap()
+            .set_default("coverage.default_radius", 300.0)
+            .unwrap();
+        for (key, value) in config {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        CoverageDefaults::from_config(&builder.build().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn requests_without_a_method_use_the_default_method() {
+        let defaults = defaults(&[
+            ("coverage.default_method", "absolute"),
+            ("coverage.default_routing", "naive"),
+        ]);
+        let request: StationInfoRequest = serde_json::from_str(r#"{"stations": []}"#).unwrap();
+        assert!(matches!(
+            request.method_for(&LayerType::Residential, &defaults),
+            Method::Absolute
+        ));
+        assert!(matches!(
+            request.routing_for(&LayerType::Residential, &defaults),
+            Routing::Naive
+        ));
+
+        let request: StationInfoRequest =
+            serde_json::from_str(r#"{"stations": [], "method": "relative"}"#).unwrap();
+        assert!(matches!(
+            request.method_for(&LayerType::Residential, &defaults),
+            Method::Relative
+        ));
+    }
+
+    #[test]
+    fn negative_counts_are_rejected() {
+        let config = |value: i64| {
+            let mut builder = Config::builder();
+            for key in COUNT_KEYS {
+                builder = builder.set_default(key, 1).unwrap();
+            }
+            builder
+                .set_override("state.max_import_mb", value)
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        assert!(validate_counts(&config(512)).is_ok());
+        assert!(validate_counts(&config(0)).is_ok());
+        assert!(validate_counts(&config(-1)).is_err());
+        assert!(validate_counts(&config(i64::from(u32::MAX) + 1)).is_err());
+    }
+
+    #[test]
+    fn the_default_radius_has_to_be_positive() {
+        assert_eq!(defaults(&[]).radius, 300.0);
+        let config = Config::builder()
+            .set_default("coverage.default_method", "relative")
+            .unwrap()
+            .set_default("coverage.default_routing", "osm")
+            .unwrap()
+            .set_default("coverage.default_radius", -1.0)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(CoverageDefaults::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn cached_results_are_used_until_the_generation_changes() {
+        let request = |json: &str| -> StationInfoRequest { serde_json::from_str(json).unwrap() };
+        let first = request(
+            r#"{
+                "stations": [],
+                "layer_routing": {"Residential": "naive", "Workplace": "osm", "Shopping": "network", "Amenity": "bike"},
+                "scale": {"Residential": 1.0, "Workplace": 0.5, "Shopping": 0.2, "Amenity": 2.0},
+                "house_type_weights": {"single_family": 1.0, "multi_family": 0.9, "high_rise": 0.8, "unknown": 1.1},
+                "method": {"Residential": "absolute", "Workplace": "relative", "Shopping": "absolute", "Amenity": "relative"}
+            }"#,
+        );
+        // the same request with the entries in a different order
+        let second = request(
+            r#"{
+                "method": {"Amenity": "relative", "Shopping": "absolute", "Workplace": "relative", "Residential": "absolute"},
+                "house_type_weights": {"unknown": 1.1, "high_rise": 0.8, "multi_family": 0.9, "single_family": 1.0},
+                "scale": {"Amenity": 2.0, "Shopping": 0.2, "Workplace": 0.5, "Residential": 1.0},
+                "layer_routing": {"Amenity": "bike", "Shopping": "network", "Workplace": "osm", "Residential": "naive"},
+                "stations": []
+            }"#,
+        );
+        let other = request(r#"{"stations": [], "layer_routing": {"Residential": "osm"}}"#);
+
+        let mut cache: CoverageCache<u32> = CoverageCache::new(2);
+        cache.insert(CoverageCache::key(&first).unwrap(), 5, 10);
+        assert_eq!(cache.get(CoverageCache::key(&second).unwrap(), 5), Some(10));
+        assert_eq!(cache.get(CoverageCache::key(&other).unwrap(), 5), None);
+
+        let key = CoverageCache::key(&second).unwrap();
+        assert_eq!(cache.get(key, 6), None);
+        // results of an older generation aren't stored any more
+        cache.insert(key, 5, 10);
+        assert_eq!(cache.get(key, 6), None);
+        cache.insert(key, 6, 11);
+        assert_eq!(cache.get(key, 6), Some(11));
+    }
+
+    fn run_minimal_stations(
+        request: serde_json::Value,
+        layers: Layers,
+    ) -> Result<web::Json<MinimalStationsResult>, OLPError> {
+        let request = web::Json(serde_json::from_value(request).unwrap());
+        let config = web::Data::new(config_defaults().build().unwrap());
+        actix_web::rt::System::new().block_on(find_minimal_stations(
+            request,
+            web::Data::new(RwLock::new(layers)),
+            config,
+        ))
+    }
+
+    #[test]
+    fn minimal_stations_reject_an_empty_route() {
+        let result = run_minimal_stations(
+            serde_json::json!({"route": [], "target": 0.5}),
+            Layers::new(),
+        );
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn minimal_stations_need_a_positive_coverage_and_loaded_data() {
+        let route = serde_json::json!([
+            {"x": 13.40, "y": 52.52},
+            {"x": 13.41, "y": 52.52}
+        ]);
+        let result = run_minimal_stations(
+            serde_json::json!({"route": route, "target": 0.5}),
+            Layers::new(),
+        );
+        assert!(matches!(result, Err(OLPError::NoDataLoaded)));
+        let result = run_minimal_stations(
+            serde_json::json!({"route": route, "target": 0.5, "coverage": 0.0}),
+            Layers::new(),
+        );
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+    }
+
+    fn run_station_set(
+        request: serde_json::Value,
+        layers: Layers,
+    ) -> Result<web::Json<StationSetResult>, OLPError> {
+        let request = web::Json(serde_json::from_value(request).unwrap());
+        let config = web::Data::new(config_defaults().build().unwrap());
+        actix_web::rt::System::new().block_on(find_station_set(
+            request,
+            web::Data::new(RwLock::new(layers)),
+            config,
+        ))
+    }
+
+    #[test]
+    fn station_sets_reject_an_empty_route_and_need_loaded_data() {
+        let result = run_station_set(serde_json::json!({"route": [], "count": 2}), Layers::new());
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+        let route = serde_json::json!([{"x": 13.40, "y": 52.52}, {"x": 13.42, "y": 52.52}]);
+        let result = run_station_set(
+            serde_json::json!({"route": route, "count": 2}),
+            Layers::new(),
+        );
+        assert!(matches!(result, Err(OLPError::NoDataLoaded)));
+    }
+
+    #[test]
+    fn a_corridor_with_two_peaks_gets_a_station_at_each_of_them() {
+        let peaks = [Point::new(13.402, 52.5205), Point::new(13.418, 52.5205)];
+        let houses = peaks
+            .iter()
+            .map(|peak| PopulatedCentroid::poi(*peak, 500))
+            .collect();
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(houses, Streets::new(), Streets::new()));
+
+        let route = serde_json::json!([{"x": 13.40, "y": 52.52}, {"x": 13.42, "y": 52.52}]);
+        let result = run_station_set(
+            serde_json::json!({
+                "route": route, "count": 2, "coverage": 300.0, "routing": "naive"
+            }),
+            layers,
+        )
+        .unwrap();
+        let result = serde_json::to_value(&result.0).unwrap();
+        assert_eq!(result["covered_inhabitants"], 1000);
+        let stations: Vec<Point> = result["stations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|station| serde_json::from_value(station["location"].clone()).unwrap())
+            .collect();
+        // each station is in reach of exactly one of the peaks
+        assert_eq!(stations.len(), 2);
+        for peak in peaks {
+            let in_reach = stations
+                .iter()
+                .filter(|station| station.haversine_distance(&peak) <= 300.0)
+                .count();
+            assert_eq!(in_reach, 1);
+        }
+    }
+
+    #[test]
+    fn the_nearest_streets_take_the_point_in_lon_lat_order() {
+        let mut streets = Streets::new();
+        // 50 m north of (16.37, 48.2)
+        let lat = 48.2 + 50.0 / 111_195.0;
+        streets.nodes.insert(NodeId(1), Point::new(16.369, lat));
+        streets.nodes.insert(NodeId(2), Point::new(16.371, lat));
+        streets.streetgraph.add_edge(NodeId(1), NodeId(2), 150.0);
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(Vec::new(), streets, Streets::new()));
+
+        let response = actix_web::rt::System::new()
+            .block_on(async {
+                let response = nearest_streets(
+                    web::Path::from((16.37, 48.2)),
+                    web::Query(NearestStreetsQuery { k: None }),
+                    web::Data::new(RwLock::new(layers)),
+                )
+                .await?;
+                actix_web::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(OLPError::from_error)
+            })
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+        let features = response["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        let distance = features[0]["properties"]["distance"].as_f64().unwrap();
+        assert!((distance - 50.0).abs() < 1.0, "{}", distance);
+    }
+
+    #[test]
+    fn naive_station_matrices_are_symmetric_with_a_zero_diagonal() {
+        let request = serde_json::json!({
+            "stations": [
+                {"id": "a", "location": {"x": 16.37, "y": 48.2}},
+                {"id": "b", "location": {"x": 16.3813, "y": 48.2057}},
+                {"id": "c", "location": {"x": 16.3625, "y": 48.2189}},
+            ],
+            "routing": "naive",
+        });
+        let config = config_defaults().build().unwrap();
+        let matrix = actix_web::rt::System::new()
+            .block_on(station_matrix(
+                web::Json(serde_json::from_value(request).unwrap()),
+                web::Data::new(RwLock::new(Layers::new())),
+                web::Data::new(config),
+            ))
+            .unwrap();
+        assert_eq!(matrix.stations, ["a", "b", "c"]);
+        for (i, row) in matrix.distances.iter().enumerate() {
+            assert_eq!(row[i], Some(0.0));
+            for (j, distance) in row.iter().enumerate() {
+                assert_eq!(*distance, matrix.distances[j][i], "{} to {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn type_weights_scale_the_inhabitants_per_layer_type() {
+        let request: StationInfoRequest = serde_json::from_value(serde_json::json!({
+            "stations": [],
+            "type_weights": {"Residential": 0.5, "Workplace": 2.0},
+        }))
+        .unwrap();
+        let mut inhabitants: population::InhabitantsMap = [
+            ("a".to_owned(), LayerType::Residential, 100),
+            ("a".to_owned(), LayerType::Workplace, 100),
+            ("a".to_owned(), LayerType::Shopping, 100),
+            ("b".to_owned(), LayerType::Residential, 31),
+        ]
+        .into_iter()
+        .collect();
+        inhabitants.scale(request.scale.as_ref().unwrap());
+
+        let inhabitants = serde_json::to_value(&inhabitants).unwrap();
+        let total = |layer_type: &str| -> u64 {
+            inhabitants
+                .as_object()
+                .unwrap()
+                .values()
+                .flat_map(|infos| infos.as_array().unwrap())
+                .filter(|info| info["layer_type"] == layer_type)
+                .map(|info| info["value"].as_u64().unwrap())
+                .sum()
+        };
+        // 50 + 15.5 rounded to 16
+        assert_eq!(total("Residential"), 66);
+        assert_eq!(total("Workplace"), 200);
+        // layer types without a weight are unchanged
+        assert_eq!(total("Shopping"), 100);
+
+        let both = serde_json::from_value::<StationInfoRequest>(serde_json::json!({
+            "stations": [],
+            "scale": 2.0,
+            "type_weights": {"Residential": 0.5},
+        }));
+        let error = both.err().unwrap().to_string();
+        assert!(error.contains("duplicate field `scale`"), "{}", error);
+    }
+
+    fn assert_rejected<T>(result: Result<T, OLPError>, endpoint: &str) {
+        match result {
+            Err(error @ OLPError::InvalidInput(_)) => {
+                assert_eq!(error.status_code(), StatusCode::BAD_REQUEST, "{}", endpoint)
+            }
+            Err(error) => panic!("{} failed with {}", endpoint, error),
+            Ok(_) => panic!("{} accepted an empty request", endpoint),
+        }
+    }
+
+    #[test]
+    fn requests_without_stations_or_route_are_rejected() {
+        let layers = web::Data::new(RwLock::new(Layers::new()));
+        let lines = web::Data::new(RwLock::new(Lines::default()));
+        let config = web::Data::new(config_defaults().build().unwrap());
+        let coverage_cache = web::Data::new(Mutex::new(CoverageCache::new(2)));
+        let removal_impact_cache = web::Data::new(Mutex::new(RemovalImpactCache::new(2)));
+        fn json<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> web::Json<T> {
+            web::Json(serde_json::from_value(value).unwrap())
+        }
+        fn no_stations<T: serde::de::DeserializeOwned>() -> web::Json<T> {
+            json(serde_json::json!({"stations": []}))
+        }
+
+        actix_web::rt::System::new().block_on(async {
+            assert_rejected(
+                station_info(
+                    no_stations(),
+                    web::Query(StationInfoQuery { group_by: None }),
+                    layers.clone(),
+                    lines.clone(),
+                    config.clone(),
+                    coverage_cache.clone(),
+                )
+                .await,
+                "station-info",
+            );
+            assert_rejected(
+                compare_scenarios(
+                    json(serde_json::json!({"a": [], "b": []})),
+                    layers.clone(),
+                    config.clone(),
+                    coverage_cache.clone(),
+                )
+                .await,
+                "station-info/compare",
+            );
+            assert_rejected(
+                access_distance(
+                    no_stations(),
+                    layers.clone(),
+                    lines.clone(),
+                    config.clone(),
+                    coverage_cache.clone(),
+                )
+                .await,
+                "station-info/access-distance",
+            );
+            assert_rejected(
+                station_removal_impact(
+                    no_stations(),
+                    layers.clone(),
+                    lines.clone(),
+                    config.clone(),
+                    removal_impact_cache.clone(),
+                )
+                .await,
+                "station-removal-impact",
+            );
+            assert_rejected(
+                coverage_overlap(no_stations(), layers.clone(), lines.clone(), config.clone())
+                    .await,
+                "coverage-overlap",
+            );
+            assert_rejected(
+                ridership_estimate(
+                    no_stations(),
+                    layers.clone(),
+                    lines.clone(),
+                    config.clone(),
+                    coverage_cache.clone(),
+                )
+                .await,
+                "ridership-estimate",
+            );
+            assert_rejected(
+                station_matrix(no_stations(), layers.clone(), config.clone()).await,
+                "station-matrix",
+            );
+            assert_rejected(
+                find_station(
+                    json(serde_json::json!({"stations": [], "route": []})),
+                    layers.clone(),
+                    config.clone(),
+                )
+                .await,
+                "find-station",
+            );
+        });
+    }
+
+    #[test]
+    fn the_type_weights_decide_the_found_station() {
+        let mut layers = Layers::new();
+        layers.push(Layer::fixture(
+            vec![PopulatedCentroid::poi(Point::new(13.403, 52.52), 150)],
+            Streets::new(),
+            Streets::new(),
+        ));
+        layers.push(
+            Layer::fixture(
+                vec![PopulatedCentroid::poi(Point::new(13.417, 52.52), 100)],
+                Streets::new(),
+                Streets::new(),
+            )
+            .with_type(LayerType::Workplace),
+        );
+        let layers = web::Data::new(RwLock::new(layers));
+        let config = web::Data::new(config_defaults().build().unwrap());
+        let found = |scale: serde_json::Value| -> Point {
+            let request = serde_json::json!({
+                "stations": [],
+                "route": [{"x": 13.40, "y": 52.52}, {"x": 13.42, "y": 52.52}],
+                "method": "absolute",
+                "routing": "naive",
+                "search_radius": 300.0,
+                "scale": scale,
+            });
+            let result = actix_web::rt::System::new()
+                .block_on(find_station(
+                    web::Json(serde_json::from_value(request).unwrap()),
+                    layers.clone(),
+                    config.clone(),
+                ))
+                .unwrap();
+            let Either::Left(result) = result else {
+                panic!("a single station was requested");
+            };
+            let result = serde_json::to_value(&result).unwrap();
+            serde_json::from_value(result["location"].clone()).unwrap()
+        };
+
+        let (residents, workplaces) = (Point::new(13.403, 52.52), Point::new(13.417, 52.52));
+        let unweighted = found(serde_json::Value::Null);
+        assert!(unweighted.haversine_distance(&residents) <= 300.0);
+        let weighted = found(serde_json::json!({"Workplace": 2.0}));
+        assert!(weighted.haversine_distance(&workplaces) <= 300.0);
+        let weighted = found(serde_json::json!({"Residential": 0.5}));
+        assert!(weighted.haversine_distance(&workplaces) <= 300.0);
+    }
+
+    /// The station info response body for `request`, grouped by station
+    fn run_station_info(
+        request: serde_json::Value,
+        layers: Layers,
+    ) -> Result<serde_json::Value, OLPError> {
+        let request = serde_json::from_value(request).unwrap();
+        actix_web::rt::System::new().block_on(async {
+            let response = station_info(
+                web::Json(request),
+                web::Query(StationInfoQuery { group_by: None }),
+                web::Data::new(RwLock::new(layers)),
+                web::Data::new(RwLock::new(Lines::default())),
+                web::Data::new(config_defaults().build().unwrap()),
+                web::Data::new(Mutex::new(CoverageCache::new(2))),
+            )
+            .await?
+            .respond_to(&actix_web::test::TestRequest::default().to_http_request());
+            let body = actix_web::body::to_bytes(response.into_body())
+                .await
+                .map_err(OLPError::from_error)?;
+            Ok(serde_json::from_slice(&body).unwrap())
+        })
+    }
+
+    #[test]
+    fn station_ids_only_keep_the_requested_stations() {
+        let houses = vec![
+            PopulatedCentroid::poi(Point::new(13.400, 52.52), 100),
+            PopulatedCentroid::poi(Point::new(13.401, 52.52), 70),
+            PopulatedCentroid::poi(Point::new(13.410, 52.52), 40),
+        ];
+        let layers = || {
+            let mut layers = Layers::new();
+            layers.push(Layer::fixture(
+                houses.clone(),
+                Streets::new(),
+                Streets::new(),
+            ));
+            layers
+        };
+        let request = |station_ids: serde_json::Value| {
+            serde_json::json!({
+                "stations": [
+                    {"id": "a", "location": {"x": 13.400, "y": 52.52}},
+                    {"id": "b", "location": {"x": 13.4015, "y": 52.52}},
+                    {"id": "c", "location": {"x": 13.410, "y": 52.52}},
+                ],
+                "method": "absolute",
+                "routing": "naive",
+                "station_ids": station_ids,
+            })
+        };
+
+        let all = run_station_info(request(serde_json::Value::Null), layers()).unwrap();
+        assert_eq!(all.as_object().unwrap().len(), 3);
+        let filtered = run_station_info(request(serde_json::json!(["a", "c"])), layers()).unwrap();
+        let mut ids: Vec<&String> = filtered.as_object().unwrap().keys().collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+        // b still takes the houses closer to it than to a
+        assert_eq!(all["a"][0]["value"], 100);
+        assert_eq!(filtered["a"], all["a"]);
+        assert_eq!(filtered["c"], all["c"]);
+
+        let unknown = run_station_info(request(serde_json::json!(["d"])), layers());
+        assert!(matches!(unknown, Err(OLPError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn each_layer_type_is_counted_with_its_own_method() {
+        let station = Point::new(13.400, 52.52);
+        // about 100 m north of the station
+        let house = Point::new(13.400, 52.5209);
+        let layers = || {
+            let mut layers = Layers::new();
+            for (layer_type, pop) in [
+                (LayerType::Residential, 1000),
+                (LayerType::Workplace, 400),
+                (LayerType::Shopping, 900),
+            ] {
+                let houses = vec![PopulatedCentroid::poi(house, pop)];
+                layers.push(
+                    Layer::fixture(houses, Streets::new(), Streets::new()).with_type(layer_type),
+                );
+            }
+            layers
+        };
+        let values = |method: serde_json::Value| {
+            let response = run_station_info(
+                serde_json::json!({
+                    "stations": [{"id": "a", "location": station}],
+                    "method": method,
+                    "routing": "naive",
+                }),
+                layers(),
+            )
+            .unwrap();
+            let mut values: Vec<(String, u64)> = response["a"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|info| {
+                    let layer_type = info["layer_type"].as_str().unwrap().to_owned();
+                    (layer_type, info["value"].as_u64().unwrap())
+                })
+                .collect();
+            values.sort();
+            values
+        };
+        let relative = |pop: f64| (pop / station.haversine_distance(&house).sqrt()) as u64;
+
+        assert_eq!(
+            values(serde_json::json!("absolute")),
+            [
+                ("Residential".to_owned(), 1000),
+                ("Shopping".to_owned(), 900),
+                ("Workplace".to_owned(), 400)
+            ]
+        );
+        // shopping isn't listed and uses the default relative method
+        assert_eq!(
+            values(serde_json::json!({"Residential": "absolute", "Workplace": "relative"})),
+            [
+                ("Residential".to_owned(), 1000),
+                ("Shopping".to_owned(), relative(900.0)),
+                ("Workplace".to_owned(), relative(400.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn house_type_weights_scale_the_houses_of_their_type() {
+        let layers = || {
+            let houses = vec![
+                PopulatedCentroid::poi(Point::new(13.4005, 52.52), 200).with_flats(30),
+                PopulatedCentroid::poi(Point::new(13.3995, 52.52), 10).with_flats(1),
+            ];
+            let mut layers = Layers::new();
+            layers.push(Layer::fixture(houses, Streets::new(), Streets::new()));
+            layers
+        };
+        let value = |weights: serde_json::Value| {
+            let response = run_station_info(
+                serde_json::json!({
+                    "stations": [{"id": "a", "location": {"x": 13.400, "y": 52.52}}],
+                    "method": "absolute",
+                    "routing": "naive",
+                    "house_type_weights": weights,
+                }),
+                layers(),
+            )
+            .unwrap();
+            response["a"][0]["value"].as_u64().unwrap()
+        };
+
+        assert_eq!(value(serde_json::Value::Null), 210);
+        assert_eq!(value(serde_json::json!({"high_rise": 0.5})), 110);
+        assert_eq!(
+            value(serde_json::json!({"single_family": 3.0, "multi_family": 0.0})),
+            230
+        );
+    }
+
+    #[test]
+    fn the_listed_buildings_add_up_to_the_station_value() {
+        let houses: Vec<PopulatedCentroid> = (0..12)
+            .map(|house| {
+                let location = Point::new(13.398 + house as f64 * 0.0004, 52.5205);
+                PopulatedCentroid::poi(location, 37 + house * 11)
+            })
+            .collect();
+        let layers = || {
+            let mut layers = Layers::new();
+            layers.push(Layer::fixture(
+                houses.clone(),
+                Streets::new(),
+                Streets::new(),
+            ));
+            layers
+        };
+        let request = |scale: f64| {
+            serde_json::json!({
+                "stations": [
+                    {"id": "a", "location": {"x": 13.399, "y": 52.52}},
+                    {"id": "b", "location": {"x": 13.402, "y": 52.52}},
+                ],
+                "method": "relative",
+                "routing": "naive",
+                "include_buildings": true,
+                "scale": scale,
+            })
+        };
+        let buildings_sum = |info: &serde_json::Value| -> u64 {
+            info["buildings"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|building| building["inhabitants"].as_u64().unwrap())
+                .sum()
+        };
+
+        let response = run_station_info(request(1.0), layers()).unwrap();
+        let scaled = run_station_info(request(2.0), layers()).unwrap();
+        for station in ["a", "b"] {
+            let info = &response[station][0];
+            assert!(info["buildings"].as_array().unwrap().len() > 1);
+            assert_eq!(buildings_sum(info), info["value"].as_u64().unwrap());
+            // the buildings are listed before scaling
+            let scaled = &scaled[station][0];
+            assert_eq!(scaled["buildings"], info["buildings"]);
+            assert_eq!(scaled["value"].as_u64().unwrap(), buildings_sum(info) * 2);
+        }
+    }
+}
@@ -1,15 +1,12 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     fs::File,
     io::{Read, Write},
     path::Path,
 };
-use datatypes::Streets;
+use datatypes::{Streets, TurnRestrictions};
 
-use crate::{
-    error::OLPError,
-    layers::Layers,
-};
+use crate::{error::OLPError, layers::Layers, line::Lines};
 use openhousepopulator::Buildings;
 
 use anyhow::Result;
@@ -20,12 +17,47 @@ pub(crate) struct PreProcessingData {
     pub streets: Streets,
 }
 
-pub(crate) fn load_preprocessed_data(path: &Path) -> Result<PreProcessingData, OLPError> {
+/// The preprocessing writes postcard or, with `--format json`, json
+fn load_preprocessed<T: DeserializeOwned>(path: &Path) -> Result<T, OLPError> {
     let mut file = File::open(path).map_err(OLPError::from_error)?;
     let mut data: Vec<u8> = Vec::new();
     file.read_to_end(&mut data).map_err(OLPError::from_error)?;
-    let result = postcard::from_bytes(&data).map_err(OLPError::from_error)?;
-    Ok(result)
+    if data.first() == Some(&b'{') {
+        if let Ok(value) = serde_json::from_slice(&data) {
+            return Ok(value);
+        }
+    }
+    postcard::from_bytes(&data).map_err(OLPError::from_error)
+}
+
+/// Loads the combined `.map` file, falling back to the separate `.streets` and
+/// `.buildings` artifacts written by the preprocessing
+pub(crate) fn load_preprocessed_data(path: &Path) -> Result<PreProcessingData, OLPError> {
+    if path.exists() {
+        return load_preprocessed(path);
+    }
+    log::info!("no combined data at {:?}, loading separate artifacts", path);
+    Ok(PreProcessingData {
+        streets: load_preprocessed(&path.with_extension("streets"))?,
+        buildings: load_preprocessed(&path.with_extension("buildings"))?,
+    })
+}
+
+/// The bike network the preprocessing writes next to the `.map` file
+pub(crate) fn load_bike_streets(path: &Path) -> Result<Streets, OLPError> {
+    load_preprocessed(path)
+}
+
+/// The turn restrictions of the bike network, see `datatypes::TurnRestrictions`
+pub(crate) fn load_restrictions(path: &Path) -> Result<TurnRestrictions, OLPError> {
+    load_preprocessed(path)
+}
+
+/// JSON with the keys of all maps sorted, so the same data always gives the same bytes
+pub(crate) fn to_stable_json<T: Serialize>(value: &T) -> Result<Vec<u8>, OLPError> {
+    // serde_json::Value keeps its objects in a BTreeMap
+    let value = serde_json::to_value(value).map_err(OLPError::from_error)?;
+    serde_json::to_vec(&value).map_err(OLPError::from_error)
 }
 
 pub(crate) fn save_layers(layers: &Layers, path: &Path) -> Result<(), OLPError> {
@@ -44,3 +76,20 @@ pub(crate) fn load_layers(path: &Path) -> Result<Layers, OLPError> {
     file.read_to_end(&mut data).map_err(OLPError::from_error)?;
     serde_json::from_slice(&data).map_err(OLPError::from_error)
 }
+
+pub(crate) fn save_lines(lines: &Lines, path: &Path) -> Result<(), OLPError> {
+    let mut file = File::create(path).map_err(OLPError::from_error)?;
+    file.write_all(
+        serde_json::to_vec(lines)
+            .map_err(OLPError::from_error)?
+            .as_slice(),
+    )
+    .map_err(OLPError::from_error)
+}
+
+pub(crate) fn load_lines(path: &Path) -> Result<Lines, OLPError> {
+    let mut file = File::open(path).map_err(OLPError::from_error)?;
+    let mut data: Vec<u8> = Vec::new();
+    file.read_to_end(&mut data).map_err(OLPError::from_error)?;
+    serde_json::from_slice(&data).map_err(OLPError::from_error)
+}
@@ -1,21 +1,50 @@
 use actix_web::body::BoxBody;
+use actix_web::http::header::Accept;
 use actix_web::http::header::ContentType;
+use actix_web::http::header::Header;
 use actix_web::HttpResponse;
 use actix_web::Responder;
-use serde::Serialize;
+use config::Config;
+use geo::Point;
+use geojson::ser::serialize_geometry;
+use serde::{Deserialize, Serialize};
 
 use crate::coverage::CoverageMap;
-use crate::layers::LayerType;
+use crate::coverage::StationOverlap;
+use crate::error::OLPError;
+use crate::export::csv_field;
+use crate::layers::{HouseType, LayerType};
+use crate::Station;
 
 use std::collections::HashMap;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct InhabitantsInfo {
     layer_type: LayerType,
     value: u32,
+    /// only calculated on request, see `InhabitantsMap::estimate_riders`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_riders: Option<u32>,
+    /// `value` before `InhabitantsMap::apply_capacities` changed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uncapped: Option<u32>,
+    /// only listed on request, see `InhabitantsMap::include_buildings`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buildings: Option<Vec<CoveredBuilding>>,
 }
 
-#[derive(Serialize)]
+/// A house covered by a station. Houses have no id of their own, so they are
+/// identified by their location.
+#[derive(Serialize, Clone)]
+pub struct CoveredBuilding {
+    #[serde(serialize_with = "serialize_geometry")]
+    location: Point,
+    /// inhabitants the house adds to the station before scaling and capacities
+    inhabitants: u32,
+    distance: f64,
+}
+
+#[derive(Serialize, Clone)]
 pub struct InhabitantsMap(HashMap<String, Vec<InhabitantsInfo>>);
 
 impl From<&[(LayerType, CoverageMap<'_, '_>)]> for InhabitantsMap {
@@ -28,6 +57,9 @@ impl From<&[(LayerType, CoverageMap<'_, '_>)]> for InhabitantsMap {
                     .push(InhabitantsInfo {
                         layer_type: layer_type.clone(),
                         value: coverage.inhabitants,
+                        estimated_riders: None,
+                        uncapped: None,
+                        buildings: None,
                     });
             }
         }
@@ -35,12 +67,724 @@ impl From<&[(LayerType, CoverageMap<'_, '_>)]> for InhabitantsMap {
     }
 }
 
+/// Population-weighted mean distance (in meters) from the covered houses to their station
+#[derive(Serialize, Default, Clone)]
+pub struct AccessDistance {
+    covered_inhabitants: u32,
+    mean_distance: Option<f64>,
+    #[serde(skip)]
+    weighted_distance: f64,
+}
+
+impl AccessDistance {
+    fn add(&mut self, inhabitants: u32, distance: f64) {
+        self.covered_inhabitants += inhabitants;
+        self.weighted_distance += inhabitants as f64 * distance;
+        self.mean_distance = (self.covered_inhabitants > 0)
+            .then(|| self.weighted_distance / self.covered_inhabitants as f64);
+    }
+
+    pub fn mean_distance(&self) -> Option<f64> {
+        self.mean_distance
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct AccessDistances {
+    total: AccessDistance,
+    by_layer_type: HashMap<LayerType, AccessDistance>,
+}
+
+impl AccessDistances {
+    pub fn total(&self) -> &AccessDistance {
+        &self.total
+    }
+}
+
+impl From<&[(LayerType, CoverageMap<'_, '_>)]> for AccessDistances {
+    fn from(value: &[(LayerType, CoverageMap<'_, '_>)]) -> Self {
+        let mut total = AccessDistance::default();
+        let mut by_layer_type: HashMap<LayerType, AccessDistance> = HashMap::new();
+        for (layer_type, coverage_map) in value {
+            let layer_distance = by_layer_type.entry(*layer_type).or_default();
+            for house in coverage_map
+                .0
+                .values()
+                .flat_map(|coverage| &coverage.houses)
+            {
+                layer_distance.add(house.centroid.pop, house.distance);
+                total.add(house.centroid.pop, house.distance);
+            }
+        }
+        AccessDistances {
+            total,
+            by_layer_type,
+        }
+    }
+}
+
+/// Covered inhabitants per station, grouped by layer type first
+#[derive(Serialize)]
+pub struct InhabitantsByLayerType(HashMap<LayerType, HashMap<String, u32>>);
+
+impl From<&InhabitantsMap> for InhabitantsByLayerType {
+    fn from(value: &InhabitantsMap) -> Self {
+        let mut map: HashMap<LayerType, HashMap<String, u32>> = HashMap::new();
+        for (station, infos) in &value.0 {
+            for info in infos {
+                *map.entry(info.layer_type)
+                    .or_default()
+                    .entry(station.clone())
+                    .or_default() += info.value;
+            }
+        }
+        InhabitantsByLayerType(map)
+    }
+}
+
+impl InhabitantsMap {
+    /// Reports the values of `original` for `duplicate` as well
+    pub fn alias(&mut self, duplicate: &str, original: &str) {
+        if let Some(infos) = self.0.get(original).cloned() {
+            self.0.insert(duplicate.to_owned(), infos);
+        }
+    }
+
+    /// Drops all stations except `station_ids`
+    pub fn retain_stations(&mut self, station_ids: &[String]) {
+        self.0.retain(|station, _| station_ids.contains(station));
+    }
+}
+
+/// Multiplier applied to the covered inhabitants at query time, either one value for
+/// all layer types or one per layer type (missing layer types stay unscaled).
+/// This is an overlay on the cached building model for sensitivity analysis, the
+/// inhabitants of the buildings are not estimated again.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InhabitantsScale {
+    Global(f64),
+    PerLayerType(HashMap<LayerType, f64>),
+}
+
+impl InhabitantsScale {
+    pub fn factor(&self, layer_type: &LayerType) -> f64 {
+        match self {
+            InhabitantsScale::Global(factor) => *factor,
+            InhabitantsScale::PerLayerType(factors) => {
+                factors.get(layer_type).copied().unwrap_or(1.0)
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), OLPError> {
+        let valid = match self {
+            InhabitantsScale::Global(factor) => factor.is_finite() && *factor >= 0.0,
+            InhabitantsScale::PerLayerType(factors) => factors
+                .values()
+                .all(|factor| factor.is_finite() && *factor >= 0.0),
+        };
+        if !valid {
+            return Err(OLPError::InvalidInput(
+                "inhabitant scale factors have to be positive numbers".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Multiplier for the inhabitants of each house type, applied to the houses before
+/// the coverage is calculated. Missing house types keep their inhabitants.
+#[derive(Serialize, Deserialize)]
+pub struct HouseTypeWeights(HashMap<HouseType, f64>);
+
+impl HouseTypeWeights {
+    pub fn factor(&self, house_type: HouseType) -> f64 {
+        self.0.get(&house_type).copied().unwrap_or(1.0)
+    }
+
+    pub fn validate(&self) -> Result<(), OLPError> {
+        if !self
+            .0
+            .values()
+            .all(|factor| factor.is_finite() && *factor >= 0.0)
+        {
+            return Err(OLPError::InvalidInput(
+                "house type weights have to be positive numbers".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl InhabitantsMap {
+    pub fn scale(&mut self, scale: &InhabitantsScale) {
+        for info in self.0.values_mut().flatten() {
+            let factor = scale.factor(&info.layer_type);
+            info.value = (info.value as f64 * factor).round() as u32;
+            info.estimated_riders = info
+                .estimated_riders
+                .map(|riders| (riders as f64 * factor).round() as u32);
+        }
+    }
+}
+
+impl FromIterator<(String, LayerType, u32)> for InhabitantsMap {
+    fn from_iter<T: IntoIterator<Item = (String, LayerType, u32)>>(iter: T) -> Self {
+        let mut map: HashMap<String, Vec<InhabitantsInfo>> = HashMap::new();
+        for (station, layer_type, value) in iter {
+            map.entry(station).or_default().push(InhabitantsInfo {
+                layer_type,
+                value,
+                estimated_riders: None,
+                uncapped: None,
+                buildings: None,
+            });
+        }
+        InhabitantsMap(map)
+    }
+}
+
+impl InhabitantsMap {
+    /// One `station_id,layer_type,inhabitants` row per station and layer type,
+    /// ordered by station id
+    pub fn to_csv(&self) -> String {
+        let mut stations: Vec<&String> = self.0.keys().collect();
+        stations.sort();
+        let mut csv = String::from("station_id,layer_type,inhabitants\n");
+        for station in stations {
+            for info in &self.0[station] {
+                csv.push_str(&format!(
+                    "{},{},{}\n",
+                    csv_field(station),
+                    info.layer_type,
+                    info.value
+                ));
+            }
+        }
+        csv
+    }
+}
+
+/// Whether the client prefers CSV over the default JSON
+fn prefers_csv(req: &actix_web::HttpRequest) -> bool {
+    Accept::parse(req)
+        .ok()
+        .and_then(|accept| accept.ranked().into_iter().next())
+        .is_some_and(|mime| mime.essence_str() == "text/csv")
+}
+
 impl Responder for InhabitantsMap {
     type Body = BoxBody;
 
+    fn respond_to(self, req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
+        if prefers_csv(req) {
+            return HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .body(self.to_csv());
+        }
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .json(self)
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct OverlapInfo {
+    layer_type: LayerType,
+    exclusive: u32,
+    shared: u32,
+}
+
+/// Exclusive and shared inhabitants per station and layer type, the sum of both for
+/// a station is its value in the `InhabitantsMap` of the same request
+#[derive(Serialize)]
+pub struct CoverageOverlap(HashMap<String, Vec<OverlapInfo>>);
+
+impl FromIterator<(String, LayerType, StationOverlap)> for CoverageOverlap {
+    fn from_iter<T: IntoIterator<Item = (String, LayerType, StationOverlap)>>(iter: T) -> Self {
+        let mut map: HashMap<String, Vec<OverlapInfo>> = HashMap::new();
+        for (station, layer_type, overlap) in iter {
+            map.entry(station).or_default().push(OverlapInfo {
+                layer_type,
+                exclusive: overlap.exclusive,
+                shared: overlap.shared,
+            });
+        }
+        CoverageOverlap(map)
+    }
+}
+
+impl CoverageOverlap {
+    /// Scales the total like `InhabitantsMap::scale` and takes the exclusive part from
+    /// it, so both still add up after rounding
+    pub fn scale(&mut self, scale: &InhabitantsScale) {
+        for info in self.0.values_mut().flatten() {
+            let factor = scale.factor(&info.layer_type);
+            let total = ((info.exclusive + info.shared) as f64 * factor).round() as u32;
+            info.shared = ((info.shared as f64 * factor).round() as u32).min(total);
+            info.exclusive = total - info.shared;
+        }
+    }
+
+    /// Reports the values of `original` for `duplicate` as well
+    pub fn alias(&mut self, duplicate: &str, original: &str) {
+        if let Some(infos) = self.0.get(original).cloned() {
+            self.0.insert(duplicate.to_owned(), infos);
+        }
+    }
+}
+
+impl Responder for CoverageOverlap {
+    type Body = BoxBody;
+
     fn respond_to(self, _req: &actix_web::HttpRequest) -> actix_web::HttpResponse<Self::Body> {
         HttpResponse::Ok()
             .content_type(ContentType::json())
             .json(self)
     }
 }
+
+/// Share of the covered inhabitants of each layer type expected to board daily
+pub struct CaptureRates(HashMap<LayerType, f64>);
+
+impl CaptureRates {
+    /// Reads the rates from `ridership.capture_rate.<layer_type>`, rates given
+    /// in the request take precedence
+    pub fn from_config(
+        config: &Config,
+        overrides: Option<&HashMap<LayerType, f64>>,
+    ) -> Result<Self, OLPError> {
+        let mut rates = HashMap::new();
+        for layer_type in [
+            LayerType::Residential,
+            LayerType::Workplace,
+            LayerType::Shopping,
+        ] {
+            let rate = config
+                .get_float(&format!(
+                    "ridership.capture_rate.{}",
+                    layer_type.to_string().to_lowercase()
+                ))
+                .map_err(OLPError::from_error)?;
+            rates.insert(layer_type, rate);
+        }
+        if let Some(overrides) = overrides {
+            rates.extend(overrides.iter());
+        }
+        Ok(CaptureRates(rates))
+    }
+
+    fn rate(&self, layer_type: &LayerType) -> f64 {
+        self.0.get(layer_type).copied().unwrap_or_default()
+    }
+}
+
+/// Share of the capture rate that applies to a house depending on its walking distance
+/// to the station: all of it up to `full_rate_distance`, then falling linearly to
+/// nothing at `zero_rate_distance`
+pub struct CaptureCurve {
+    full_rate_distance: f64,
+    zero_rate_distance: f64,
+}
+
+impl CaptureCurve {
+    /// Reads the distances from `ridership.distance_decay`
+    pub fn from_config(config: &Config) -> Result<Self, OLPError> {
+        let full_rate_distance = config
+            .get_float("ridership.distance_decay.full_rate_distance")
+            .map_err(OLPError::from_error)?;
+        let zero_rate_distance = config
+            .get_float("ridership.distance_decay.zero_rate_distance")
+            .map_err(OLPError::from_error)?;
+        if !(0.0..zero_rate_distance).contains(&full_rate_distance) {
+            return Err(OLPError::GenericError(
+                "ridership.distance_decay.full_rate_distance has to be positive and below zero_rate_distance"
+                    .to_owned(),
+            ));
+        }
+        Ok(CaptureCurve {
+            full_rate_distance,
+            zero_rate_distance,
+        })
+    }
+
+    fn factor(&self, distance: f64) -> f64 {
+        let decay = (distance - self.full_rate_distance)
+            / (self.zero_rate_distance - self.full_rate_distance);
+        (1.0 - decay).clamp(0.0, 1.0)
+    }
+}
+
+impl InhabitantsMap {
+    /// Adds the expected riders to the inhabitants of every station and layer type,
+    /// each covered house counts with the capture rate of its layer type reduced by
+    /// `curve` for its distance to the station. Like `RidershipEstimate` this is a
+    /// planning heuristic and not a forecast.
+    pub fn estimate_riders(
+        &mut self,
+        coverage: &[(LayerType, CoverageMap<'_, '_>)],
+        capture_rates: &CaptureRates,
+        curve: &CaptureCurve,
+    ) {
+        for (layer_type, coverage_map) in coverage {
+            let rate = capture_rates.rate(layer_type);
+            for (station, coverage) in &coverage_map.0 {
+                let riders: f64 = coverage
+                    .houses
+                    .iter()
+                    .map(|hi| hi.centroid.pop as f64 * rate * curve.factor(hi.distance))
+                    .sum();
+                let info = self
+                    .0
+                    .get_mut(*station)
+                    .and_then(|infos| infos.iter_mut().find(|info| info.layer_type == *layer_type));
+                if let Some(info) = info {
+                    info.estimated_riders = Some(riders.round() as u32);
+                }
+            }
+        }
+    }
+}
+
+impl InhabitantsMap {
+    /// Lists the covered houses of every station and layer type with the inhabitants
+    /// each of them adds, these sum up to the value before `scale` and
+    /// `apply_capacities` changed it
+    pub fn include_buildings(&mut self, coverage: &[(LayerType, CoverageMap<'_, '_>)]) {
+        for (layer_type, coverage_map) in coverage {
+            for (station, coverage) in &coverage_map.0 {
+                let info = self
+                    .0
+                    .get_mut(*station)
+                    .and_then(|infos| infos.iter_mut().find(|info| info.layer_type == *layer_type));
+                if let Some(info) = info {
+                    info.buildings = Some(
+                        coverage
+                            .houses
+                            .iter()
+                            .zip(&coverage.contributions)
+                            .map(|(hi, inhabitants)| CoveredBuilding {
+                                location: hi.centroid.geometry,
+                                inhabitants: *inhabitants,
+                                distance: hi.distance,
+                            })
+                            .collect(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl InhabitantsMap {
+    /// Limits the inhabitants of every station with a capacity to it, summed up over
+    /// the layer types. Stations are handled in the order they are given. The excess
+    /// of a station goes to the active stations whose coverage overlaps its own,
+    /// nearest first, each taking as much as its capacity leaves room for; stations
+    /// without a capacity take all of it. Excess no neighbor has room for isn't
+    /// counted. Moved inhabitants keep the layer types they had at their station.
+    pub fn apply_capacities(&mut self, stations: &[Station]) {
+        if stations.iter().all(|station| station.capacity().is_none()) {
+            return;
+        }
+        let mut values: HashMap<&str, HashMap<LayerType, f64>> = stations
+            .iter()
+            .map(|station| {
+                let infos = self.0.get(&station.id).into_iter().flatten();
+                let by_layer_type = infos
+                    .map(|info| (info.layer_type, info.value as f64))
+                    .collect();
+                (station.id.as_str(), by_layer_type)
+            })
+            .collect();
+        let total = |values: &HashMap<&str, HashMap<LayerType, f64>>, id: &str| -> f64 {
+            values
+                .get(id)
+                .map_or(0.0, |by_layer_type| by_layer_type.values().sum())
+        };
+
+        for station in stations.iter().filter(|station| station.is_active()) {
+            let Some(capacity) = station.capacity() else {
+                continue;
+            };
+            let station_total = total(&values, &station.id);
+            if station_total <= capacity as f64 {
+                continue;
+            }
+            // the excess keeps the layer type shares of the station
+            let excess_share = (station_total - capacity as f64) / station_total;
+            let mut excess: HashMap<LayerType, f64> = HashMap::new();
+            for (layer_type, value) in values.entry(station.id.as_str()).or_default() {
+                excess.insert(*layer_type, *value * excess_share);
+                *value -= *value * excess_share;
+            }
+            let mut neighbors: Vec<&Station> = stations
+                .iter()
+                .filter(|other| other.is_active() && other.id != station.id)
+                .filter(|other| {
+                    other.haversine_distance(station) < other.coverage() + station.coverage()
+                })
+                .collect();
+            neighbors.sort_by(|a, b| {
+                a.haversine_distance(station)
+                    .total_cmp(&b.haversine_distance(station))
+            });
+            for neighbor in neighbors {
+                let excess_total: f64 = excess.values().sum();
+                if excess_total <= 0.0 {
+                    break;
+                }
+                let room = neighbor.capacity().map_or(f64::MAX, |capacity| {
+                    (capacity as f64 - total(&values, &neighbor.id)).max(0.0)
+                });
+                let moved_share = (room / excess_total).min(1.0);
+                let neighbor_values = values.entry(neighbor.id.as_str()).or_default();
+                for (layer_type, value) in excess.iter_mut() {
+                    let moved = *value * moved_share;
+                    *neighbor_values.entry(*layer_type).or_default() += moved;
+                    *value -= moved;
+                }
+            }
+        }
+
+        for (id, by_layer_type) in values {
+            let infos = self.0.entry(id.to_owned()).or_default();
+            for (layer_type, value) in by_layer_type {
+                let value = value.round() as u32;
+                match infos.iter_mut().find(|info| info.layer_type == layer_type) {
+                    Some(info) if info.value != value => {
+                        info.uncapped = Some(info.value);
+                        info.value = value;
+                    }
+                    Some(_) => {}
+                    None => infos.push(InhabitantsInfo {
+                        layer_type,
+                        value,
+                        estimated_riders: None,
+                        uncapped: Some(0),
+                        buildings: None,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Rough daily boardings per station derived from the covered inhabitants.
+/// This is a planning heuristic and not a ridership forecast.
+#[derive(Serialize)]
+pub struct RidershipEstimate {
+    estimate: bool,
+    estimated_daily_boardings: HashMap<String, u32>,
+}
+
+impl RidershipEstimate {
+    pub fn new(inhabitants: &InhabitantsMap, capture_rates: &CaptureRates) -> Self {
+        RidershipEstimate {
+            estimate: true,
+            estimated_daily_boardings: inhabitants
+                .0
+                .iter()
+                .map(|(station, infos)| {
+                    let boardings: f64 = infos
+                        .iter()
+                        .map(|info| info.value as f64 * capture_rates.rate(&info.layer_type))
+                        .sum();
+                    (station.clone(), boardings.round() as u32)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Difference between the inhabitants covered by scenario `b` and scenario `a`.
+/// Like the station info the totals add up the stations, so houses in reach of
+/// several stations count once per station unless a separation distance is given.
+#[derive(Serialize)]
+pub struct ScenarioComparison {
+    /// covered inhabitants of `b` minus those of `a`
+    by_layer_type: HashMap<LayerType, i64>,
+    /// sum of the layer types covering more inhabitants in `b`
+    gained: u32,
+    /// sum of the layer types covering fewer inhabitants in `b`
+    lost: u32,
+    /// stations in both scenarios, at the same place and covering the same
+    /// inhabitants of every layer type
+    unchanged_stations: Vec<String>,
+}
+
+impl ScenarioComparison {
+    pub fn new(
+        a: &InhabitantsMap,
+        a_stations: &[Station],
+        b: &InhabitantsMap,
+        b_stations: &[Station],
+    ) -> Self {
+        let (a_totals, b_totals) = (a.totals(), b.totals());
+        let by_layer_type: HashMap<LayerType, i64> = a_totals
+            .keys()
+            .chain(b_totals.keys())
+            .map(|layer_type| {
+                let total = |totals: &HashMap<LayerType, u32>| {
+                    totals.get(layer_type).copied().unwrap_or_default() as i64
+                };
+                (*layer_type, total(&b_totals) - total(&a_totals))
+            })
+            .collect();
+        let mut unchanged_stations: Vec<String> = a_stations
+            .iter()
+            .filter(|station| b_stations.contains(station))
+            .filter(|station| a.values(&station.id) == b.values(&station.id))
+            .map(|station| station.id.clone())
+            .collect();
+        unchanged_stations.sort();
+        unchanged_stations.dedup();
+        ScenarioComparison {
+            gained: by_layer_type
+                .values()
+                .filter(|delta| **delta > 0)
+                .sum::<i64>() as u32,
+            lost: by_layer_type
+                .values()
+                .filter(|delta| **delta < 0)
+                .sum::<i64>()
+                .unsigned_abs() as u32,
+            by_layer_type,
+            unchanged_stations,
+        }
+    }
+}
+
+impl InhabitantsMap {
+    fn totals(&self) -> HashMap<LayerType, u32> {
+        let mut totals: HashMap<LayerType, u32> = HashMap::new();
+        for info in self.0.values().flatten() {
+            *totals.entry(info.layer_type).or_default() += info.value;
+        }
+        totals
+    }
+
+    /// Covered inhabitants of `station` per layer type, sorted for comparisons
+    fn values(&self, station: &str) -> Vec<(LayerType, u32)> {
+        let mut values: Vec<(LayerType, u32)> = self
+            .0
+            .get(station)
+            .into_iter()
+            .flatten()
+            .map(|info| (info.layer_type, info.value))
+            .collect();
+        values.sort_by_key(|(layer_type, value)| (layer_type.to_string(), *value));
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(id: &str, longitude: f64, capacity: Option<u32>) -> Station {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "location": Point::new(longitude, 48.2),
+            "capacity": capacity,
+        }))
+        .unwrap()
+    }
+
+    fn inhabitants(values: &[(&str, LayerType, u32)]) -> InhabitantsMap {
+        values
+            .iter()
+            .map(|(station, layer_type, value)| (station.to_string(), *layer_type, *value))
+            .collect()
+    }
+
+    #[test]
+    fn the_excess_of_a_station_moves_to_its_nearest_neighbors_with_room() {
+        let stations = [
+            station("a", 16.37, Some(100)),
+            // about 330 m east, it has room for 20 more
+            station("b", 16.3745, Some(80)),
+            // about 450 m west
+            station("c", 16.364, None),
+            // too far away to overlap with a
+            station("d", 16.4, None),
+        ];
+        let mut inhabitants = inhabitants(&[
+            ("a", LayerType::Residential, 150),
+            ("a", LayerType::Workplace, 50),
+            ("b", LayerType::Residential, 60),
+            ("c", LayerType::Residential, 10),
+            ("d", LayerType::Residential, 30),
+        ]);
+
+        inhabitants.apply_capacities(&stations);
+        // the excess keeps the shares of its layer types
+        assert_eq!(
+            inhabitants.values("a"),
+            vec![(LayerType::Residential, 75), (LayerType::Workplace, 25)]
+        );
+        assert_eq!(
+            inhabitants.values("b"),
+            vec![(LayerType::Residential, 75), (LayerType::Workplace, 5)]
+        );
+        assert_eq!(
+            inhabitants.values("c"),
+            vec![(LayerType::Residential, 70), (LayerType::Workplace, 20)]
+        );
+        assert_eq!(inhabitants.values("d"), vec![(LayerType::Residential, 30)]);
+        let uncapped: Vec<Option<u32>> = inhabitants.0["b"]
+            .iter()
+            .map(|info| info.uncapped)
+            .collect();
+        assert_eq!(uncapped, vec![Some(60), Some(0)]);
+        assert!(inhabitants.0["d"]
+            .iter()
+            .all(|info| info.uncapped.is_none()));
+    }
+
+    #[test]
+    fn scenarios_are_compared_per_layer_type() {
+        let a_stations = [
+            station("x", 16.37, None),
+            station("y", 16.38, None),
+            station("w", 16.39, None),
+        ];
+        let a = inhabitants(&[
+            ("x", LayerType::Residential, 100),
+            ("x", LayerType::Workplace, 50),
+            ("y", LayerType::Residential, 30),
+            ("w", LayerType::Residential, 10),
+        ]);
+        // y is replaced by z and w is moved, covering the same inhabitants
+        let b_stations = [
+            station("x", 16.37, None),
+            station("z", 16.385, None),
+            station("w", 16.395, None),
+        ];
+        let b = inhabitants(&[
+            ("x", LayerType::Residential, 100),
+            ("x", LayerType::Workplace, 50),
+            ("z", LayerType::Residential, 10),
+            ("z", LayerType::Workplace, 80),
+            ("z", LayerType::Shopping, 5),
+            ("w", LayerType::Residential, 10),
+        ]);
+
+        let comparison = ScenarioComparison::new(&a, &a_stations, &b, &b_stations);
+        assert_eq!(
+            comparison.by_layer_type,
+            HashMap::from([
+                (LayerType::Residential, -20),
+                (LayerType::Workplace, 80),
+                (LayerType::Shopping, 5),
+            ])
+        );
+        assert_eq!(comparison.gained, 85);
+        assert_eq!(comparison.lost, 20);
+        assert_eq!(comparison.unchanged_stations, ["x"]);
+
+        let reversed = ScenarioComparison::new(&b, &b_stations, &a, &a_stations);
+        assert_eq!((reversed.gained, reversed.lost), (20, 85));
+    }
+}
@@ -0,0 +1,136 @@
+//! In-memory token bucket per client ip for the routes querying Overpass, so a busy
+//! client doesn't get the whole server banned there.
+
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::{Error, HttpResponse};
+
+/// Above this many tracked clients the buckets that refilled completely are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Allows `per_minute` requests per client ip and minute, with bursts of up to
+/// `per_minute` requests. Clones share their buckets, a limit of 0 allows everything.
+#[derive(Clone)]
+pub struct RateLimit {
+    per_minute: u32,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimit {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token for `client`, or returns the seconds until the next one is available
+    fn take(&self, client: &str) -> Result<(), u64> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = self.per_minute as f64;
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+        // a poisoned lock only means another request panicked, the buckets are still usable
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buckets.len() > MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < capacity
+            });
+        }
+        let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+            .min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(((1.0 - bucket.tokens) / per_second).ceil() as u64);
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service,
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limit: RateLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // behind a proxy the client is taken from the Forwarded headers, without one
+        // the peer address includes the port, which changes with every connection
+        let client = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(|address| match address.parse::<SocketAddr>() {
+                Ok(address) => address.ip().to_string(),
+                Err(_) => address.to_owned(),
+            })
+            .unwrap_or_default();
+        if let Err(retry_after) = self.limit.take(&client) {
+            log::warn!("rate limited {} on {}", client, req.path());
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .body(format!(
+                    "too many requests, retry in {} seconds",
+                    retry_after
+                ));
+            return Box::pin(ready(Ok(req.into_response(response).map_into_right_body())));
+        }
+        let response = self.service.call(req);
+        Box::pin(async move { response.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{Cursor, Read, Write};
+use std::sync::RwLock;
+
+use actix_web::{http::header, web, HttpResponse};
+use config::Config;
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::error::OLPError;
+use crate::layers::{self, Layers};
+use crate::line::{self, Lines};
+use crate::persistence;
+
+/// Bumped whenever the layout of the state archive or the serialized layers changes.
+/// Version 2 added the lines.
+const STATE_FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct StateManifest {
+    format_version: u32,
+    backend_version: String,
+    /// hash of the exported layers, see `Layers::content_hash`
+    data_hash: String,
+    layers: usize,
+    lines: usize,
+}
+
+/// Packs all calculated layers and the stored lines into an archive that
+/// `/state/import` restores on another instance. Exporting the same layers twice
+/// gives the same layers.json.
+pub async fn export_state(
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+) -> Result<HttpResponse, OLPError> {
+    let (mut manifest, layers_json) = {
+        let layers = layers.read().map_err(OLPError::from_error)?;
+        let manifest = StateManifest {
+            format_version: STATE_FORMAT_VERSION,
+            backend_version: env!("CARGO_PKG_VERSION").to_owned(),
+            data_hash: format!("{:016x}", layers.content_hash()?),
+            layers: layers.len(),
+            lines: 0,
+        };
+        (manifest, persistence::to_stable_json(&*layers)?)
+    };
+    let lines_json = {
+        let lines = lines.read().map_err(OLPError::from_error)?;
+        manifest.lines = lines.len();
+        persistence::to_stable_json(&*lines)?
+    };
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("manifest.json", FileOptions::default())
+        .map_err(OLPError::from_error)?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest).map_err(OLPError::from_error)?)
+        .map_err(OLPError::from_error)?;
+    zip.start_file("layers.json", FileOptions::default())
+        .map_err(OLPError::from_error)?;
+    zip.write_all(&layers_json).map_err(OLPError::from_error)?;
+    zip.start_file("lines.json", FileOptions::default())
+        .map_err(OLPError::from_error)?;
+    zip.write_all(&lines_json).map_err(OLPError::from_error)?;
+    let archive = zip.finish().map_err(OLPError::from_error)?.into_inner();
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(header::ContentDisposition::attachment("state.zip"))
+        .body(archive))
+}
+
+/// Replaces all layers and lines with the ones of an archive written by `/state/export`
+pub async fn import_state(
+    body: web::Bytes,
+    layers: web::Data<RwLock<Layers>>,
+    lines: web::Data<RwLock<Lines>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, OLPError> {
+    let mut archive = ZipArchive::new(Cursor::new(body))
+        .map_err(|err| OLPError::InvalidInput(format!("not a state archive: {}", err)))?;
+
+    let manifest: StateManifest =
+        serde_json::from_slice(&read_file(&mut archive, "manifest.json")?)
+            .map_err(|err| OLPError::InvalidInput(format!("invalid manifest: {}", err)))?;
+    if manifest.format_version != STATE_FORMAT_VERSION {
+        return Err(OLPError::InvalidInput(format!(
+            "state archive has format version {} (backend {}), this backend reads version {}, export it again with a current backend",
+            manifest.format_version, manifest.backend_version, STATE_FORMAT_VERSION
+        )));
+    }
+    let imported: Layers = serde_json::from_slice(&read_file(&mut archive, "layers.json")?)
+        .map_err(|err| OLPError::InvalidInput(format!("invalid layers: {}", err)))?;
+    if format!("{:016x}", imported.content_hash()?) != manifest.data_hash {
+        return Err(OLPError::InvalidInput(
+            "layers in the state archive don't match the hash of its manifest".to_owned(),
+        ));
+    }
+    let imported_lines: Lines = serde_json::from_slice(&read_file(&mut archive, "lines.json")?)
+        .map_err(|err| OLPError::InvalidInput(format!("invalid lines: {}", err)))?;
+
+    log::info!(
+        "importing {} layers and {} lines exported by backend {}",
+        imported.len(),
+        imported_lines.len(),
+        manifest.backend_version
+    );
+    {
+        let mut layers = layers.write().map_err(OLPError::from_error)?;
+        *layers = imported;
+        layers.new_generation();
+    }
+    layers::cache_layers(&layers, &config)?;
+    *lines.write().map_err(OLPError::from_error)? = imported_lines;
+    line::cache_lines(&lines, &config)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn read_file(
+    archive: &mut ZipArchive<Cursor<web::Bytes>>,
+    name: &str,
+) -> Result<Vec<u8>, OLPError> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|_| OLPError::InvalidInput(format!("state archive is missing {}", name)))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(OLPError::from_error)?;
+    Ok(data)
+}
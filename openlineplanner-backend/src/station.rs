@@ -1,24 +1,39 @@
-use std::borrow::Borrow;
-
 use actix_web::{body::BoxBody, http::header::ContentType, HttpResponse, Responder};
-use geo::{HaversineDistance, LineString, Point};
+use geo::{Contains, HaversineDistance, HaversineLength, LineString, MultiPolygon, Point};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use datatypes::Streets;
 
 use crate::{
-    coverage::StationCoverageInfo,
-    coverage::{get_houses_in_coverage, houses_for_stations, Method, Routing},
-    geometry::{DensifyHaversine, OsmDistanceCalculator},
+    coverage::{get_houses_in_coverage, houses_for_stations, within_coverage, Method, Routing},
+    coverage::{PopulatedCentroidInfo, StationCoverageInfo},
+    error::OLPError,
+    geometry::{
+        self, DensifyHaversine, DistanceCalculator, DistanceFromPoint, HaversineDistanceCalculator,
+        NetworkDistanceCalculator, OsmDistanceCalculator,
+    },
     layers::PopulatedCentroid,
+    validation,
 };
 
 static DEFAULT_COVERAGE: f64 = 300f64;
+/// stations closer than this (in meters) are considered to be the same station
+static DUPLICATE_STATION_DISTANCE: f64 = 1f64;
+/// distance (in meters) between the positions on a route tried as stations for a coverage target
+static CANDIDATE_SPACING: f64 = 25f64;
 
-#[derive(Deserialize, PartialEq)]
+/// A station as sent by the client, `location` is expected in `[lon, lat]` order
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Station {
     pub id: String,
     pub location: Point,
     coverage: Option<f64>,
+    /// inactive stations stay in the set but are ignored when calculating coverage
+    active: Option<bool>,
+    /// minutes between two departures, only used for coverage by travel time
+    headway: Option<f64>,
+    /// most inhabitants the station can serve, see `InhabitantsMap::apply_capacities`
+    capacity: Option<u32>,
 }
 
 impl Station {
@@ -29,65 +44,656 @@ impl Station {
     pub fn coverage(&self) -> f64 {
         self.coverage.unwrap_or(DEFAULT_COVERAGE)
     }
+
+    pub fn is_active(&self) -> bool {
+        self.active.unwrap_or(true)
+    }
+
+    pub fn headway(&self) -> Option<f64> {
+        self.headway
+    }
+
+    pub fn capacity(&self) -> Option<u32> {
+        self.capacity
+    }
+
+    pub fn with_coverage(&self, coverage: f64) -> Station {
+        Station {
+            coverage: Some(coverage),
+            ..self.clone()
+        }
+    }
+}
+
+/// Stations sent twice by the client
+pub struct Duplicates {
+    /// (id of the dropped station, id of the station it was merged into)
+    pub merged: Vec<(String, String)>,
+}
+
+impl Duplicates {
+    pub fn log_warnings(&self) {
+        for (duplicate, original) in &self.merged {
+            log::warn!(
+                "station {} is at the same location as station {}, merging them",
+                duplicate,
+                original
+            );
+        }
+    }
+}
+
+/// Drops stations at the same location as an earlier station, stations that are
+/// merely close to each other are kept
+pub fn deduplicate(stations: &[Station]) -> (Vec<Station>, Duplicates) {
+    let mut unique: Vec<Station> = Vec::new();
+    let mut merged = Vec::new();
+    for station in stations {
+        match unique
+            .iter()
+            .find(|other| other.haversine_distance(station) < DUPLICATE_STATION_DISTANCE)
+        {
+            Some(original) => merged.push((station.id.clone(), original.id.clone())),
+            None => unique.push(station.clone()),
+        }
+    }
+    (unique, Duplicates { merged })
+}
+
+/// A place on a route no station can be built at, like a tunnel or a bridge: everything
+/// within `radius` meters of a single point or of the line through the points
+#[derive(Deserialize)]
+pub struct Exclusion {
+    points: Vec<Point>,
+    radius: f64,
+}
+
+impl Exclusion {
+    pub fn validate(&self) -> Result<(), OLPError> {
+        if self.points.is_empty() {
+            return Err(OLPError::InvalidInput(
+                "exclusions need at least one point".to_owned(),
+            ));
+        }
+        if !self.radius.is_finite() || self.radius < 0.0 {
+            return Err(OLPError::InvalidInput(
+                "the radius of exclusions has to be a positive number of meters".to_owned(),
+            ));
+        }
+        validation::validate_points(&self.points, None)
+    }
+
+    fn contains(&self, point: &Point) -> bool {
+        if let [single] = self.points.as_slice() {
+            return single.haversine_distance(point) <= self.radius;
+        }
+        geometry::haversine_distance_to(&LineString::from(self.points.clone()), point)
+            .is_some_and(|distance| distance <= self.radius)
+    }
+}
+
+/// Parameters for the search of a new station along a route
+pub struct FindStationOptions<'a> {
+    pub coverage: f64,
+    pub method: &'a Method,
+    pub routing: &'a Routing,
+    /// stations can only be placed inside the service area, houses outside of it are still counted
+    pub service_area: Option<&'a MultiPolygon>,
+    /// positions on the route stations can't be placed at
+    pub exclusions: &'a [Exclusion],
+    /// radii the coverage of the found position is reported for, `coverage` if empty
+    pub radii: &'a [f64],
+    /// largest distance (in meters) between two positions on the route that are tried
+    pub sampling_interval: f64,
+    /// routes with more positions than this at `sampling_interval` are rejected
+    pub max_candidates: usize,
+}
+
+impl FindStationOptions<'_> {
+    fn allows(&self, point: &Point) -> bool {
+        self.service_area
+            .is_none_or(|service_area| service_area.contains(point))
+            && !self
+                .exclusions
+                .iter()
+                .any(|exclusion| exclusion.contains(point))
+    }
+
+    /// `line` densified to `sampling_interval`, so routes given by a few far apart
+    /// points are searched along their whole length
+    fn sample_route(&self, line: &[Point]) -> Result<LineString, OLPError> {
+        densify_route(line, self.sampling_interval, self.max_candidates)
+    }
+}
+
+/// `line` with a position at least every `interval` meters, routes that would get more
+/// than `max_candidates` positions are rejected
+fn densify_route(
+    line: &[Point],
+    interval: f64,
+    max_candidates: usize,
+) -> Result<LineString, OLPError> {
+    let route = LineString::from(line.to_vec());
+    // each segment keeps its first point and gets one more per interval
+    let positions = route
+        .lines()
+        .map(|segment| (segment.haversine_length() / interval).ceil().max(1.0))
+        .sum::<f64>()
+        + 1.0;
+    if positions > max_candidates as f64 {
+        return Err(OLPError::InvalidInput(format!(
+            "the route is {:.0} m long, which gives {} positions every {} m, at most {} are allowed",
+            route.haversine_length(),
+            positions,
+            interval,
+            max_candidates
+        )));
+    }
+    Ok(route.densify_haversine(interval))
 }
 
 pub fn find_optimal_station(
     line: Vec<Point>,
-    coverage: f64,
     houses: &[PopulatedCentroid],
     other_stations: &[Station],
-    method: &Method,
-    routing: &Routing,
     streets: &Streets,
-) -> OptimalStationResult {
-    let linestring = Into::<LineString>::into(line.clone()).densify_haversine(10.0);
-    let others: Vec<&Station> = other_stations.iter().map(|x| x.borrow()).collect();
-    let original_coverage: Vec<&PopulatedCentroid> =
-        houses_for_stations(other_stations, houses, method, routing, streets)
-            .0
-            .values()
-            .into_iter()
-            .flat_map(|elem| elem.houses.clone())
-            .map(|elem| elem.centroid)
-            .collect();
+    options: &FindStationOptions,
+) -> Result<OptimalStationResult, OLPError> {
+    let mut results = find_optimal_stations(line, houses, other_stations, streets, options, 1)?;
+    Ok(results.remove(0))
+}
+
+/// The `n` best positions along the route ranked by the inhabitants they cover. The
+/// positions are at least the coverage radius apart, so the results aren't all
+/// clustered around the best one. Positions covering the same inhabitants are ranked
+/// by their distance along the route from its middle, then by longitude and latitude.
+pub fn find_optimal_stations(
+    line: Vec<Point>,
+    houses: &[PopulatedCentroid],
+    other_stations: &[Station],
+    streets: &Streets,
+    options: &FindStationOptions,
+    n: usize,
+) -> Result<Vec<OptimalStationResult>, OLPError> {
+    let FindStationOptions {
+        coverage,
+        method,
+        routing,
+        ..
+    } = *options;
+    let linestring = options.sample_route(&line)?;
+    let others: Vec<&Station> = other_stations
+        .iter()
+        .filter(|station| station.is_active())
+        .collect();
+    let original_coverage: Vec<&PopulatedCentroid> = houses_for_stations(
+        other_stations,
+        houses,
+        None,
+        method,
+        routing,
+        streets,
+        &[],
+        None,
+    )
+    .0
+    .values()
+    .flat_map(|elem| elem.houses.clone())
+    .map(|elem| elem.centroid)
+    .collect();
     let leftover_houses: Vec<PopulatedCentroid> = houses
         .iter()
         .filter(|house| !original_coverage.contains(house))
         .cloned()
         .collect();
-    let location = linestring
+    // distance of every candidate from the middle of the route, for breaking ties
+    let mut along_route = 0.0;
+    let mut previous: Option<Point> = None;
+    let positions: Vec<(Point, f64)> = linestring
         .points()
-        .max_by_key(|point| {
-            StationCoverageInfo::from_houses_with_method(
-                get_houses_in_coverage(
+        .map(|point| {
+            along_route += previous.map_or(0.0, |previous| previous.haversine_distance(&point));
+            previous = Some(point);
+            (point, along_route)
+        })
+        .collect();
+    let midpoint = along_route / 2.0;
+    let mut candidates: Vec<(Point, u32, f64)> = positions
+        .into_iter()
+        .filter(|(point, _)| options.allows(point))
+        .map(|(point, position)| {
+            let inhabitants = StationCoverageInfo::from_houses_with_method(
+                routed_houses_in_coverage(
                     &point,
                     coverage,
                     &leftover_houses,
-                    OsmDistanceCalculator::new(streets),
+                    streets,
+                    routing,
                     &others,
                 ),
                 method,
+                coverage,
             )
-            .inhabitants
+            .inhabitants;
+            // in centimeters, so rounding errors of mirrored positions don't decide ties
+            let from_middle = ((position - midpoint).abs() * 100.0).round();
+            (point, inhabitants, from_middle)
         })
-        .expect("could not find ideal station");
-    // find the position of the newly found point inside the original line
+        .collect();
+    // of equally good positions the one closest to the middle of the route is ranked
+    // first, then the one furthest west and south, so the result doesn't depend on
+    // the order the candidates are evaluated in
+    candidates.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then(a.2.total_cmp(&b.2))
+            .then(a.0.x().total_cmp(&b.0.x()))
+            .then(a.0.y().total_cmp(&b.0.y()))
+    });
+    let mut locations: Vec<Point> = Vec::new();
+    for (point, _, _) in candidates {
+        if locations.len() == n {
+            break;
+        }
+        if locations
+            .iter()
+            .all(|location| location.haversine_distance(&point) >= coverage)
+        {
+            locations.push(point);
+        }
+    }
+    if locations.is_empty() {
+        return Err(OLPError::InvalidInput(
+            "no position on the route is allowed for a new station, all are outside of the service area or excluded".to_owned(),
+        ));
+    }
+
     let points = linestring.into_points();
-    let location_index = points
+    Ok(locations
+        .into_iter()
+        .map(|location| {
+            optimal_station_result(
+                location,
+                &line,
+                &points,
+                &leftover_houses,
+                &others,
+                streets,
+                options,
+            )
+        })
+        .collect())
+}
+
+fn optimal_station_result(
+    location: Point,
+    line: &[Point],
+    densified_line: &[Point],
+    leftover_houses: &[PopulatedCentroid],
+    others: &[&Station],
+    streets: &Streets,
+    options: &FindStationOptions,
+) -> OptimalStationResult {
+    // find the position of the newly found point inside the original line
+    let location_index = densified_line
         .iter()
         .position(|elem| elem == &location)
         .expect("could not find location of new point in line");
-    let index = points[0..location_index]
+    let index = densified_line[0..location_index]
         .iter()
         .filter(|elem| line.contains(elem))
         .count();
-    OptimalStationResult { location, index }
+
+    // one search up to the largest radius yields the distances for all smaller ones
+    let radii = if options.radii.is_empty() {
+        vec![options.coverage]
+    } else {
+        options.radii.to_vec()
+    };
+    let max_radius = radii.iter().copied().fold(0f64, f64::max);
+    let in_reach = routed_houses_in_coverage(
+        &location,
+        max_radius,
+        leftover_houses,
+        streets,
+        options.routing,
+        others,
+    );
+    let radius_coverage = radii
+        .into_iter()
+        .map(|radius| RadiusCoverage {
+            radius,
+            inhabitants: StationCoverageInfo::from_houses_with_method(
+                in_reach
+                    .iter()
+                    .filter(|house| within_coverage(house.distance, radius))
+                    .cloned()
+                    .collect(),
+                options.method,
+                radius,
+            )
+            .inhabitants,
+        })
+        .collect();
+
+    OptimalStationResult {
+        location,
+        index,
+        coverage: radius_coverage,
+    }
+}
+
+/// Parameters for the search of the smallest set of stations reaching a coverage target
+pub struct MinimalStationsOptions<'a> {
+    pub coverage: f64,
+    /// share of the inhabitants in reach of the route that has to be covered, between 0 and 1
+    pub target: f64,
+    /// minimal distance between two of the chosen stations in meters
+    pub min_spacing: f64,
+    pub routing: &'a Routing,
+    /// routes with more positions than this every `CANDIDATE_SPACING` meters are rejected
+    pub max_candidates: usize,
+}
+
+#[derive(Serialize)]
+pub struct MinimalStationsResult {
+    stations: Vec<Point>,
+    covered_inhabitants: u32,
+    /// inhabitants in reach of any position on the route, the target refers to these
+    reachable_inhabitants: u32,
+    achieved_coverage: f64,
+    target_reached: bool,
+}
+
+/// Greedily places stations along the route, each at the position covering the most
+/// inhabitants not yet covered, until the coverage target is reached
+pub fn find_minimal_stations(
+    line: Vec<Point>,
+    houses: &[PopulatedCentroid],
+    streets: &Streets,
+    options: &MinimalStationsOptions,
+) -> Result<MinimalStationsResult, OLPError> {
+    if !(0.0..=1.0).contains(&options.target) {
+        return Err(OLPError::InvalidInput(
+            "the coverage target has to be between 0 and 1".to_owned(),
+        ));
+    }
+    let (candidates, reach) = candidates_in_reach(
+        line,
+        houses,
+        streets,
+        options.coverage,
+        options.routing,
+        options.max_candidates,
+    )?;
+
+    let mut reachable = vec![false; houses.len()];
+    reach
+        .iter()
+        .flatten()
+        .for_each(|house| reachable[*house] = true);
+    let reachable_inhabitants: u32 = houses
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| **reachable)
+        .map(|(house, _)| house.pop)
+        .sum();
+    let goal = (reachable_inhabitants as f64 * options.target).ceil() as u32;
+
+    let picked = pick_greedily(
+        &candidates,
+        &reach,
+        houses,
+        options.min_spacing,
+        |_, covered_inhabitants| covered_inhabitants < goal,
+    );
+    let covered_inhabitants = picked.iter().map(|(_, gain)| gain).sum();
+
+    Ok(MinimalStationsResult {
+        stations: picked.into_iter().map(|(station, _)| station).collect(),
+        covered_inhabitants,
+        reachable_inhabitants,
+        achieved_coverage: if reachable_inhabitants == 0 {
+            0.0
+        } else {
+            covered_inhabitants as f64 / reachable_inhabitants as f64
+        },
+        target_reached: covered_inhabitants >= goal,
+    })
+}
+
+/// Parameters for the search of a fixed number of stations along a route
+pub struct StationSetOptions<'a> {
+    pub coverage: f64,
+    pub count: usize,
+    /// minimal distance between two of the chosen stations in meters
+    pub min_spacing: f64,
+    pub routing: &'a Routing,
+    /// routes with more positions than this every `CANDIDATE_SPACING` meters are rejected
+    pub max_candidates: usize,
+}
+
+#[derive(Serialize)]
+pub struct PlacedStation {
+    location: Point,
+    /// inhabitants covered by this station and none of the ones chosen before it
+    inhabitants: u32,
+}
+
+#[derive(Serialize)]
+pub struct StationSetResult {
+    /// in the order they were chosen, the most valuable first. Fewer than requested
+    /// if the spacing leaves no room or no further inhabitants can be covered.
+    stations: Vec<PlacedStation>,
+    covered_inhabitants: u32,
+}
+
+/// Places up to `count` stations along the route, greedily like `find_minimal_stations`,
+/// so houses shared by two stations are only counted for the first one
+pub fn find_optimal_station_set(
+    line: Vec<Point>,
+    houses: &[PopulatedCentroid],
+    streets: &Streets,
+    options: &StationSetOptions,
+) -> Result<StationSetResult, OLPError> {
+    if !options.min_spacing.is_finite() || options.min_spacing < 0.0 {
+        return Err(OLPError::InvalidInput(
+            "min_spacing has to be a positive number of meters".to_owned(),
+        ));
+    }
+    let (candidates, reach) = candidates_in_reach(
+        line,
+        houses,
+        streets,
+        options.coverage,
+        options.routing,
+        options.max_candidates,
+    )?;
+    let picked = pick_greedily(
+        &candidates,
+        &reach,
+        houses,
+        options.min_spacing,
+        |stations, _| stations < options.count,
+    );
+    Ok(StationSetResult {
+        covered_inhabitants: picked.iter().map(|(_, gain)| gain).sum(),
+        stations: picked
+            .into_iter()
+            .map(|(location, inhabitants)| PlacedStation {
+                location,
+                inhabitants,
+            })
+            .collect(),
+    })
+}
+
+/// Candidate positions every `CANDIDATE_SPACING` meters along the route and the
+/// houses in reach of each of them
+fn candidates_in_reach(
+    line: Vec<Point>,
+    houses: &[PopulatedCentroid],
+    streets: &Streets,
+    coverage: f64,
+    routing: &Routing,
+    max_candidates: usize,
+) -> Result<(Vec<Point>, Vec<Vec<usize>>), OLPError> {
+    let candidates = densify_route(&line, CANDIDATE_SPACING, max_candidates)?.into_points();
+    let reach = match routing {
+        Routing::Naive => houses_in_reach(
+            &candidates,
+            houses,
+            coverage,
+            HaversineDistanceCalculator::new(),
+        ),
+        Routing::Osm => houses_in_reach(
+            &candidates,
+            houses,
+            coverage,
+            OsmDistanceCalculator::new(streets),
+        ),
+        Routing::Network => houses_in_reach(
+            &candidates,
+            houses,
+            coverage,
+            NetworkDistanceCalculator::new(streets, coverage),
+        ),
+        Routing::Bike => houses_in_reach(
+            &candidates,
+            houses,
+            coverage,
+            NetworkDistanceCalculator::cycling(streets, coverage),
+        ),
+    };
+    Ok((candidates, reach))
+}
+
+/// Repeatedly picks the candidate adding the most inhabitants not covered yet, at least
+/// `min_spacing` away from the ones picked before, as long as `more` is given the
+/// number of picked stations and their inhabitants and returns true. Returns the
+/// picked candidates with the inhabitants each of them added.
+fn pick_greedily(
+    candidates: &[Point],
+    reach: &[Vec<usize>],
+    houses: &[PopulatedCentroid],
+    min_spacing: f64,
+    more: impl Fn(usize, u32) -> bool,
+) -> Vec<(Point, u32)> {
+    let mut covered = vec![false; houses.len()];
+    let mut covered_inhabitants = 0;
+    let mut picked: Vec<(Point, u32)> = Vec::new();
+    while more(picked.len(), covered_inhabitants) {
+        let best = candidates
+            .iter()
+            .zip(reach)
+            .filter(|(candidate, _)| {
+                picked
+                    .iter()
+                    .all(|(station, _)| station.haversine_distance(candidate) >= min_spacing)
+            })
+            .map(|(candidate, in_reach)| {
+                let gain: u32 = in_reach
+                    .iter()
+                    .filter(|house| !covered[**house])
+                    .map(|house| houses[*house].pop)
+                    .sum();
+                (candidate, in_reach, gain)
+            })
+            .max_by_key(|(_, _, gain)| *gain);
+        let Some((candidate, in_reach, gain)) = best else {
+            break;
+        };
+        if gain == 0 {
+            break;
+        }
+        in_reach.iter().for_each(|house| covered[*house] = true);
+        covered_inhabitants += gain;
+        picked.push((*candidate, gain));
+    }
+    picked
+}
+
+/// Indices of the houses in reach of each candidate
+fn houses_in_reach<D: DistanceCalculator>(
+    candidates: &[Point],
+    houses: &[PopulatedCentroid],
+    coverage: f64,
+    distance_calculator: D,
+) -> Vec<Vec<usize>> {
+    candidates
+        .iter()
+        .map(|candidate| {
+            let distance_from_candidate = distance_calculator.fix_point(candidate);
+            houses
+                .par_iter()
+                .enumerate()
+                .filter(|(_, house)| {
+                    within_coverage(distance_from_candidate.distance(house), coverage)
+                })
+                .map(|(index, _)| index)
+                .collect()
+        })
+        .collect()
+}
+
+/// `get_houses_in_coverage` with the distance calculator of `routing`, `streets` has
+/// to be the network it routes on
+fn routed_houses_in_coverage<'a>(
+    origin: &Point,
+    coverage: f64,
+    houses: &'a [PopulatedCentroid],
+    streets: &Streets,
+    routing: &Routing,
+    others: &[&Station],
+) -> Vec<PopulatedCentroidInfo<'a>> {
+    // the collision checks need the distances up to the radius of the other stations
+    let max_distance = others
+        .iter()
+        .map(|other| other.coverage())
+        .fold(coverage, f64::max);
+    match routing {
+        Routing::Naive => get_houses_in_coverage(
+            origin,
+            coverage,
+            houses,
+            HaversineDistanceCalculator::new(),
+            others,
+        ),
+        Routing::Osm => get_houses_in_coverage(
+            origin,
+            coverage,
+            houses,
+            OsmDistanceCalculator::new(streets),
+            others,
+        ),
+        Routing::Network => get_houses_in_coverage(
+            origin,
+            coverage,
+            houses,
+            NetworkDistanceCalculator::new(streets, max_distance),
+            others,
+        ),
+        Routing::Bike => get_houses_in_coverage(
+            origin,
+            coverage,
+            houses,
+            NetworkDistanceCalculator::cycling(streets, max_distance),
+            others,
+        ),
+    }
+}
+
+#[derive(Serialize)]
+pub struct RadiusCoverage {
+    radius: f64,
+    inhabitants: u32,
 }
 
 #[derive(Serialize)]
 pub struct OptimalStationResult {
     location: Point,
     index: usize,
+    /// additional inhabitants covered by the new station, per radius
+    coverage: Vec<RadiusCoverage>,
 }
 
 impl Responder for OptimalStationResult {
@@ -99,3 +705,204 @@ impl Responder for OptimalStationResult {
             .json(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::NodeId;
+
+    use super::*;
+
+    const ROUTE: [(f64, f64); 2] = [(16.370, 48.2), (16.380, 48.2)];
+
+    fn route() -> Vec<Point> {
+        ROUTE.iter().map(|(x, y)| Point::new(*x, *y)).collect()
+    }
+
+    fn options<'a>(method: &'a Method, routing: &'a Routing) -> FindStationOptions<'a> {
+        FindStationOptions {
+            coverage: 300.0,
+            method,
+            routing,
+            service_area: None,
+            exclusions: &[],
+            radii: &[],
+            sampling_interval: 25.0,
+            max_candidates: 1000,
+        }
+    }
+
+    /// A network of one street from `from` on the route to the house at `to`
+    fn street(from: Point, to: Point) -> Streets {
+        let mut streets = Streets::new();
+        streets.nodes.insert(NodeId(1), from);
+        streets.nodes.insert(NodeId(2), to);
+        streets
+            .streetgraph
+            .add_edge(NodeId(1), NodeId(2), from.haversine_distance(&to));
+        streets
+    }
+
+    #[test]
+    fn bike_routing_searches_the_bike_network() {
+        // the west house can only be walked to, the east house only cycled to
+        let west = Point::new(16.371, 48.2018);
+        let east = Point::new(16.379, 48.2018);
+        let walking = street(Point::new(16.371, 48.2), west);
+        let cycling = street(Point::new(16.379, 48.2), east);
+        let mut houses = vec![
+            PopulatedCentroid::poi(west, 100),
+            PopulatedCentroid::poi(east, 100),
+        ];
+        houses[0].street_graph_id = Some(NodeId(2));
+        houses[0].bike_graph_id = Some(NodeId(99));
+        houses[1].street_graph_id = Some(NodeId(99));
+        houses[1].bike_graph_id = Some(NodeId(2));
+
+        let method = Method::Absolute;
+        let walk = find_optimal_station(
+            route(),
+            &houses,
+            &[],
+            &walking,
+            &options(&method, &Routing::Network),
+        )
+        .unwrap();
+        let bike = find_optimal_station(
+            route(),
+            &houses,
+            &[],
+            &cycling,
+            &options(&method, &Routing::Bike),
+        )
+        .unwrap();
+        assert!(walk.location.x() < 16.375, "{:?}", walk.location);
+        assert!(bike.location.x() > 16.375, "{:?}", bike.location);
+        assert_eq!(walk.coverage[0].inhabitants, 100);
+        assert_eq!(bike.coverage[0].inhabitants, 100);
+    }
+
+    #[test]
+    fn routes_are_searched_between_their_points() {
+        // about 55 m north of the middle of the 740 m route, out of reach of its ends
+        let house = Point::new(16.375, 48.2005);
+        let houses = vec![PopulatedCentroid::poi(house, 100)];
+        let method = Method::Absolute;
+        let options = FindStationOptions {
+            coverage: 100.0,
+            ..options(&method, &Routing::Naive)
+        };
+        for point in route() {
+            assert!(point.haversine_distance(&house) > 300.0);
+        }
+
+        let found = find_optimal_station(route(), &houses, &[], &Streets::new(), &options).unwrap();
+        assert_eq!(found.coverage[0].inhabitants, 100);
+        assert!(found.location.haversine_distance(&house) <= 100.0);
+    }
+
+    #[test]
+    fn placing_several_stations_rejects_routes_with_too_many_positions() {
+        let houses = vec![PopulatedCentroid::poi(Point::new(16.375, 48.2005), 100)];
+        // the 740 m route gives 31 positions every 25 m
+        let options = |max_candidates| StationSetOptions {
+            coverage: 300.0,
+            count: 1,
+            min_spacing: 300.0,
+            routing: &Routing::Naive,
+            max_candidates,
+        };
+        let placed = find_optimal_station_set(route(), &houses, &Streets::new(), &options(31));
+        assert_eq!(placed.unwrap().covered_inhabitants, 100);
+        let rejected = find_optimal_station_set(route(), &houses, &Streets::new(), &options(30));
+        assert!(matches!(rejected, Err(OLPError::InvalidInput(_))));
+
+        let minimal = |max_candidates| MinimalStationsOptions {
+            coverage: 300.0,
+            target: 1.0,
+            min_spacing: 300.0,
+            routing: &Routing::Naive,
+            max_candidates,
+        };
+        let rejected = find_minimal_stations(route(), &houses, &Streets::new(), &minimal(30));
+        assert!(matches!(rejected, Err(OLPError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn excluded_places_are_skipped_even_if_they_cover_the_most() {
+        // about 670 m apart, no position covers both houses
+        let houses = vec![
+            PopulatedCentroid::poi(Point::new(16.3705, 48.2005), 200),
+            PopulatedCentroid::poi(Point::new(16.3795, 48.2005), 50),
+        ];
+        let method = Method::Absolute;
+        let found = |exclusions: &[Exclusion]| {
+            let options = FindStationOptions {
+                exclusions,
+                ..options(&method, &Routing::Naive)
+            };
+            find_optimal_station(route(), &houses, &[], &Streets::new(), &options)
+        };
+        let tunnel = Exclusion {
+            points: vec![Point::new(16.3705, 48.2)],
+            radius: 400.0,
+        };
+
+        let unconstrained = found(&[]).unwrap();
+        assert_eq!(unconstrained.coverage[0].inhabitants, 200);
+        assert!(tunnel.contains(&unconstrained.location));
+        let constrained = found(std::slice::from_ref(&tunnel)).unwrap();
+        assert_eq!(constrained.coverage[0].inhabitants, 50);
+        assert!(!tunnel.contains(&constrained.location));
+
+        let everywhere = Exclusion {
+            points: route(),
+            radius: 10.0,
+        };
+        assert!(matches!(
+            found(&[everywhere]),
+            Err(OLPError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn ties_between_symmetric_positions_are_broken_the_same_way() {
+        // two equal houses mirrored at the middle of the route
+        let houses = vec![
+            PopulatedCentroid::poi(Point::new(16.3705, 48.2005), 100),
+            PopulatedCentroid::poi(Point::new(16.3795, 48.2005), 100),
+        ];
+        let method = Method::Absolute;
+        let options = options(&method, &Routing::Naive);
+        let middle = Point::new(16.375, 48.2);
+        let found = |route: Vec<Point>| {
+            let found =
+                find_optimal_station(route, &houses, &[], &Streets::new(), &options).unwrap();
+            assert_eq!(found.coverage[0].inhabitants, 100);
+            found.location
+        };
+
+        let forward = found(route());
+        let backward = found(route().into_iter().rev().collect());
+        assert!(
+            forward.haversine_distance(&backward) < 1.0,
+            "{:?} {:?}",
+            forward,
+            backward
+        );
+        // equally close to the middle, the western one is taken
+        assert!(forward.x() < middle.x());
+        // of all positions covering a house the one closest to the middle wins
+        let house = houses
+            .iter()
+            .min_by(|a, b| {
+                a.haversine_distance(&forward)
+                    .total_cmp(&b.haversine_distance(&forward))
+            })
+            .unwrap();
+        assert!(forward.haversine_distance(&middle) < house.haversine_distance(&middle));
+        assert!(house.haversine_distance(&forward) > 300.0 - options.sampling_interval);
+        for _ in 0..5 {
+            assert_eq!(found(route()), forward);
+        }
+    }
+}
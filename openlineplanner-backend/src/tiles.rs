@@ -0,0 +1,190 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock};
+
+use actix_web::{web, HttpResponse};
+use config::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::coverage::{
+    coverage_layer, CoverageDefaults, OutputCost, PopulatedCentroidCoverageLayer, Routing,
+};
+use crate::error::OLPError;
+use crate::layers::Layers;
+use crate::station::Station;
+
+/// Coverage results vector tiles are served from, together with the tiles
+/// already encoded for them. Tiles without houses aren't kept.
+#[derive(Default)]
+pub struct TileCache {
+    results: HashMap<String, Arc<PopulatedCentroidCoverageLayer>>,
+    tiles: HashMap<(String, u32, u32, u32), Vec<u8>>,
+}
+
+impl TileCache {
+    fn insert_result(&mut self, key: String, result: PopulatedCentroidCoverageLayer, limit: usize) {
+        if self.results.len() >= limit && !self.results.contains_key(&key) {
+            log::info!(
+                "tile cache is full, dropping {} results",
+                self.results.len()
+            );
+            self.results.clear();
+            self.tiles.clear();
+        }
+        self.tiles.retain(|(result, _, _, _), _| *result != key);
+        self.results.insert(key, Arc::new(result));
+    }
+
+    /// Keeps a tile unless its result was replaced or dropped while it was encoded
+    fn insert_tile(&mut self, key: (String, u32, u32, u32), tile: Vec<u8>, limit: usize) {
+        if !self.results.contains_key(&key.0) {
+            return;
+        }
+        if self.tiles.len() >= limit {
+            log::info!("tile cache is full, dropping {} tiles", self.tiles.len());
+            self.tiles.clear();
+        }
+        self.tiles.insert(key, tile);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TileResultRequest {
+    stations: Vec<Station>,
+    routing: Option<Routing>,
+    output_cost: Option<OutputCost>,
+}
+
+#[derive(Serialize)]
+pub struct TileResult {
+    result: String,
+    tiles: String,
+}
+
+/// Calculates the coverage of the given stations and keeps it for `/tiles/{result}/{z}/{x}/{y}.mvt`
+pub async fn create_tile_result(
+    request: web::Json<TileResultRequest>,
+    layers: web::Data<RwLock<Layers>>,
+    config: web::Data<Config>,
+    cache: web::Data<Mutex<TileCache>>,
+) -> Result<web::Json<TileResult>, OLPError> {
+    let defaults = CoverageDefaults::from_config(&config)?;
+    let coverage = coverage_layer(
+        &request.stations,
+        request.routing.as_ref().unwrap_or(&defaults.routing),
+        request.output_cost.unwrap_or_default(),
+        &layers,
+        &config,
+    )?;
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&*request)
+        .map_err(OLPError::from_error)?
+        .hash(&mut hasher);
+    layers
+        .read()
+        .map_err(OLPError::from_error)?
+        .content_hash()?
+        .hash(&mut hasher);
+    let key = format!("{:016x}", hasher.finish());
+
+    let limit = config
+        .get_int("tiles.max_cached_results")
+        .map_err(OLPError::from_error)?;
+    cache.lock().map_err(OLPError::from_error)?.insert_result(
+        key.clone(),
+        coverage,
+        limit.max(1) as usize,
+    );
+
+    Ok(web::Json(TileResult {
+        tiles: format!("/tiles/{}/{{z}}/{{x}}/{{y}}.mvt", key),
+        result: key,
+    }))
+}
+
+/// Serves tile `z/x/y` of a coverage result, 204 No Content if none of its houses are in it
+pub async fn vector_tile(
+    path: web::Path<(String, u32, u32, u32)>,
+    config: web::Data<Config>,
+    cache: web::Data<Mutex<TileCache>>,
+) -> Result<HttpResponse, OLPError> {
+    let (result, z, x, y) = path.into_inner();
+    if z > 24 || x >= 1 << z || y >= 1 << z {
+        return Err(OLPError::InvalidInput(format!(
+            "tile {}/{}/{} does not exist",
+            z, x, y
+        )));
+    }
+    let key = (result, z, x, y);
+    let coverage = {
+        let cache = cache.lock().map_err(OLPError::from_error)?;
+        if let Some(tile) = cache.tiles.get(&key) {
+            return Ok(tile_response(tile.clone()));
+        }
+        match cache.results.get(&key.0) {
+            Some(coverage) => coverage.clone(),
+            None => {
+                return Ok(
+                    HttpResponse::NotFound().body(format!("no coverage result with id {}", key.0))
+                )
+            }
+        }
+    };
+
+    // encoded without holding the lock, so other tiles can be served meanwhile
+    let Some(tile) = coverage.to_mvt(z, x, y)? else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+    let limit = config
+        .get_int("tiles.max_cached_tiles")
+        .map_err(OLPError::from_error)?;
+    cache.lock().map_err(OLPError::from_error)?.insert_tile(
+        key,
+        tile.clone(),
+        limit.max(1) as usize,
+    );
+    Ok(tile_response(tile))
+}
+
+fn tile_response(tile: Vec<u8>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/vnd.mapbox-vector-tile")
+        .body(tile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_number_of_cached_tiles_is_capped() {
+        let mut cache = TileCache::default();
+        cache.insert_result(
+            "result".to_owned(),
+            PopulatedCentroidCoverageLayer::default(),
+            4,
+        );
+        for x in 0..5 {
+            cache.insert_tile(("result".to_owned(), 3, x, 0), vec![1], 3);
+            assert!(cache.tiles.len() <= 3);
+        }
+        assert!(cache.tiles.contains_key(&("result".to_owned(), 3, 4, 0)));
+
+        // tiles of results dropped while they were encoded aren't kept
+        cache.insert_tile(("other".to_owned(), 3, 0, 0), vec![1], 3);
+        assert!(!cache.tiles.contains_key(&("other".to_owned(), 3, 0, 0)));
+    }
+
+    #[test]
+    fn results_without_stations_are_rejected() {
+        let request = serde_json::from_value(serde_json::json!({"stations": []})).unwrap();
+        let result = actix_web::rt::System::new().block_on(create_tile_result(
+            web::Json(request),
+            web::Data::new(RwLock::new(Layers::new())),
+            web::Data::new(crate::config_defaults().build().unwrap()),
+            web::Data::new(Mutex::new(TileCache::default())),
+        ));
+        assert!(matches!(result, Err(OLPError::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,63 @@
+//! Timing of the coverage calculations, logged as `key=value` fields so slow requests
+//! can be found and compared in the logs.
+//!
+//! The timing of each layer is logged at debug level and the summary of a request at
+//! info level, so both follow `log.level`.
+
+use std::time::{Duration, Instant};
+
+/// How long the calculation for one layer took
+pub struct LayerTiming {
+    /// layer type, or `merged` for the layer all layers are merged into
+    pub layer: String,
+    pub centroids: usize,
+    pub stations: usize,
+    pub duration: Duration,
+}
+
+impl LayerTiming {
+    /// Runs `calculate` for a layer and logs how long it took
+    pub fn measure<T>(
+        operation: &str,
+        layer: String,
+        centroids: usize,
+        stations: usize,
+        calculate: impl FnOnce() -> T,
+    ) -> (T, LayerTiming) {
+        let start = Instant::now();
+        let result = calculate();
+        let timing = LayerTiming {
+            layer,
+            centroids,
+            stations,
+            duration: start.elapsed(),
+        };
+        log::debug!(
+            "timing operation={} layer={} centroids={} stations={} duration_ms={:.1}",
+            operation,
+            timing.layer,
+            timing.centroids,
+            timing.stations,
+            millis(timing.duration)
+        );
+        (result, timing)
+    }
+}
+
+/// Logs the totals of a request that started at `start`. `duration_ms` includes the
+/// work outside of the layers, e.g. merging them.
+pub fn log_summary(operation: &str, start: Instant, layers: &[LayerTiming]) {
+    log::info!(
+        "timing operation={} layers={} centroids={} stations={} layer_duration_ms={:.1} duration_ms={:.1}",
+        operation,
+        layers.len(),
+        layers.iter().map(|layer| layer.centroids).sum::<usize>(),
+        layers.iter().map(|layer| layer.stations).max().unwrap_or_default(),
+        millis(layers.iter().map(|layer| layer.duration).sum()),
+        millis(start.elapsed())
+    );
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,214 @@
+//! Sanity checks for coordinates sent by clients.
+//!
+//! All incoming points are expected in `[lon, lat]` order (`x` is the longitude,
+//! `y` the latitude), the same order GeoJSON uses. A client sending `[lat, lon]`
+//! gets results for a place far away from the intended one, so points that look
+//! swapped are rejected with a hint instead of being processed.
+
+use config::Config;
+use geo::{CoordsIter, Point, Rect};
+
+use crate::error::OLPError;
+use crate::layers::Layers;
+
+fn in_range(point: &Point) -> bool {
+    (-180.0..=180.0).contains(&point.x()) && (-90.0..=90.0).contains(&point.y())
+}
+
+fn within(bounds: &Rect, point: &Point) -> bool {
+    (bounds.min().x..=bounds.max().x).contains(&point.x())
+        && (bounds.min().y..=bounds.max().y).contains(&point.y())
+}
+
+fn swapped(point: &Point) -> Point {
+    Point::new(point.y(), point.x())
+}
+
+/// Returns the bounds incoming points have to lie in, if checking against the
+/// loaded data is enabled via `validation.within_data_bounds`
+pub fn data_bounds(config: &Config, layers: &Layers) -> Option<Rect> {
+    if !config
+        .get_bool("validation.within_data_bounds")
+        .unwrap_or(false)
+    {
+        return None;
+    }
+    layers.bounding_rect()
+}
+
+/// Rejects requests with more stations than `validation.max_stations` allows, as
+/// every station adds a full routing run
+pub fn validate_station_count(count: usize, config: &Config) -> Result<(), OLPError> {
+    let max_stations = config.get_int("validation.max_stations").unwrap_or(500);
+    if count as i64 > max_stations {
+        return Err(OLPError::UnprocessableInput(format!(
+            "request contains {} stations, at most {} are allowed per request (validation.max_stations)",
+            count, max_stations
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects empty lists the request can't be answered without, `what` names the
+/// field in the error message
+pub fn validate_not_empty(count: usize, what: &str) -> Result<(), OLPError> {
+    if count == 0 {
+        return Err(OLPError::InvalidInput(format!(
+            "{} must not be empty",
+            what
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_separation_distance(separation_distance: Option<f64>) -> Result<(), OLPError> {
+    match separation_distance {
+        Some(distance) if !distance.is_finite() || distance < 0.0 => Err(OLPError::InvalidInput(
+            "separation_distance has to be a positive number of meters".to_owned(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Rejects radii that aren't a positive number of meters, `what` names the field in
+/// the error message
+pub fn validate_radius(radius: f64, what: &str) -> Result<(), OLPError> {
+    if !radius.is_finite() || radius <= 0.0 {
+        return Err(OLPError::InvalidInput(format!(
+            "{} has to be a positive number of meters",
+            what
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_point(point: &Point, bounds: Option<&Rect>) -> Result<(), OLPError> {
+    if !point.x().is_finite() || !point.y().is_finite() {
+        return Err(OLPError::InvalidInput(format!(
+            "point ({}, {}) has to consist of finite numbers",
+            point.x(),
+            point.y()
+        )));
+    }
+    if !in_range(point) {
+        let hint = if in_range(&swapped(point)) {
+            ", coordinates look swapped (expected [lon, lat])"
+        } else {
+            ""
+        };
+        return Err(OLPError::InvalidInput(format!(
+            "point ({}, {}) is out of range{}",
+            point.x(),
+            point.y(),
+            hint
+        )));
+    }
+    if let Some(bounds) = bounds {
+        if !within(bounds, point) {
+            let hint = if within(bounds, &swapped(point)) {
+                ", coordinates look swapped (expected [lon, lat])"
+            } else {
+                ""
+            };
+            return Err(OLPError::InvalidInput(format!(
+                "point ({}, {}) is outside of the loaded data{}",
+                point.x(),
+                point.y(),
+                hint
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_points<'a>(
+    points: impl IntoIterator<Item = &'a Point>,
+    bounds: Option<&Rect>,
+) -> Result<(), OLPError> {
+    points
+        .into_iter()
+        .try_for_each(|point| validate_point(point, bounds))
+}
+
+/// Checks every coordinate of an uploaded geometry like `validate_point`
+pub fn validate_geometry<'a>(
+    geometry: &'a impl CoordsIter<'a, Scalar = f64>,
+) -> Result<(), OLPError> {
+    geometry
+        .coords_iter()
+        .try_for_each(|coord| validate_point(&Point::from(coord), None))
+}
+
+/// The box from `[west, south, east, north]` in degrees, rejecting swapped or empty ones
+pub fn validate_bbox(bbox: [f64; 4]) -> Result<Rect, OLPError> {
+    let [west, south, east, north] = bbox;
+    validate_point(&Point::new(west, south), None)?;
+    validate_point(&Point::new(east, north), None)?;
+    if west >= east || south >= north {
+        return Err(OLPError::InvalidInput(
+            "bbox has to be [west, south, east, north] with west < east and south < north"
+                .to_owned(),
+        ));
+    }
+    Ok(Rect::new((west, south), (east, north)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(point: Point, bounds: Option<&Rect>) -> String {
+        match validate_point(&point, bounds) {
+            Err(OLPError::InvalidInput(message)) => message,
+            other => panic!(
+                "expected invalid input for {:?}, got {:?}",
+                point,
+                other.err()
+            ),
+        }
+    }
+
+    #[test]
+    fn points_in_range_are_valid() {
+        assert!(validate_point(&Point::new(16.37, 48.2), None).is_ok());
+        assert!(validate_point(&Point::new(-180.0, 90.0), None).is_ok());
+    }
+
+    #[test]
+    fn swapped_coordinates_get_a_hint() {
+        // [lat, lon] of Tokyo
+        assert!(error(Point::new(35.7, 139.7), None).contains("look swapped"));
+        assert!(error(Point::new(200.0, 100.0), None).ends_with("out of range"));
+
+        let vienna = Rect::new((16.1, 48.1), (16.6, 48.4));
+        assert!(error(Point::new(48.2, 16.37), Some(&vienna)).contains("look swapped"));
+        assert!(
+            error(Point::new(13.4, 52.5), Some(&vienna)).ends_with("outside of the loaded data")
+        );
+    }
+
+    #[test]
+    fn nan_and_infinite_coordinates_are_rejected() {
+        for point in [
+            Point::new(f64::NAN, 48.2),
+            Point::new(16.37, f64::NAN),
+            Point::new(f64::INFINITY, 48.2),
+            Point::new(16.37, f64::NEG_INFINITY),
+        ] {
+            assert!(error(point, None).contains("finite numbers"));
+        }
+    }
+
+    #[test]
+    fn out_of_range_coordinates_are_rejected() {
+        for point in [
+            Point::new(180.1, 0.0),
+            Point::new(-180.1, 0.0),
+            Point::new(0.0, 90.1),
+            Point::new(0.0, -90.1),
+            Point::new(200.0, 100.0),
+        ] {
+            assert!(error(point, None).contains("out of range"), "{:?}", point);
+        }
+    }
+}
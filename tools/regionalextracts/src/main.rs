@@ -9,6 +9,8 @@ use std::{
 
 use admin_area::AdminArea;
 use clap::{Parser, Subcommand};
+use config::Config;
+use datatypes::persistence;
 use geo::{MultiPolygon, Polygon};
 use serde::{Serialize, Serializer};
 
@@ -38,8 +40,26 @@ enum Commands {
     },
     /// Remove all files with the same name as the directory above
     Deduplicate,
-    /// Preprocess all files in the current directory for use with OpenLinePlanner
-    Preprocess,
+    /// Preprocess all .pbf and .osm files in the current directory for use with OpenLinePlanner
+    Preprocess {
+        /// Only rebuild the streets or the buildings and reuse the other from cache
+        #[arg(long, value_enum, default_value_t = processing::Rebuild::All)]
+        rebuild: processing::Rebuild,
+        /// Merge all files into a single area instead of preprocessing each on its own,
+        /// e.g. for adjacent regional extracts
+        #[arg(long)]
+        merge: bool,
+        /// Serialization of the .map files, json is larger but can be inspected,
+        /// the backend reads both
+        #[arg(long, default_value_t = persistence::Format::Postcard)]
+        format: persistence::Format,
+        /// Flag passed to openhousepopulator::calculate_buildings, e.g.
+        /// `--populator-flag false`, takes precedence over `populator.flag` of the
+        /// Config.toml. Cached buildings are only recalculated with it when they are
+        /// rebuilt, which `--rebuild streets` skips
+        #[arg(long, action = clap::ArgAction::Set)]
+        populator_flag: Option<bool>,
+    },
 }
 
 fn main() {
@@ -93,10 +113,37 @@ fn main() {
                 fs::remove_file(file).expect("failed to delete file");
             }
         }
-        Commands::Preprocess => processing::process_data(&env::current_dir().unwrap()),
+        Commands::Preprocess {
+            rebuild,
+            merge,
+            format,
+            populator_flag,
+        } => {
+            let mut populator =
+                processing::PopulatorOptions::from_config(&load_config().unwrap()).unwrap();
+            if let Some(flag) = populator_flag {
+                populator.flag = flag;
+            }
+            processing::process_data(
+                &env::current_dir().unwrap(),
+                rebuild,
+                merge,
+                format,
+                populator,
+                |stem, stage| println!("{}: {}", stem, stage),
+            )
+        }
     }
 }
 
+/// The options of the preprocessing, see `processing::PopulatorOptions` for the keys
+fn load_config() -> Result<Config> {
+    Ok(Config::builder()
+        .set_default("populator.flag", true)?
+        .add_source(config::File::with_name("Config.toml").required(false))
+        .build()?)
+}
+
 fn split_for_level(pbf: &Path, admin_level: u16, target_dir: &Path) -> Result<()> {
     println!("splitting pbf {:?}", pbf.file_stem().unwrap());
     let area_id = pbf
@@ -1,13 +1,21 @@
 use std::{
     io::Write,
     process::{Command, Stdio},
+    time::Duration,
 };
 
 use anyhow::{anyhow, Result};
 use geojson::GeoJson;
 
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The queries stop on the server after 25s, the rest is for downloading large answers
+static TIMEOUT: Duration = Duration::from_secs(120);
+
 pub fn query_overpass(query: String) -> Result<GeoJson> {
-    let client = reqwest::blocking::Client::new();
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(TIMEOUT)
+        .build()?;
     let response = client
         .post("https://overpass-api.de/api/interpreter")
         .body(query)
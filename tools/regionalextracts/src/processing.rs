@@ -1,46 +1,419 @@
 use std::{
+    env, fmt,
     fs::{self, File},
+    io::Read,
     path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use config::{Config, ConfigError};
 use openhousepopulator::Buildings;
 use osmpbfreader::OsmPbfReader;
+use serde_json::Value;
 
-use datatypes::{persistence, Streets};
+use datatypes::{persistence, Streets, TravelMode, TurnRestrictions};
 
-fn load_buildings<T: std::io::Read + std::io::Seek>(pbf: &mut OsmPbfReader<T>) -> Buildings {
-    openhousepopulator::calculate_buildings(
+/// Options of the population estimate, read from the `populator` table of the
+/// `Config.toml` in the working directory:
+///
+/// - `populator.flag` (default `true`): flag argument of
+///   `openhousepopulator::calculate_buildings`, overridden by `--populator-flag`
+/// - `populator.inhabitants_per_flat` (unset by default): inhabitants assumed for
+///   each flat, replaces the population openhousepopulator estimated for a building
+///   by its number of flats times this
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PopulatorOptions {
+    pub flag: bool,
+    pub inhabitants_per_flat: Option<f64>,
+}
+
+impl PopulatorOptions {
+    pub fn from_config(config: &Config) -> Result<PopulatorOptions> {
+        let inhabitants_per_flat = match config.get_float("populator.inhabitants_per_flat") {
+            Ok(inhabitants) if inhabitants.is_finite() && inhabitants >= 0.0 => Some(inhabitants),
+            Ok(inhabitants) => bail!(
+                "populator.inhabitants_per_flat has to be a non-negative number, got {}",
+                inhabitants
+            ),
+            Err(ConfigError::NotFound(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(PopulatorOptions {
+            flag: config.get_bool("populator.flag")?,
+            inhabitants_per_flat,
+        })
+    }
+}
+
+fn load_buildings<T: std::io::Read + std::io::Seek>(
+    pbf: &mut OsmPbfReader<T>,
+    options: &PopulatorOptions,
+) -> Buildings {
+    let buildings = openhousepopulator::calculate_buildings(
         pbf,
-        true,
+        options.flag,
         &openhousepopulator::Config::builder().build(),
     )
-    .unwrap()
+    .unwrap();
+    match options.inhabitants_per_flat {
+        Some(inhabitants_per_flat) => {
+            with_inhabitants_per_flat(buildings, inhabitants_per_flat).unwrap()
+        }
+        None => buildings,
+    }
+}
+
+/// The inhabitants of a flat aren't passed through the openhousepopulator config and
+/// `Buildings` can't be changed in place, so the population is set on the
+/// serialized buildings
+fn with_inhabitants_per_flat(buildings: Buildings, inhabitants_per_flat: f64) -> Result<Buildings> {
+    let mut value = serde_json::to_value(buildings)?;
+    set_inhabitants_per_flat(&mut value, inhabitants_per_flat);
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Sets `pop` of every serialized building, i.e. every object with `flats` and `pop`,
+/// rounded to whole inhabitants
+fn set_inhabitants_per_flat(value: &mut Value, inhabitants_per_flat: f64) {
+    match value {
+        Value::Object(building)
+            if building.contains_key("flats") && building.contains_key("pop") =>
+        {
+            let flats = building["flats"].as_f64().unwrap_or_default();
+            building["pop"] = Value::from((flats * inhabitants_per_flat).round() as u64);
+        }
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| set_inhabitants_per_flat(value, inhabitants_per_flat)),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| set_inhabitants_per_flat(value, inhabitants_per_flat)),
+        _ => {}
+    }
+}
+
+/// Which parts of the preprocessed data are rebuilt from the pbf, the other part
+/// is taken from its cache artifact if there is one
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Rebuild {
+    All,
+    Streets,
+    Buildings,
+}
+
+/// Coarse stages of the preprocessing of one file, in the order they are reported
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stage {
+    Streets,
+    Buildings,
+    BikeStreets,
+    TurnRestrictions,
+    Saving,
+    Done,
 }
 
-pub fn process_data(path: &Path) {
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Streets => "building street graph",
+            Stage::Buildings => "computing buildings",
+            Stage::BikeStreets => "building bike network",
+            Stage::TurnRestrictions => "reading turn restrictions",
+            Stage::Saving => "saving cache",
+            Stage::Done => "done",
+        })
+    }
+}
+
+/// `progress` is called with the stem of the processed file whenever it enters a new stage
+pub fn process_data(
+    path: &Path,
+    rebuild: Rebuild,
+    merge: bool,
+    format: persistence::Format,
+    populator: PopulatorOptions,
+    progress: impl Fn(&str, Stage),
+) {
     let paths = fs::read_dir(path).unwrap();
-    let pbf_files: Vec<PathBuf> = paths
+    let mut osm_files: Vec<PathBuf> = paths
         .into_iter()
         .filter_map(|direntry| direntry.map(|de| de.path()).ok())
-        .filter(|path| path.extension().map(|e| e.eq_ignore_ascii_case("pbf")) == Some(true))
+        .filter(|path| has_extension(path, "pbf") || is_osm_xml(path))
         .collect();
+    osm_files.sort();
+
+    // the converted and merged pbfs are deleted when this goes out of scope
+    let mut temp_files = Vec::new();
+    let pbf_files: Vec<(String, PathBuf, PathBuf)> = osm_files
+        .into_iter()
+        .map(|osm_file| {
+            let stem = osm_file.file_stem().unwrap().to_string_lossy().into_owned();
+            let pbf_file = if has_extension(&osm_file, "pbf") {
+                osm_file.clone()
+            } else {
+                let converted = convert_to_pbf(&osm_file).unwrap();
+                temp_files.push(TempFile(converted.clone()));
+                converted
+            };
+            (stem, pbf_file, osm_file)
+        })
+        .collect();
+
+    if merge && pbf_files.len() > 1 {
+        // the merged artifacts are named after all inputs, so the cache of a single
+        // extract or of another combination is never reused for them
+        let stem = pbf_files
+            .iter()
+            .map(|(stem, _, _)| stem.as_str())
+            .collect::<Vec<_>>()
+            .join("+");
+        let pbfs: Vec<&Path> = pbf_files.iter().map(|(_, pbf, _)| pbf.as_path()).collect();
+        let sources: Vec<&Path> = pbf_files.iter().map(|(_, _, osm)| osm.as_path()).collect();
+        println!("merging files {:?}", pbfs);
+        let merged = merge_pbfs(&pbfs, &stem).unwrap();
+        temp_files.push(TempFile(merged.clone()));
+        process_file(
+            &merged,
+            &sources,
+            &stem,
+            rebuild,
+            format,
+            populator,
+            &|stage| progress(&stem, stage),
+        );
+    } else {
+        for (stem, pbf_file, osm_file) in pbf_files {
+            process_file(
+                &pbf_file,
+                &[osm_file.as_path()],
+                &stem,
+                rebuild,
+                format,
+                populator,
+                &|stage| progress(&stem, stage),
+            );
+        }
+    }
+}
 
-    for pbf_file in pbf_files {
-        println!("prepocessing file {:?}", pbf_file.file_name());
-        let mut path = PathBuf::from("./out");
-        fs::create_dir_all(&path).expect("failed to create cache dir");
-        path.push(&pbf_file.file_stem().unwrap());
-        path.set_extension("map");
+/// `sources` are the original input files, which may differ from `pbf_file` if
+/// they were converted or merged
+fn process_file(
+    pbf_file: &Path,
+    sources: &[&Path],
+    stem: &str,
+    rebuild: Rebuild,
+    format: persistence::Format,
+    populator: PopulatorOptions,
+    progress: &dyn Fn(Stage),
+) {
+    println!("prepocessing file {:?}", pbf_file.file_name());
+    let mut path = PathBuf::from("./out");
+    fs::create_dir_all(&path).expect("failed to create cache dir");
+    path.push(stem);
+    path.set_extension("map");
 
-        let mut pbf = OsmPbfReader::new(File::open(pbf_file).unwrap());
+    let mut pbf = OsmPbfReader::new(File::open(pbf_file).unwrap());
+
+    // cached artifacts are only reused if the input didn't change since they were generated
+    let up_to_date = persistence::is_up_to_date(&path, sources);
+    if !up_to_date && rebuild != Rebuild::All {
+        println!("input changed since the last run, rebuilding everything");
+    }
 
-        let streets = load_streetgraph(&mut pbf);
-        let buildings = load_buildings(&mut pbf);
+    let streets_path = path.with_extension("streets");
+    let streets = match persistence::load_streets(&streets_path) {
+        Ok(streets) if up_to_date && rebuild == Rebuild::Buildings => streets,
+        _ => {
+            progress(Stage::Streets);
+            load_streetgraph(&mut pbf)
+        }
+    };
+    let buildings_path = path.with_extension("buildings");
+    let buildings = match persistence::load_buildings(&buildings_path) {
+        Ok(buildings) if up_to_date && rebuild == Rebuild::Streets => buildings,
+        _ => {
+            progress(Stage::Buildings);
+            load_buildings(&mut pbf, &populator)
+        }
+    };
 
-        persistence::save_preprocessed_data(buildings, streets, &path).unwrap();
+    // read by the backend for bike routing, rebuilt together with the streets
+    let bike_path = path.with_extension("bike");
+    let bike_streets = match persistence::load_streets(&bike_path) {
+        Ok(bike_streets) if up_to_date && rebuild == Rebuild::Buildings => bike_streets,
+        _ => {
+            progress(Stage::BikeStreets);
+            let bike_streets = Streets::from_pbf_for_mode(&mut pbf, TravelMode::Bike);
+            persistence::save_streets(&bike_streets, &bike_path).unwrap();
+            bike_streets
+        }
+    };
+
+    // the restrictions refer to the bike network, which is the only one honoring them
+    let restrictions_path = path.with_extension("restrictions");
+    if !up_to_date || rebuild != Rebuild::Buildings || !restrictions_path.exists() {
+        progress(Stage::TurnRestrictions);
+        let restrictions = TurnRestrictions::from_pbf(&mut pbf, &bike_streets);
+        println!("found {} prohibited turns", restrictions.len());
+        persistence::save_restrictions(&restrictions, &restrictions_path).unwrap();
     }
+
+    progress(Stage::Saving);
+    persistence::save_streets(&streets, &streets_path).unwrap();
+    persistence::save_buildings(&buildings, &buildings_path).unwrap();
+    persistence::save_preprocessed_data(buildings, streets, &path, format).unwrap();
+    persistence::save_source(
+        &persistence::SourceFingerprint::of(sources).unwrap(),
+        &path.with_extension("source"),
+    )
+    .unwrap();
+    progress(Stage::Done);
 }
 
 fn load_streetgraph<T: std::io::Read + std::io::Seek>(pbf: &mut OsmPbfReader<T>) -> Streets {
     Streets::from_pbf(pbf)
 }
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().map(|e| e.eq_ignore_ascii_case(extension)) == Some(true)
+}
+
+/// .osm/.xml files (e.g. JOSM exports) are only accepted if they actually contain xml
+fn is_osm_xml(path: &Path) -> bool {
+    if !has_extension(path, "osm") && !has_extension(path, "xml") {
+        return false;
+    }
+    let mut start = [0u8; 64];
+    let Ok(read) = File::open(path).and_then(|mut file| file.read(&mut start)) else {
+        return false;
+    };
+    String::from_utf8_lossy(&start[..read])
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .starts_with('<')
+}
+
+/// osmpbfreader and openhousepopulator only read pbf, so xml input is converted with osmium first
+fn convert_to_pbf(xml: &Path) -> Result<PathBuf> {
+    let pbf = temp_pbf(&xml.file_stem().unwrap_or_default().to_string_lossy());
+    println!("converting {:?} to pbf", xml.file_name());
+    if !process::Command::new("osmium")
+        .arg("cat")
+        .arg("--overwrite")
+        .arg("-o")
+        .arg(&pbf)
+        .arg(xml)
+        .status()
+        .context("failed to run osmium, is it installed?")?
+        .success()
+    {
+        bail!("execution of osmium failed")
+    }
+    Ok(pbf)
+}
+
+/// Merges adjacent extracts into a single pbf with osmium. Nodes, ways and relations
+/// contained in more than one extract (e.g. along the shared border) are only kept once
+/// since osmium deduplicates them by their OSM id and version.
+fn merge_pbfs(pbfs: &[&Path], stem: &str) -> Result<PathBuf> {
+    let merged = temp_pbf(stem);
+    if !process::Command::new("osmium")
+        .arg("merge")
+        .arg("--overwrite")
+        .arg("-o")
+        .arg(&merged)
+        .args(pbfs)
+        .status()
+        .context("failed to run osmium, is it installed?")?
+        .success()
+    {
+        bail!("execution of osmium failed")
+    }
+    Ok(merged)
+}
+
+/// Path in the temp dir that no other run, or other input of this run, writes to
+fn temp_pbf(stem: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    env::temp_dir().join(format!(
+        "{}-{}-{}.pbf",
+        stem,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Deletes the file when dropped, also if the processing panicked
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            println!("failed to delete {:?}: {}", self.0, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn options(config: &[(&str, &str)]) -> PopulatorOptions {
+        let mut builder = Config::builder()
+            .set_default("populator.flag", true)
+            .unwrap();
+        for (key, value) in config {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        PopulatorOptions::from_config(&builder.build().unwrap()).unwrap()
+    }
+
+    fn population(options: &PopulatorOptions) -> Vec<u64> {
+        let mut buildings = json!([
+            { "flats": 4.0, "pop": 9.0 },
+            { "flats": 1.0, "pop": 2.0 },
+        ]);
+        if let Some(inhabitants_per_flat) = options.inhabitants_per_flat {
+            set_inhabitants_per_flat(&mut buildings, inhabitants_per_flat);
+        }
+        buildings
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|building| building["pop"].as_f64().unwrap() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn inhabitants_per_flat_changes_the_population() {
+        let default = options(&[]);
+        assert_eq!(
+            default,
+            PopulatorOptions {
+                flag: true,
+                inhabitants_per_flat: None
+            }
+        );
+        assert_eq!(population(&default), vec![9, 2]);
+
+        let configured = options(&[("populator.inhabitants_per_flat", "1.5")]);
+        assert_eq!(population(&configured), vec![6, 2]);
+    }
+
+    #[test]
+    fn negative_inhabitants_per_flat_are_rejected() {
+        let config = Config::builder()
+            .set_default("populator.flag", true)
+            .unwrap()
+            .set_override("populator.inhabitants_per_flat", -1.0)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(PopulatorOptions::from_config(&config).is_err());
+    }
+}